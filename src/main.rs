@@ -7,14 +7,17 @@ pub mod window;
 
 use crate::render::RenderSystem;
 use crate::render::command_buffer::RenderingRecorder;
+use crate::render::hot_reload::HotReloader;
 use crate::render::pipeline::{
-    ColorBlendingDescription, GraphicsPipeline, GraphicsPipelineDescription,
-    MultisamplingDescription, PipelineLayout, PipelineLayoutDescription,
-    PipelineRenderCompatibility, RasterizerDescription, VertexLayout, standard_blend_attachment,
-    standard_vertex_fragment_stages, standard_viewport_scissor_from_extent,
+    ColorBlendingDescription, GraphicsPipelineDescription, MultisamplingDescription,
+    PipelineLayout, PipelineLayoutDescription, PipelineRenderCompatibility, RasterizerDescription,
+    VertexLayout, standard_blend_attachment, standard_viewport_scissor_from_extent,
 };
-use crate::render::primary_renderer::PrimaryRenderer;
-use crate::render::shader::ShaderModule;
+use crate::render::pipeline_file::PipelineRegistry;
+use crate::render::primary_renderer::{DEFAULT_FRAMES_IN_FLIGHT, PrimaryRenderer};
+use crate::render::profiler::GpuProfiler;
+use crate::render::shader_cache::ShaderPermutation;
+use crate::render::validation::set_command_validation;
 use crate::window::WindowSystem;
 use ash::vk;
 use ash::vk::{
@@ -25,22 +28,30 @@ use ash::vk::{
 use glfw::{Action, Key, WindowEvent, WindowMode};
 use log::debug;
 use render::render_target::SwapchainRenderTarget;
-use shaderc::ShaderKind;
 use std::ops::Not;
 use std::rc::Rc;
 
 fn main() -> anyhow::Result<()> {
     env_logger::init();
+    set_command_validation(cfg!(debug_assertions));
+
     let mut window_system = WindowSystem::new()?;
     let render_system = RenderSystem::new(&window_system)?;
     let mut window = window_system.create_window((800, 600), "Hello!", WindowMode::Windowed)?;
     window.create_surface(&render_system)?;
 
-    let mut primary_renderer = PrimaryRenderer::new(&render_system)?;
+    let mut primary_renderer = PrimaryRenderer::new(&render_system, DEFAULT_FRAMES_IN_FLIGHT)?;
     let mut window_target = SwapchainRenderTarget::new(window, &render_system)?;
 
     primary_renderer.set_clear_color(0, Some([0.25, 0.5, 0.5, 1.0]));
 
+    let profiler = Rc::new(GpuProfiler::new(
+        &render_system,
+        primary_renderer.frames_in_flight(),
+        64,
+    )?);
+    primary_renderer.set_profiler(Some(profiler.clone()))?;
+
     window_target.set_key_polling(true);
 
     let mut this_frame = window_system.get_time();
@@ -49,39 +60,31 @@ fn main() -> anyhow::Result<()> {
 
     let mut frame_counter: usize = 0;
 
-    let vertex_shader = Rc::new(ShaderModule::load_glsl(
-        &render_system,
-        "res/main.vert",
-        ShaderKind::Vertex,
-    )?);
-
-    let fragment_shader = Rc::new(ShaderModule::load_glsl(
-        &render_system,
-        "res/main.frag",
-        ShaderKind::Fragment,
-    )?);
-
     let pipeline_layout = Rc::new(PipelineLayout::new(
         &render_system,
         &PipelineLayoutDescription {
             push_constant_ranges: vec![],
             descriptor_set_layouts: vec![],
+            debug_name: Some("main pipeline layout".to_string()),
         },
     )?);
 
     let extent = window_target.get_swapchain_extent();
+    let swapchain_format = window_target.get_swapchain_format();
 
-    let pipeline = GraphicsPipeline::new(
+    let mut hot_reloader = HotReloader::new(PipelineRegistry::new("res"))?;
+    let pipeline = hot_reloader.add_pipeline(
         &render_system,
-        &GraphicsPipelineDescription {
-            layout: pipeline_layout,
+        vec![
+            ShaderPermutation::new("main.vert", ShaderStageFlags::VERTEX),
+            ShaderPermutation::new("main.frag", ShaderStageFlags::FRAGMENT),
+        ],
+        move |shader_stages| GraphicsPipelineDescription {
+            layout: pipeline_layout.clone(),
             rendering_compatibility: PipelineRenderCompatibility::simple_from_format(
-                window_target.get_swapchain_format(),
-            ),
-            shader_stages: standard_vertex_fragment_stages(
-                vertex_shader.clone(),
-                fragment_shader.clone(),
+                swapchain_format,
             ),
+            shader_stages,
             vertex_layout: VertexLayout::default(),
             primitive_topology: PrimitiveTopology::TRIANGLE_LIST,
             allow_primitive_restart: false,
@@ -95,6 +98,7 @@ fn main() -> anyhow::Result<()> {
                 ..ColorBlendingDescription::default()
             },
             dynamic_states: vec![],
+            debug_name: Some("main pipeline".to_string()),
         },
     )?;
 
@@ -106,6 +110,9 @@ fn main() -> anyhow::Result<()> {
             _ => (),
         });
 
+        hot_reloader.update(&render_system, primary_renderer.frames_in_flight());
+
+        let pipeline = pipeline.get();
         primary_renderer.render_to_target(&mut window_target, |cmd, _frame_info| {
             cmd.bind_graphics_pipeline(&pipeline);
             cmd.draw(3, 1, 0, 0);
@@ -114,6 +121,13 @@ fn main() -> anyhow::Result<()> {
         frame_counter += 1;
         if frame_counter % 1000 == 0 {
             debug!("FPS: {:?}", 1.0 / delta_time);
+            if let Some(frame) = profiler.last_frame() {
+                debug!(
+                    "Frame CPU time: {:?}, GPU time: {:?}",
+                    frame.cpu_time(),
+                    frame.gpu_time()
+                );
+            }
         }
 
         last_frame = this_frame;
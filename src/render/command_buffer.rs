@@ -1,32 +1,209 @@
+use crate::render::RenderSystem;
+use crate::render::command_list::{
+    CommandList, RecordedBufferBarrier, RecordedClearValue, RecordedCommand, RecordedImageBarrier,
+    RecordedRenderingInfo,
+};
+use crate::render::command_pool::CommandPool;
 use crate::render::pipeline::GraphicsPipeline;
+use crate::render::profiler::GpuProfiler;
+use crate::render::query::QueryPool;
+use crate::render::render_pass::Framebuffer;
+use crate::render::resource::Buffer;
+use crate::render::resource_state::ResourceStateTracker;
+use crate::render::validation::{AttachmentFormats, CommandValidator, command_validation_enabled};
 use ash::vk;
 use ash::vk::{
-    CommandBufferBeginInfo, CommandBufferUsageFlags, DependencyInfo, ImageMemoryBarrier2,
-    PipelineBindPoint, RenderingInfo, StructureType,
+    CommandBufferBeginInfo, CommandBufferInheritanceInfo, CommandBufferInheritanceRenderingInfo,
+    CommandBufferUsageFlags, DependencyInfo, ImageMemoryBarrier2, RenderingInfo, StructureType,
 };
+use std::cell::{Ref, RefCell};
 use std::marker::PhantomData;
 use std::ops::{Deref, DerefMut};
-use log::info;
+use std::rc::Rc;
+use std::sync::Once;
+use log::{info, warn};
 
 pub struct CommandBuffer {
-    command_buffer: vk::CommandBuffer,
-    device: ash::Device,
+    backend: CommandBufferBackend,
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    conditional_rendering: Option<ash::ext::conditional_rendering::Device>,
+    profiler: Option<Rc<GpuProfiler>>,
+    validator: RefCell<Option<CommandValidator>>,
+    // the pool the command buffer is freed to on drop
+    pool: Option<Rc<CommandPool>>,
+}
+
+enum CommandBufferBackend {
+    Device {
+        device: ash::Device,
+        command_buffer: vk::CommandBuffer,
+    },
+    List(RefCell<CommandList>),
 }
 
 pub struct CommandRecorder<'a>(&'a mut CommandBuffer);
 
 pub trait GenericCommandRecorder<'a> {
     fn command_recorder(&self) -> &CommandRecorder<'a>;
+
+    /// Opens a debug label region which is closed when the returned guard is dropped.
+    /// Does nothing without VK_EXT_debug_utils.
+    fn begin_label<'s>(&'s self, name: &str, color: [f32; 4]) -> DebugLabelScope<'s, Self>
+    where
+        Self: Sized,
+        'a: 's,
+    {
+        let command_buffer: &CommandBuffer = self.command_recorder();
+        let end = command_buffer.labels_enabled().then(|| {
+            command_buffer.record(RecordedCommand::BeginLabel {
+                name: name.to_string(),
+                color,
+            });
+            command_buffer
+        });
+
+        DebugLabelScope {
+            recorder: self,
+            end,
+        }
+    }
+
+    /// Opens a profiled scope which is closed when the returned guard is dropped. Does nothing unless a
+    /// `GpuProfiler` is attached to the command buffer.
+    fn profile_scope<'s>(&'s self, name: &str) -> ProfileScope<'s, Self>
+    where
+        Self: Sized,
+        'a: 's,
+    {
+        let command_buffer: &CommandBuffer = self.command_recorder();
+        let end = command_buffer.profiler.as_deref().map(|profiler| {
+            let scope = profiler.begin_scope(command_buffer, name);
+            (profiler, command_buffer, scope)
+        });
+
+        ProfileScope {
+            recorder: self,
+            end,
+        }
+    }
+
+    /// Discards the draws and other commands recorded while the returned guard is alive if the 32-bit value
+    /// at `offset` in `buffer` is zero, or non-zero when `inverted`. The buffer needs
+    /// `BufferUsageFlags::CONDITIONAL_RENDERING_EXT` and `offset` has to be a multiple of 4.
+    ///
+    /// Without VK_EXT_conditional_rendering the commands are executed unconditionally and a warning is
+    /// logged once.
+    fn begin_conditional_rendering<'s>(
+        &'s self,
+        buffer: &Buffer,
+        offset: u64,
+        inverted: bool,
+    ) -> ConditionalRenderingScope<'s, Self>
+    where
+        Self: Sized,
+        'a: 's,
+    {
+        let command_buffer: &CommandBuffer = self.command_recorder();
+        let end = if command_buffer.conditional_rendering_enabled() {
+            command_buffer.record(RecordedCommand::BeginConditionalRendering {
+                buffer: buffer.handle(),
+                offset,
+                flags: if inverted {
+                    vk::ConditionalRenderingFlagsEXT::INVERTED
+                } else {
+                    vk::ConditionalRenderingFlagsEXT::empty()
+                },
+            });
+            Some(command_buffer)
+        } else {
+            static WARNING: Once = Once::new();
+            WARNING.call_once(|| {
+                warn!(
+                    "VK_EXT_conditional_rendering is unsupported, conditional rendering scopes are \
+                     executed unconditionally"
+                )
+            });
+            None
+        };
+
+        ConditionalRenderingScope {
+            recorder: self,
+            end,
+        }
+    }
+
+    fn insert_label(&self, name: &str, color: [f32; 4]) {
+        let cmd = self.command_recorder();
+        if cmd.labels_enabled() {
+            cmd.record(RecordedCommand::InsertLabel {
+                name: name.to_string(),
+                color,
+            });
+        }
+    }
 }
 
 impl CommandBuffer {
     pub fn wrap(device: &ash::Device, command_buffer: vk::CommandBuffer) -> Self {
         Self {
-            command_buffer,
-            device: device.clone(),
+            backend: CommandBufferBackend::Device {
+                device: device.clone(),
+                command_buffer,
+            },
+            debug_utils: None,
+            conditional_rendering: None,
+            profiler: None,
+            validator: RefCell::new(None),
+            pool: None,
+        }
+    }
+
+    /// A command buffer that records into a `CommandList` instead of a device, so recording code can run
+    /// and be inspected without a GPU. Labels are always recorded.
+    pub fn recording() -> Self {
+        Self {
+            backend: CommandBufferBackend::List(RefCell::new(CommandList::new())),
+            debug_utils: None,
+            conditional_rendering: None,
+            profiler: None,
+            validator: RefCell::new(None),
+            pool: None,
         }
     }
 
+    #[inline]
+    pub fn with_debug_utils(mut self, debug_utils: Option<ash::ext::debug_utils::Device>) -> Self {
+        self.debug_utils = debug_utils;
+        self
+    }
+
+    #[inline]
+    pub fn with_conditional_rendering(
+        mut self,
+        conditional_rendering: Option<ash::ext::conditional_rendering::Device>,
+    ) -> Self {
+        self.conditional_rendering = conditional_rendering;
+        self
+    }
+
+    #[inline]
+    pub(crate) fn with_pool(mut self, pool: Rc<CommandPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        if let CommandBufferBackend::Device { command_buffer, .. } = &self.backend {
+            render_system.set_debug_name(*command_buffer, name);
+        }
+    }
+
+    /// Scopes opened with `profile_scope` while recording this command buffer are timed by `profiler`.
+    #[inline]
+    pub fn set_profiler(&mut self, profiler: Option<Rc<GpuProfiler>>) {
+        self.profiler = profiler;
+    }
+
     #[inline]
     pub fn begin(
         &mut self,
@@ -35,8 +212,93 @@ impl CommandBuffer {
         CommandRecorder::begin(self, begin_info)
     }
 
+    /// A null handle for recording command buffers.
     pub fn handle(&self) -> vk::CommandBuffer {
-        self.command_buffer
+        match &self.backend {
+            CommandBufferBackend::Device { command_buffer, .. } => *command_buffer,
+            CommandBufferBackend::List(_) => vk::CommandBuffer::null(),
+        }
+    }
+
+    /// What has been recorded since the last `begin`. `None` unless created with `recording`.
+    pub fn command_list(&self) -> Option<Ref<'_, CommandList>> {
+        match &self.backend {
+            CommandBufferBackend::List(list) => Some(list.borrow()),
+            CommandBufferBackend::Device { .. } => None,
+        }
+    }
+
+    pub fn take_command_list(&mut self) -> Option<CommandList> {
+        match &mut self.backend {
+            CommandBufferBackend::List(list) => Some(std::mem::take(list.get_mut())),
+            CommandBufferBackend::Device { .. } => None,
+        }
+    }
+
+    /// Records a command into the command list, or straight into the Vulkan command buffer.
+    pub fn record(&self, command: RecordedCommand) {
+        match &self.backend {
+            CommandBufferBackend::Device {
+                device,
+                command_buffer,
+            } => command.replay(
+                device,
+                *command_buffer,
+                self.debug_utils.as_ref(),
+                self.conditional_rendering.as_ref(),
+            ),
+            CommandBufferBackend::List(list) => list.borrow_mut().push(command),
+        }
+    }
+
+    /// Like `record`, but executes `execute` instead of building the command when recording into a
+    /// Vulkan command buffer, which keeps the original structure's `p_next` chain intact.
+    fn record_with(
+        &self,
+        command: impl FnOnce() -> RecordedCommand,
+        execute: impl FnOnce(&ash::Device, vk::CommandBuffer),
+    ) {
+        match &self.backend {
+            CommandBufferBackend::Device {
+                device,
+                command_buffer,
+            } => execute(device, *command_buffer),
+            CommandBufferBackend::List(list) => list.borrow_mut().push(command()),
+        }
+    }
+
+    /// What command validation reported since the last `begin`. Empty unless validation is enabled with
+    /// `set_command_validation`.
+    pub fn validation_errors(&self) -> Vec<String> {
+        self.validator
+            .borrow()
+            .as_ref()
+            .map_or_else(Vec::new, |validator| validator.errors().to_vec())
+    }
+
+    fn validate(&self, f: impl FnOnce(&mut CommandValidator)) {
+        if let Some(validator) = self.validator.borrow_mut().as_mut() {
+            f(validator);
+        }
+    }
+
+    fn labels_enabled(&self) -> bool {
+        self.debug_utils.is_some() || matches!(self.backend, CommandBufferBackend::List(_))
+    }
+
+    fn conditional_rendering_enabled(&self) -> bool {
+        self.conditional_rendering.is_some()
+            || matches!(self.backend, CommandBufferBackend::List(_))
+    }
+}
+
+impl Drop for CommandBuffer {
+    fn drop(&mut self) {
+        if let (Some(pool), CommandBufferBackend::Device { command_buffer, .. }) =
+            (self.pool.as_ref(), &self.backend)
+        {
+            pool.free_handles(&[*command_buffer]);
+        }
     }
 }
 
@@ -62,24 +324,30 @@ impl<'a> CommandRecorder<'a> {
         command_buffer: &'a mut CommandBuffer,
         begin_info: Option<CommandBufferBeginInfo>,
     ) -> anyhow::Result<Self> {
-        unsafe {
-            let begin_info = begin_info.unwrap_or(CommandBufferBeginInfo::default());
+        match &mut command_buffer.backend {
+            CommandBufferBackend::Device {
+                device,
+                command_buffer,
+            } => unsafe {
+                let begin_info = begin_info.unwrap_or(CommandBufferBeginInfo::default());
 
-            command_buffer
-                .device
-                .begin_command_buffer(command_buffer.command_buffer, &begin_info)
-        }?;
+                device.begin_command_buffer(*command_buffer, &begin_info)
+            }?,
+            CommandBufferBackend::List(list) => list.get_mut().clear(),
+        }
+        *command_buffer.validator.get_mut() =
+            command_validation_enabled().then(CommandValidator::default);
 
         Ok(Self(command_buffer))
     }
 
     pub fn pipeline_barrier(&self, dependency_info: DependencyInfo) {
-        unsafe {
-            let _ = self
-                .0
-                .device
-                .cmd_pipeline_barrier2(self.0.command_buffer, &dependency_info);
-        }
+        self.record_with(
+            || RecordedCommand::pipeline_barrier(&dependency_info),
+            |device, command_buffer| unsafe {
+                device.cmd_pipeline_barrier2(command_buffer, &dependency_info)
+            },
+        );
     }
 
     pub fn image_transitions(&self, transitions: &[ImageTransition]) {
@@ -113,6 +381,96 @@ impl<'a> CommandRecorder<'a> {
         self.image_transitions(&[transition]);
     }
 
+    /// Records every barrier the tracker has accumulated as a single batch. Does nothing if there are none.
+    pub fn resource_barriers(&self, tracker: &mut ResourceStateTracker) {
+        if !tracker.has_pending_barriers() {
+            return;
+        }
+
+        let (image_barriers, buffer_barriers) = tracker.take_barriers();
+        self.record(RecordedCommand::PipelineBarrier {
+            dependency_flags: vk::DependencyFlags::empty(),
+            memory_barriers: Vec::new(),
+            image_barriers: image_barriers.iter().map(RecordedImageBarrier::from).collect(),
+            buffer_barriers: buffer_barriers.iter().map(RecordedBufferBarrier::from).collect(),
+        });
+    }
+
+    pub fn reset_query_pool(&self, query_pool: &QueryPool, first_query: u32, query_count: u32) {
+        self.record(RecordedCommand::ResetQueryPool {
+            query_pool: query_pool.handle(),
+            first_query,
+            query_count,
+        });
+    }
+
+    /// `QueryControlFlags::PRECISE` is only valid for occlusion queries. It's dropped with a warning if
+    /// the pool doesn't support it, see `QueryPool::supports_precise`.
+    pub fn begin_query(&self, query_pool: &QueryPool, query: u32, flags: vk::QueryControlFlags) {
+        let mut flags = flags;
+        if flags.contains(vk::QueryControlFlags::PRECISE) && !query_pool.supports_precise() {
+            static WARNING: Once = Once::new();
+            WARNING.call_once(|| {
+                warn!(
+                    "Precise occlusion queries are unsupported, QueryControlFlags::PRECISE is \
+                     ignored"
+                )
+            });
+            flags &= !vk::QueryControlFlags::PRECISE;
+        }
+        self.record(RecordedCommand::BeginQuery {
+            query_pool: query_pool.handle(),
+            query,
+            flags,
+        });
+    }
+
+    pub fn end_query(&self, query_pool: &QueryPool, query: u32) {
+        self.record(RecordedCommand::EndQuery {
+            query_pool: query_pool.handle(),
+            query,
+        });
+    }
+
+    /// Copies query results into `buffer`, tightly packed: each query's values (followed by its
+    /// availability with `QueryResultFlags::WITH_AVAILABILITY`), as u64 with `QueryResultFlags::TYPE_64`
+    /// and u32 otherwise. Must be recorded outside of a rendering scope.
+    pub fn copy_query_pool_results(
+        &self,
+        query_pool: &QueryPool,
+        first_query: u32,
+        query_count: u32,
+        buffer: &Buffer,
+        offset: u64,
+        flags: vk::QueryResultFlags,
+    ) {
+        let value_size = if flags.contains(vk::QueryResultFlags::TYPE_64) {
+            8
+        } else {
+            4
+        };
+        let values = query_pool.kind().values_per_query() as u64
+            + flags.contains(vk::QueryResultFlags::WITH_AVAILABILITY) as u64;
+
+        self.record(RecordedCommand::CopyQueryPoolResults {
+            query_pool: query_pool.handle(),
+            first_query,
+            query_count,
+            buffer: buffer.handle(),
+            offset,
+            stride: values * value_size,
+            flags,
+        });
+    }
+
+    pub fn write_timestamp(&self, stage: vk::PipelineStageFlags2, query_pool: &QueryPool, query: u32) {
+        self.record(RecordedCommand::WriteTimestamp {
+            stage,
+            query_pool: query_pool.handle(),
+            query,
+        });
+    }
+
     #[inline]
     pub fn begin_rendering(
         &self,
@@ -120,6 +478,46 @@ impl<'a> CommandRecorder<'a> {
     ) -> DynamicRenderingRecorder<'_, 'a> {
         DynamicRenderingRecorder::begin(self, rendering_info)
     }
+
+    /// Like `begin_rendering`, but tells command validation the attachments' formats so bound pipelines can
+    /// be checked against them.
+    #[inline]
+    pub fn begin_rendering_with_formats(
+        &self,
+        rendering_info: RenderingInfo,
+        formats: &AttachmentFormats,
+    ) -> DynamicRenderingRecorder<'_, 'a> {
+        DynamicRenderingRecorder::begin_with_formats(self, rendering_info, Some(formats))
+    }
+
+    /// `render_area` defaults to the whole framebuffer.
+    #[inline]
+    pub fn begin_render_pass(
+        &self,
+        framebuffer: &Framebuffer,
+        render_area: Option<vk::Rect2D>,
+        clear_values: &[vk::ClearValue],
+        contents: vk::SubpassContents,
+    ) -> RenderPassRecorder<'_, 'a> {
+        RenderPassRecorder::begin(self, framebuffer, render_area, clear_values, contents)
+    }
+
+    /// Inside a rendering scope this requires the scope to have been begun with
+    /// `RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS`.
+    pub fn execute_commands(&self, command_buffers: &[vk::CommandBuffer]) {
+        if command_buffers.is_empty() {
+            return;
+        }
+
+        self.record(RecordedCommand::ExecuteCommands(command_buffers.to_vec()));
+    }
+
+    /// Records every command of `command_list`, e.g. to replay a recording onto a real command buffer.
+    pub fn replay(&self, command_list: &CommandList) {
+        for command in command_list.commands() {
+            self.record(command.clone());
+        }
+    }
 }
 
 impl<'a> GenericCommandRecorder<'a> for CommandRecorder<'a> {
@@ -130,8 +528,14 @@ impl<'a> GenericCommandRecorder<'a> for CommandRecorder<'a> {
 
 impl Drop for CommandRecorder<'_> {
     fn drop(&mut self) {
-        unsafe {
-            let _ = self.device.end_command_buffer(self.command_buffer);
+        if let CommandBufferBackend::Device {
+            device,
+            command_buffer,
+        } = &self.0.backend
+        {
+            unsafe {
+                let _ = device.end_command_buffer(*command_buffer);
+            }
         }
     }
 }
@@ -146,27 +550,20 @@ impl Deref for CommandRecorder<'_> {
 
 pub trait RenderingRecorder<'a>: GenericCommandRecorder<'a> {
     fn bind_graphics_pipeline(&self, pipeline: &GraphicsPipeline) {
-        unsafe {
-            let cmd = self.command_recorder();
-            cmd.device.cmd_bind_pipeline(
-                cmd.command_buffer,
-                PipelineBindPoint::GRAPHICS,
-                pipeline.handle(),
-            );
-        }
+        let cmd = self.command_recorder();
+        cmd.validate(|validator| validator.bind_pipeline(pipeline));
+        cmd.record(RecordedCommand::BindGraphicsPipeline(pipeline.handle()));
     }
 
     fn draw(&self, vertex_count: u32, instance_count: u32, first_vertex: u32, first_instance: u32) {
-        unsafe {
-            let cmd = self.command_recorder();
-            cmd.device.cmd_draw(
-                cmd.command_buffer,
-                vertex_count,
-                instance_count,
-                first_vertex,
-                first_instance,
-            );
-        }
+        let cmd = self.command_recorder();
+        cmd.validate(CommandValidator::draw);
+        cmd.record(RecordedCommand::Draw {
+            vertex_count,
+            instance_count,
+            first_vertex,
+            first_instance,
+        });
     }
 }
 
@@ -185,19 +582,37 @@ where
 }
 
 impl<'a, 'b> DynamicRenderingRecorder<'a, 'b> {
+    #[inline]
     pub fn begin(command_recorder: &'a CommandRecorder<'b>, rendering_info: RenderingInfo) -> Self {
-        let _ = unsafe {
-            command_recorder
-                .device
-                .cmd_begin_rendering(command_recorder.command_buffer, &rendering_info)
-        };
+        Self::begin_with_formats(command_recorder, rendering_info, None)
+    }
+
+    pub fn begin_with_formats(
+        command_recorder: &'a CommandRecorder<'b>,
+        rendering_info: RenderingInfo,
+        formats: Option<&AttachmentFormats>,
+    ) -> Self {
+        command_recorder.validate(|validator| {
+            validator.begin_rendering(rendering_info.view_mask, formats.cloned())
+        });
+        command_recorder.record_with(
+            || {
+                RecordedCommand::BeginRendering(RecordedRenderingInfo::from_rendering_info(
+                    &rendering_info,
+                ))
+            },
+            |device, command_buffer| unsafe {
+                device.cmd_begin_rendering(command_buffer, &rendering_info)
+            },
+        );
         Self(command_recorder)
     }
 }
 
 impl Drop for DynamicRenderingRecorder<'_, '_> {
     fn drop(&mut self) {
-        unsafe { self.device.cmd_end_rendering(self.command_buffer) };
+        self.validate(CommandValidator::end_rendering);
+        self.record(RecordedCommand::EndRendering);
     }
 }
 
@@ -208,3 +623,210 @@ impl<'a> GenericCommandRecorder<'a> for DynamicRenderingRecorder<'_, 'a> {
 }
 
 impl<'a> RenderingRecorder<'a> for DynamicRenderingRecorder<'_, 'a> {}
+
+// Render Pass Recorder
+pub struct RenderPassRecorder<'a, 'b>(&'a CommandRecorder<'b>);
+
+impl<'a, 'b> Deref for RenderPassRecorder<'a, 'b>
+where
+    'b: 'a,
+{
+    type Target = CommandRecorder<'b>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0
+    }
+}
+
+impl<'a, 'b> RenderPassRecorder<'a, 'b> {
+    pub fn begin(
+        command_recorder: &'a CommandRecorder<'b>,
+        framebuffer: &Framebuffer,
+        render_area: Option<vk::Rect2D>,
+        clear_values: &[vk::ClearValue],
+        contents: vk::SubpassContents,
+    ) -> Self {
+        command_recorder.validate(|validator| validator.begin_render_pass(framebuffer.render_pass()));
+        command_recorder.record(RecordedCommand::BeginRenderPass {
+            render_pass: framebuffer.render_pass().handle(),
+            framebuffer: framebuffer.handle(),
+            render_area: render_area.unwrap_or(vk::Rect2D::from(framebuffer.extent())),
+            clear_values: clear_values.iter().copied().map(RecordedClearValue).collect(),
+            contents,
+        });
+        Self(command_recorder)
+    }
+
+    pub fn next_subpass(&self, contents: vk::SubpassContents) {
+        self.validate(CommandValidator::next_subpass);
+        self.record(RecordedCommand::NextSubpass(contents));
+    }
+}
+
+impl Drop for RenderPassRecorder<'_, '_> {
+    fn drop(&mut self) {
+        self.validate(CommandValidator::end_render_pass);
+        self.record(RecordedCommand::EndRenderPass);
+    }
+}
+
+impl<'a> GenericCommandRecorder<'a> for RenderPassRecorder<'_, 'a> {
+    fn command_recorder(&self) -> &CommandRecorder<'a> {
+        &self.0
+    }
+}
+
+impl<'a> RenderingRecorder<'a> for RenderPassRecorder<'_, 'a> {}
+
+// Secondary Rendering Recorder
+/// Records a secondary command buffer that continues a dynamic rendering scope begun in a primary.
+pub struct SecondaryRenderingRecorder<'a>(CommandRecorder<'a>);
+
+impl<'a> SecondaryRenderingRecorder<'a> {
+    pub fn begin(
+        command_buffer: &'a mut CommandBuffer,
+        inheritance_rendering_info: &mut CommandBufferInheritanceRenderingInfo,
+    ) -> anyhow::Result<Self> {
+        let view_mask = inheritance_rendering_info.view_mask;
+        let formats = AttachmentFormats {
+            color: match inheritance_rendering_info.color_attachment_count {
+                0 => Vec::new(),
+                count => unsafe {
+                    std::slice::from_raw_parts(
+                        inheritance_rendering_info.p_color_attachment_formats,
+                        count as usize,
+                    )
+                }
+                .to_vec(),
+            },
+            depth: inheritance_rendering_info.depth_attachment_format,
+            stencil: inheritance_rendering_info.stencil_attachment_format,
+        };
+        let inheritance_info =
+            CommandBufferInheritanceInfo::default().push_next(inheritance_rendering_info);
+
+        let cmd = CommandRecorder::begin(
+            command_buffer,
+            Some(
+                CommandBufferBeginInfo::default()
+                    .flags(
+                        CommandBufferUsageFlags::ONE_TIME_SUBMIT
+                            | CommandBufferUsageFlags::RENDER_PASS_CONTINUE,
+                    )
+                    .inheritance_info(&inheritance_info),
+            ),
+        )?;
+        // the rendering scope itself is begun by the primary this will be executed in
+        cmd.validate(|validator| validator.begin_rendering(view_mask, Some(formats)));
+
+        Ok(Self(cmd))
+    }
+}
+
+impl<'a> Deref for SecondaryRenderingRecorder<'a> {
+    type Target = CommandRecorder<'a>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<'a> GenericCommandRecorder<'a> for SecondaryRenderingRecorder<'a> {
+    fn command_recorder(&self) -> &CommandRecorder<'a> {
+        &self.0
+    }
+}
+
+impl<'a> RenderingRecorder<'a> for SecondaryRenderingRecorder<'a> {}
+
+// Debug Label Scope
+pub struct DebugLabelScope<'a, R> {
+    recorder: &'a R,
+    end: Option<&'a CommandBuffer>,
+}
+
+impl<R> Deref for DebugLabelScope<'_, R> {
+    type Target = R;
+
+    fn deref(&self) -> &Self::Target {
+        self.recorder
+    }
+}
+
+impl<'a, 'b, R: GenericCommandRecorder<'b>> GenericCommandRecorder<'b> for DebugLabelScope<'a, R> {
+    fn command_recorder(&self) -> &CommandRecorder<'b> {
+        self.recorder.command_recorder()
+    }
+}
+
+impl<'a, 'b, R: RenderingRecorder<'b>> RenderingRecorder<'b> for DebugLabelScope<'a, R> {}
+
+impl<R> Drop for DebugLabelScope<'_, R> {
+    fn drop(&mut self) {
+        if let Some(command_buffer) = self.end {
+            command_buffer.record(RecordedCommand::EndLabel);
+        }
+    }
+}
+
+// Conditional Rendering Scope
+pub struct ConditionalRenderingScope<'a, R> {
+    recorder: &'a R,
+    end: Option<&'a CommandBuffer>,
+}
+
+impl<R> Deref for ConditionalRenderingScope<'_, R> {
+    type Target = R;
+
+    fn deref(&self) -> &Self::Target {
+        self.recorder
+    }
+}
+
+impl<'a, 'b, R: GenericCommandRecorder<'b>> GenericCommandRecorder<'b>
+    for ConditionalRenderingScope<'a, R>
+{
+    fn command_recorder(&self) -> &CommandRecorder<'b> {
+        self.recorder.command_recorder()
+    }
+}
+
+impl<'a, 'b, R: RenderingRecorder<'b>> RenderingRecorder<'b> for ConditionalRenderingScope<'a, R> {}
+
+impl<R> Drop for ConditionalRenderingScope<'_, R> {
+    fn drop(&mut self) {
+        if let Some(command_buffer) = self.end {
+            command_buffer.record(RecordedCommand::EndConditionalRendering);
+        }
+    }
+}
+
+// Profile Scope
+pub struct ProfileScope<'a, R> {
+    recorder: &'a R,
+    end: Option<(&'a GpuProfiler, &'a CommandBuffer, usize)>,
+}
+
+impl<R> Deref for ProfileScope<'_, R> {
+    type Target = R;
+
+    fn deref(&self) -> &Self::Target {
+        self.recorder
+    }
+}
+
+impl<'a, 'b, R: GenericCommandRecorder<'b>> GenericCommandRecorder<'b> for ProfileScope<'a, R> {
+    fn command_recorder(&self) -> &CommandRecorder<'b> {
+        self.recorder.command_recorder()
+    }
+}
+
+impl<'a, 'b, R: RenderingRecorder<'b>> RenderingRecorder<'b> for ProfileScope<'a, R> {}
+
+impl<R> Drop for ProfileScope<'_, R> {
+    fn drop(&mut self) {
+        if let Some((profiler, command_buffer, scope)) = self.end {
+            profiler.end_scope(command_buffer, scope);
+        }
+    }
+}
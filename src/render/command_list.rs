@@ -0,0 +1,904 @@
+use ash::vk;
+use ash::vk::Handle;
+use ash::vk::{
+    BufferMemoryBarrier2, DebugUtilsLabelEXT, DependencyInfo, ImageMemoryBarrier2, MemoryBarrier2,
+    PipelineBindPoint, RenderingAttachmentInfo, RenderingInfo,
+};
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::fmt;
+
+/// A recorded rendering scope. `p_next` chains of the original structures are not kept.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRenderingInfo {
+    #[serde(with = "raw")]
+    pub flags: vk::RenderingFlags,
+    #[serde(with = "raw")]
+    pub render_area: vk::Rect2D,
+    pub layer_count: u32,
+    pub view_mask: u32,
+    pub color_attachments: Vec<RecordedAttachment>,
+    pub depth_attachment: Option<RecordedAttachment>,
+    pub stencil_attachment: Option<RecordedAttachment>,
+}
+
+/// A `RenderingAttachmentInfo` without its `p_next` chain.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedAttachment {
+    #[serde(with = "raw")]
+    pub image_view: vk::ImageView,
+    #[serde(with = "raw")]
+    pub image_layout: vk::ImageLayout,
+    #[serde(with = "raw")]
+    pub resolve_mode: vk::ResolveModeFlags,
+    #[serde(with = "raw")]
+    pub resolve_image_view: vk::ImageView,
+    #[serde(with = "raw")]
+    pub resolve_image_layout: vk::ImageLayout,
+    #[serde(with = "raw")]
+    pub load_op: vk::AttachmentLoadOp,
+    #[serde(with = "raw")]
+    pub store_op: vk::AttachmentStoreOp,
+    pub clear_value: RecordedClearValue,
+}
+
+/// A clear value as recorded. `ClearValue` is a union, so this prints both of its interpretations and
+/// compares and serializes its raw bits.
+#[derive(Copy, Clone, Serialize, Deserialize)]
+pub struct RecordedClearValue(#[serde(with = "raw")] pub vk::ClearValue);
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedMemoryBarrier {
+    #[serde(with = "raw")]
+    pub src_stage_mask: vk::PipelineStageFlags2,
+    #[serde(with = "raw")]
+    pub src_access_mask: vk::AccessFlags2,
+    #[serde(with = "raw")]
+    pub dst_stage_mask: vk::PipelineStageFlags2,
+    #[serde(with = "raw")]
+    pub dst_access_mask: vk::AccessFlags2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedImageBarrier {
+    #[serde(with = "raw")]
+    pub src_stage_mask: vk::PipelineStageFlags2,
+    #[serde(with = "raw")]
+    pub src_access_mask: vk::AccessFlags2,
+    #[serde(with = "raw")]
+    pub dst_stage_mask: vk::PipelineStageFlags2,
+    #[serde(with = "raw")]
+    pub dst_access_mask: vk::AccessFlags2,
+    #[serde(with = "raw")]
+    pub old_layout: vk::ImageLayout,
+    #[serde(with = "raw")]
+    pub new_layout: vk::ImageLayout,
+    pub src_queue_family_index: u32,
+    pub dst_queue_family_index: u32,
+    #[serde(with = "raw")]
+    pub image: vk::Image,
+    pub subresource_range: RecordedSubresourceRange,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedSubresourceRange {
+    #[serde(with = "raw")]
+    pub aspect_mask: vk::ImageAspectFlags,
+    pub base_mip_level: u32,
+    pub level_count: u32,
+    pub base_array_layer: u32,
+    pub layer_count: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedBufferBarrier {
+    #[serde(with = "raw")]
+    pub src_stage_mask: vk::PipelineStageFlags2,
+    #[serde(with = "raw")]
+    pub src_access_mask: vk::AccessFlags2,
+    #[serde(with = "raw")]
+    pub dst_stage_mask: vk::PipelineStageFlags2,
+    #[serde(with = "raw")]
+    pub dst_access_mask: vk::AccessFlags2,
+    pub src_queue_family_index: u32,
+    pub dst_queue_family_index: u32,
+    #[serde(with = "raw")]
+    pub buffer: vk::Buffer,
+    pub offset: u64,
+    pub size: u64,
+}
+
+/// A single command as recorded by a `CommandRecorder`. Barriers and attachments are stored without
+/// their `p_next` chains, and handles, flags and enums are serialized as their raw values.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum RecordedCommand {
+    PipelineBarrier {
+        #[serde(with = "raw")]
+        dependency_flags: vk::DependencyFlags,
+        memory_barriers: Vec<RecordedMemoryBarrier>,
+        image_barriers: Vec<RecordedImageBarrier>,
+        buffer_barriers: Vec<RecordedBufferBarrier>,
+    },
+    BeginRendering(RecordedRenderingInfo),
+    EndRendering,
+    BeginRenderPass {
+        #[serde(with = "raw")]
+        render_pass: vk::RenderPass,
+        #[serde(with = "raw")]
+        framebuffer: vk::Framebuffer,
+        #[serde(with = "raw")]
+        render_area: vk::Rect2D,
+        clear_values: Vec<RecordedClearValue>,
+        #[serde(with = "raw")]
+        contents: vk::SubpassContents,
+    },
+    NextSubpass(#[serde(with = "raw")] vk::SubpassContents),
+    EndRenderPass,
+    BindGraphicsPipeline(#[serde(with = "raw")] vk::Pipeline),
+    Draw {
+        vertex_count: u32,
+        instance_count: u32,
+        first_vertex: u32,
+        first_instance: u32,
+    },
+    ExecuteCommands(#[serde(with = "raw_vec")] Vec<vk::CommandBuffer>),
+    BeginLabel {
+        name: String,
+        color: [f32; 4],
+    },
+    EndLabel,
+    InsertLabel {
+        name: String,
+        color: [f32; 4],
+    },
+    ResetQueryPool {
+        #[serde(with = "raw")]
+        query_pool: vk::QueryPool,
+        first_query: u32,
+        query_count: u32,
+    },
+    BeginQuery {
+        #[serde(with = "raw")]
+        query_pool: vk::QueryPool,
+        query: u32,
+        #[serde(with = "raw")]
+        flags: vk::QueryControlFlags,
+    },
+    EndQuery {
+        #[serde(with = "raw")]
+        query_pool: vk::QueryPool,
+        query: u32,
+    },
+    CopyQueryPoolResults {
+        #[serde(with = "raw")]
+        query_pool: vk::QueryPool,
+        first_query: u32,
+        query_count: u32,
+        #[serde(with = "raw")]
+        buffer: vk::Buffer,
+        offset: u64,
+        stride: u64,
+        #[serde(with = "raw")]
+        flags: vk::QueryResultFlags,
+    },
+    WriteTimestamp {
+        #[serde(with = "raw")]
+        stage: vk::PipelineStageFlags2,
+        #[serde(with = "raw")]
+        query_pool: vk::QueryPool,
+        query: u32,
+    },
+    BeginConditionalRendering {
+        #[serde(with = "raw")]
+        buffer: vk::Buffer,
+        offset: u64,
+        #[serde(with = "raw")]
+        flags: vk::ConditionalRenderingFlagsEXT,
+    },
+    EndConditionalRendering,
+}
+
+/// A CPU-side command buffer. Record into it with `CommandBuffer::recording`, inspect it in tests, dump
+/// it with `Display` or serde for bug reports, and replay it onto a real command buffer with
+/// `CommandRecorder::replay`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CommandList {
+    commands: Vec<RecordedCommand>,
+}
+
+impl RecordedRenderingInfo {
+    pub fn from_rendering_info(rendering_info: &RenderingInfo) -> Self {
+        let attachments = |pointer: *const RenderingAttachmentInfo, count: u32| {
+            slice(pointer, count)
+                .iter()
+                .map(RecordedAttachment::from)
+                .collect::<Vec<_>>()
+        };
+
+        Self {
+            flags: rendering_info.flags,
+            render_area: rendering_info.render_area,
+            layer_count: rendering_info.layer_count,
+            view_mask: rendering_info.view_mask,
+            color_attachments: attachments(
+                rendering_info.p_color_attachments,
+                rendering_info.color_attachment_count,
+            ),
+            depth_attachment: attachments(rendering_info.p_depth_attachment, 1).pop(),
+            stencil_attachment: attachments(rendering_info.p_stencil_attachment, 1).pop(),
+        }
+    }
+}
+
+impl From<&RenderingAttachmentInfo<'_>> for RecordedAttachment {
+    fn from(attachment: &RenderingAttachmentInfo) -> Self {
+        Self {
+            image_view: attachment.image_view,
+            image_layout: attachment.image_layout,
+            resolve_mode: attachment.resolve_mode,
+            resolve_image_view: attachment.resolve_image_view,
+            resolve_image_layout: attachment.resolve_image_layout,
+            load_op: attachment.load_op,
+            store_op: attachment.store_op,
+            clear_value: RecordedClearValue(attachment.clear_value),
+        }
+    }
+}
+
+impl RecordedAttachment {
+    pub fn to_vk(&self) -> RenderingAttachmentInfo<'static> {
+        RenderingAttachmentInfo::default()
+            .image_view(self.image_view)
+            .image_layout(self.image_layout)
+            .resolve_mode(self.resolve_mode)
+            .resolve_image_view(self.resolve_image_view)
+            .resolve_image_layout(self.resolve_image_layout)
+            .load_op(self.load_op)
+            .store_op(self.store_op)
+            .clear_value(self.clear_value.0)
+    }
+}
+
+impl From<&MemoryBarrier2<'_>> for RecordedMemoryBarrier {
+    fn from(b: &MemoryBarrier2) -> Self {
+        Self {
+            src_stage_mask: b.src_stage_mask,
+            src_access_mask: b.src_access_mask,
+            dst_stage_mask: b.dst_stage_mask,
+            dst_access_mask: b.dst_access_mask,
+        }
+    }
+}
+
+impl RecordedMemoryBarrier {
+    pub fn to_vk(&self) -> MemoryBarrier2<'static> {
+        MemoryBarrier2::default()
+            .src_stage_mask(self.src_stage_mask)
+            .src_access_mask(self.src_access_mask)
+            .dst_stage_mask(self.dst_stage_mask)
+            .dst_access_mask(self.dst_access_mask)
+    }
+}
+
+impl From<&ImageMemoryBarrier2<'_>> for RecordedImageBarrier {
+    fn from(b: &ImageMemoryBarrier2) -> Self {
+        Self {
+            src_stage_mask: b.src_stage_mask,
+            src_access_mask: b.src_access_mask,
+            dst_stage_mask: b.dst_stage_mask,
+            dst_access_mask: b.dst_access_mask,
+            old_layout: b.old_layout,
+            new_layout: b.new_layout,
+            src_queue_family_index: b.src_queue_family_index,
+            dst_queue_family_index: b.dst_queue_family_index,
+            image: b.image,
+            subresource_range: b.subresource_range.into(),
+        }
+    }
+}
+
+impl RecordedImageBarrier {
+    pub fn to_vk(&self) -> ImageMemoryBarrier2<'static> {
+        ImageMemoryBarrier2::default()
+            .src_stage_mask(self.src_stage_mask)
+            .src_access_mask(self.src_access_mask)
+            .dst_stage_mask(self.dst_stage_mask)
+            .dst_access_mask(self.dst_access_mask)
+            .old_layout(self.old_layout)
+            .new_layout(self.new_layout)
+            .src_queue_family_index(self.src_queue_family_index)
+            .dst_queue_family_index(self.dst_queue_family_index)
+            .image(self.image)
+            .subresource_range(self.subresource_range.into())
+    }
+}
+
+impl From<vk::ImageSubresourceRange> for RecordedSubresourceRange {
+    fn from(range: vk::ImageSubresourceRange) -> Self {
+        Self {
+            aspect_mask: range.aspect_mask,
+            base_mip_level: range.base_mip_level,
+            level_count: range.level_count,
+            base_array_layer: range.base_array_layer,
+            layer_count: range.layer_count,
+        }
+    }
+}
+
+impl From<RecordedSubresourceRange> for vk::ImageSubresourceRange {
+    fn from(range: RecordedSubresourceRange) -> Self {
+        Self {
+            aspect_mask: range.aspect_mask,
+            base_mip_level: range.base_mip_level,
+            level_count: range.level_count,
+            base_array_layer: range.base_array_layer,
+            layer_count: range.layer_count,
+        }
+    }
+}
+
+impl From<&BufferMemoryBarrier2<'_>> for RecordedBufferBarrier {
+    fn from(b: &BufferMemoryBarrier2) -> Self {
+        Self {
+            src_stage_mask: b.src_stage_mask,
+            src_access_mask: b.src_access_mask,
+            dst_stage_mask: b.dst_stage_mask,
+            dst_access_mask: b.dst_access_mask,
+            src_queue_family_index: b.src_queue_family_index,
+            dst_queue_family_index: b.dst_queue_family_index,
+            buffer: b.buffer,
+            offset: b.offset,
+            size: b.size,
+        }
+    }
+}
+
+impl RecordedBufferBarrier {
+    pub fn to_vk(&self) -> BufferMemoryBarrier2<'static> {
+        BufferMemoryBarrier2::default()
+            .src_stage_mask(self.src_stage_mask)
+            .src_access_mask(self.src_access_mask)
+            .dst_stage_mask(self.dst_stage_mask)
+            .dst_access_mask(self.dst_access_mask)
+            .src_queue_family_index(self.src_queue_family_index)
+            .dst_queue_family_index(self.dst_queue_family_index)
+            .buffer(self.buffer)
+            .offset(self.offset)
+            .size(self.size)
+    }
+}
+
+impl RecordedCommand {
+    pub fn pipeline_barrier(dependency_info: &DependencyInfo) -> Self {
+        RecordedCommand::PipelineBarrier {
+            dependency_flags: dependency_info.dependency_flags,
+            memory_barriers: slice(
+                dependency_info.p_memory_barriers,
+                dependency_info.memory_barrier_count,
+            )
+            .iter()
+            .map(RecordedMemoryBarrier::from)
+            .collect(),
+            image_barriers: slice(
+                dependency_info.p_image_memory_barriers,
+                dependency_info.image_memory_barrier_count,
+            )
+            .iter()
+            .map(RecordedImageBarrier::from)
+            .collect(),
+            buffer_barriers: slice(
+                dependency_info.p_buffer_memory_barriers,
+                dependency_info.buffer_memory_barrier_count,
+            )
+            .iter()
+            .map(RecordedBufferBarrier::from)
+            .collect(),
+        }
+    }
+
+    /// Records the command into a Vulkan command buffer. Labels are skipped without VK_EXT_debug_utils and
+    /// conditional rendering scopes without VK_EXT_conditional_rendering.
+    pub(crate) fn replay(
+        &self,
+        device: &ash::Device,
+        command_buffer: vk::CommandBuffer,
+        debug_utils: Option<&ash::ext::debug_utils::Device>,
+        conditional_rendering: Option<&ash::ext::conditional_rendering::Device>,
+    ) {
+        unsafe {
+            match self {
+                RecordedCommand::PipelineBarrier {
+                    dependency_flags,
+                    memory_barriers,
+                    image_barriers,
+                    buffer_barriers,
+                } => {
+                    let memory_barriers = memory_barriers
+                        .iter()
+                        .map(RecordedMemoryBarrier::to_vk)
+                        .collect::<Vec<_>>();
+                    let image_barriers = image_barriers
+                        .iter()
+                        .map(RecordedImageBarrier::to_vk)
+                        .collect::<Vec<_>>();
+                    let buffer_barriers = buffer_barriers
+                        .iter()
+                        .map(RecordedBufferBarrier::to_vk)
+                        .collect::<Vec<_>>();
+                    device.cmd_pipeline_barrier2(
+                        command_buffer,
+                        &DependencyInfo::default()
+                            .dependency_flags(*dependency_flags)
+                            .memory_barriers(memory_barriers.as_slice())
+                            .image_memory_barriers(image_barriers.as_slice())
+                            .buffer_memory_barriers(buffer_barriers.as_slice()),
+                    )
+                }
+                RecordedCommand::BeginRendering(info) => {
+                    let color_attachments = info
+                        .color_attachments
+                        .iter()
+                        .map(RecordedAttachment::to_vk)
+                        .collect::<Vec<_>>();
+                    let depth_attachment = info
+                        .depth_attachment
+                        .as_ref()
+                        .map(RecordedAttachment::to_vk);
+                    let stencil_attachment = info
+                        .stencil_attachment
+                        .as_ref()
+                        .map(RecordedAttachment::to_vk);
+                    let mut rendering_info = RenderingInfo::default()
+                        .flags(info.flags)
+                        .render_area(info.render_area)
+                        .layer_count(info.layer_count)
+                        .view_mask(info.view_mask)
+                        .color_attachments(color_attachments.as_slice());
+                    if let Some(depth_attachment) = depth_attachment.as_ref() {
+                        rendering_info = rendering_info.depth_attachment(depth_attachment);
+                    }
+                    if let Some(stencil_attachment) = stencil_attachment.as_ref() {
+                        rendering_info = rendering_info.stencil_attachment(stencil_attachment);
+                    }
+                    device.cmd_begin_rendering(command_buffer, &rendering_info)
+                }
+                RecordedCommand::EndRendering => device.cmd_end_rendering(command_buffer),
+                RecordedCommand::BeginRenderPass {
+                    render_pass,
+                    framebuffer,
+                    render_area,
+                    clear_values,
+                    contents,
+                } => {
+                    let clear_values = clear_values.iter().map(|c| c.0).collect::<Vec<_>>();
+                    device.cmd_begin_render_pass(
+                        command_buffer,
+                        &vk::RenderPassBeginInfo::default()
+                            .render_pass(*render_pass)
+                            .framebuffer(*framebuffer)
+                            .render_area(*render_area)
+                            .clear_values(clear_values.as_slice()),
+                        *contents,
+                    )
+                }
+                RecordedCommand::NextSubpass(contents) => {
+                    device.cmd_next_subpass(command_buffer, *contents)
+                }
+                RecordedCommand::EndRenderPass => device.cmd_end_render_pass(command_buffer),
+                RecordedCommand::BindGraphicsPipeline(pipeline) => {
+                    device.cmd_bind_pipeline(command_buffer, PipelineBindPoint::GRAPHICS, *pipeline)
+                }
+                RecordedCommand::Draw {
+                    vertex_count,
+                    instance_count,
+                    first_vertex,
+                    first_instance,
+                } => device.cmd_draw(
+                    command_buffer,
+                    *vertex_count,
+                    *instance_count,
+                    *first_vertex,
+                    *first_instance,
+                ),
+                RecordedCommand::ExecuteCommands(command_buffers) => {
+                    device.cmd_execute_commands(command_buffer, command_buffers)
+                }
+                RecordedCommand::BeginLabel { name, color } => {
+                    if let Some(debug_utils) = debug_utils {
+                        let name = CString::new(name.as_str()).unwrap_or_default();
+                        debug_utils.cmd_begin_debug_utils_label(
+                            command_buffer,
+                            &DebugUtilsLabelEXT::default()
+                                .label_name(name.as_c_str())
+                                .color(*color),
+                        )
+                    }
+                }
+                RecordedCommand::EndLabel => {
+                    if let Some(debug_utils) = debug_utils {
+                        debug_utils.cmd_end_debug_utils_label(command_buffer)
+                    }
+                }
+                RecordedCommand::InsertLabel { name, color } => {
+                    if let Some(debug_utils) = debug_utils {
+                        let name = CString::new(name.as_str()).unwrap_or_default();
+                        debug_utils.cmd_insert_debug_utils_label(
+                            command_buffer,
+                            &DebugUtilsLabelEXT::default()
+                                .label_name(name.as_c_str())
+                                .color(*color),
+                        )
+                    }
+                }
+                RecordedCommand::ResetQueryPool {
+                    query_pool,
+                    first_query,
+                    query_count,
+                } => device.cmd_reset_query_pool(
+                    command_buffer,
+                    *query_pool,
+                    *first_query,
+                    *query_count,
+                ),
+                RecordedCommand::BeginQuery {
+                    query_pool,
+                    query,
+                    flags,
+                } => device.cmd_begin_query(command_buffer, *query_pool, *query, *flags),
+                RecordedCommand::EndQuery { query_pool, query } => {
+                    device.cmd_end_query(command_buffer, *query_pool, *query)
+                }
+                RecordedCommand::CopyQueryPoolResults {
+                    query_pool,
+                    first_query,
+                    query_count,
+                    buffer,
+                    offset,
+                    stride,
+                    flags,
+                } => device.cmd_copy_query_pool_results(
+                    command_buffer,
+                    *query_pool,
+                    *first_query,
+                    *query_count,
+                    *buffer,
+                    *offset,
+                    *stride,
+                    *flags,
+                ),
+                RecordedCommand::WriteTimestamp {
+                    stage,
+                    query_pool,
+                    query,
+                } => device.cmd_write_timestamp2(command_buffer, *stage, *query_pool, *query),
+                RecordedCommand::BeginConditionalRendering {
+                    buffer,
+                    offset,
+                    flags,
+                } => {
+                    if let Some(conditional_rendering) = conditional_rendering {
+                        (conditional_rendering
+                            .fp()
+                            .cmd_begin_conditional_rendering_ext)(
+                            command_buffer,
+                            &vk::ConditionalRenderingBeginInfoEXT::default()
+                                .buffer(*buffer)
+                                .offset(*offset)
+                                .flags(*flags),
+                        )
+                    }
+                }
+                RecordedCommand::EndConditionalRendering => {
+                    if let Some(conditional_rendering) = conditional_rendering {
+                        (conditional_rendering.fp().cmd_end_conditional_rendering_ext)(
+                            command_buffer,
+                        )
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CommandList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn commands(&self) -> &[RecordedCommand] {
+        &self.commands
+    }
+
+    #[inline]
+    pub fn push(&mut self, command: RecordedCommand) {
+        self.commands.push(command);
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    /// All barriers recorded so far, in recording order.
+    pub fn image_barriers(&self) -> impl Iterator<Item = &RecordedImageBarrier> {
+        self.commands.iter().flat_map(|c| match c {
+            RecordedCommand::PipelineBarrier { image_barriers, .. } => image_barriers.as_slice(),
+            _ => &[],
+        })
+    }
+
+    pub fn buffer_barriers(&self) -> impl Iterator<Item = &RecordedBufferBarrier> {
+        self.commands.iter().flat_map(|c| match c {
+            RecordedCommand::PipelineBarrier {
+                buffer_barriers, ..
+            } => buffer_barriers.as_slice(),
+            _ => &[],
+        })
+    }
+}
+
+/// One command per line, indented by rendering, label and conditional rendering scopes. Meant for logs and
+/// bug reports.
+impl fmt::Display for CommandList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut depth = 0usize;
+        for command in &self.commands {
+            if matches!(
+                command,
+                RecordedCommand::EndRendering
+                    | RecordedCommand::EndRenderPass
+                    | RecordedCommand::EndLabel
+                    | RecordedCommand::EndConditionalRendering
+            ) {
+                depth = depth.saturating_sub(1);
+            }
+            write!(f, "{:indent$}", "", indent = depth * 2)?;
+
+            match command {
+                RecordedCommand::PipelineBarrier {
+                    dependency_flags,
+                    memory_barriers,
+                    image_barriers,
+                    buffer_barriers,
+                } => {
+                    writeln!(f, "PipelineBarrier {:?}", dependency_flags)?;
+                    for b in memory_barriers {
+                        writeln!(
+                            f,
+                            "{:indent$}  memory {:?}/{:?} -> {:?}/{:?}",
+                            "",
+                            b.src_stage_mask,
+                            b.src_access_mask,
+                            b.dst_stage_mask,
+                            b.dst_access_mask,
+                            indent = depth * 2
+                        )?;
+                    }
+                    for b in image_barriers {
+                        writeln!(
+                            f,
+                            "{:indent$}  image {:?} {:?} {:?}/{:?}/{:?} -> {:?}/{:?}/{:?} queue {} -> {}",
+                            "",
+                            b.image,
+                            b.subresource_range,
+                            b.src_stage_mask,
+                            b.src_access_mask,
+                            b.old_layout,
+                            b.dst_stage_mask,
+                            b.dst_access_mask,
+                            b.new_layout,
+                            b.src_queue_family_index,
+                            b.dst_queue_family_index,
+                            indent = depth * 2
+                        )?;
+                    }
+                    for b in buffer_barriers {
+                        writeln!(
+                            f,
+                            "{:indent$}  buffer {:?} {}+{} {:?}/{:?} -> {:?}/{:?} queue {} -> {}",
+                            "",
+                            b.buffer,
+                            b.offset,
+                            b.size,
+                            b.src_stage_mask,
+                            b.src_access_mask,
+                            b.dst_stage_mask,
+                            b.dst_access_mask,
+                            b.src_queue_family_index,
+                            b.dst_queue_family_index,
+                            indent = depth * 2
+                        )?;
+                    }
+                }
+                RecordedCommand::BeginRendering(info) => {
+                    writeln!(
+                        f,
+                        "BeginRendering {:?} area {:?} layers {} view mask {}",
+                        info.flags, info.render_area, info.layer_count, info.view_mask
+                    )?;
+                    let attachments = info
+                        .color_attachments
+                        .iter()
+                        .map(|a| ("color", a))
+                        .chain(info.depth_attachment.iter().map(|a| ("depth", a)))
+                        .chain(info.stencil_attachment.iter().map(|a| ("stencil", a)));
+                    for (kind, a) in attachments {
+                        writeln!(
+                            f,
+                            "{:indent$}  {} {:?} {:?} {:?}/{:?}",
+                            "",
+                            kind,
+                            a.image_view,
+                            a.image_layout,
+                            a.load_op,
+                            a.store_op,
+                            indent = depth * 2
+                        )?;
+                    }
+                    depth += 1;
+                }
+                RecordedCommand::BeginRenderPass { .. }
+                | RecordedCommand::BeginConditionalRendering { .. } => {
+                    writeln!(f, "{:?}", command)?;
+                    depth += 1;
+                }
+                RecordedCommand::BeginLabel { name, .. } => {
+                    writeln!(f, "BeginLabel {:?}", name)?;
+                    depth += 1;
+                }
+                command => writeln!(f, "{:?}", command)?,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Debug for RecordedClearValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        unsafe {
+            f.debug_struct("ClearValue")
+                .field("float32", &self.0.color.float32)
+                .field("uint32", &self.0.color.uint32)
+                .field("depth_stencil", &self.0.depth_stencil)
+                .finish()
+        }
+    }
+}
+
+impl PartialEq for RecordedClearValue {
+    fn eq(&self, other: &Self) -> bool {
+        unsafe { self.0.color.uint32 == other.0.color.uint32 }
+    }
+}
+
+fn slice<'a, T>(pointer: *const T, count: u32) -> &'a [T] {
+    if pointer.is_null() || count == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(pointer, count as usize) }
+    }
+}
+
+/// Values stored as their Vulkan type but serialized as their raw representation.
+trait RawValue: Copy {
+    type Raw: Serialize + for<'de> Deserialize<'de>;
+
+    fn to_raw_value(self) -> Self::Raw;
+    fn from_raw_value(raw: Self::Raw) -> Self;
+}
+
+macro_rules! raw_values {
+    ($($ty:ty => $raw:ty),* $(,)?) => {
+        $(
+            impl RawValue for $ty {
+                type Raw = $raw;
+
+                fn to_raw_value(self) -> $raw {
+                    self.as_raw()
+                }
+
+                fn from_raw_value(raw: $raw) -> Self {
+                    <$ty>::from_raw(raw)
+                }
+            }
+        )*
+    };
+}
+
+raw_values! {
+    vk::Image => u64,
+    vk::ImageView => u64,
+    vk::Buffer => u64,
+    vk::RenderPass => u64,
+    vk::Framebuffer => u64,
+    vk::Pipeline => u64,
+    vk::CommandBuffer => u64,
+    vk::QueryPool => u64,
+    vk::PipelineStageFlags2 => u64,
+    vk::AccessFlags2 => u64,
+    vk::ImageAspectFlags => u32,
+    vk::DependencyFlags => u32,
+    vk::RenderingFlags => u32,
+    vk::ResolveModeFlags => u32,
+    vk::QueryControlFlags => u32,
+    vk::QueryResultFlags => u32,
+    vk::ConditionalRenderingFlagsEXT => u32,
+    vk::ImageLayout => i32,
+    vk::AttachmentLoadOp => i32,
+    vk::AttachmentStoreOp => i32,
+    vk::SubpassContents => i32,
+}
+
+impl RawValue for vk::Rect2D {
+    type Raw = (i32, i32, u32, u32);
+
+    fn to_raw_value(self) -> Self::Raw {
+        (
+            self.offset.x,
+            self.offset.y,
+            self.extent.width,
+            self.extent.height,
+        )
+    }
+
+    fn from_raw_value((x, y, width, height): Self::Raw) -> Self {
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height },
+        }
+    }
+}
+
+// the widest member of the union covers all of its bits
+impl RawValue for vk::ClearValue {
+    type Raw = [u32; 4];
+
+    fn to_raw_value(self) -> [u32; 4] {
+        unsafe { self.color.uint32 }
+    }
+
+    fn from_raw_value(uint32: [u32; 4]) -> Self {
+        vk::ClearValue {
+            color: vk::ClearColorValue { uint32 },
+        }
+    }
+}
+
+mod raw {
+    use super::RawValue;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    pub(super) fn serialize<T: RawValue, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        value.to_raw_value().serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, T: RawValue, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<T, D::Error> {
+        T::Raw::deserialize(deserializer).map(T::from_raw_value)
+    }
+}
+
+mod raw_vec {
+    use super::RawValue;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<T: RawValue, S: Serializer>(
+        values: &[T],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(values.iter().map(|v| v.to_raw_value()))
+    }
+
+    pub(super) fn deserialize<'de, T: RawValue, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<T>, D::Error> {
+        Vec::<T::Raw>::deserialize(deserializer)
+            .map(|raw| raw.into_iter().map(T::from_raw_value).collect())
+    }
+}
@@ -0,0 +1,126 @@
+use crate::render::RenderSystem;
+use crate::render::command_buffer::CommandBuffer;
+use ash::vk;
+use ash::vk::{CommandBufferAllocateInfo, CommandPoolCreateFlags, CommandPoolResetFlags};
+use std::rc::Rc;
+
+/// Allocates command buffers for a single queue family. Destroying the pool frees all of its command
+/// buffers, so buffers allocated with `allocate_command_buffers` keep it alive.
+pub struct CommandPool {
+    command_pool: vk::CommandPool,
+    queue_family: u32,
+    flags: CommandPoolCreateFlags,
+    device: ash::Device,
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    conditional_rendering: Option<ash::ext::conditional_rendering::Device>,
+}
+
+impl CommandPool {
+    /// Use `CommandPoolCreateFlags::TRANSIENT` for buffers that are re-recorded every frame or submitted
+    /// once, and `RESET_COMMAND_BUFFER` to allow resetting buffers individually instead of with `reset`.
+    pub fn new(
+        render_system: &RenderSystem,
+        queue_family: u32,
+        flags: CommandPoolCreateFlags,
+    ) -> anyhow::Result<Self> {
+        let device = render_system.device().clone();
+        let command_pool = unsafe {
+            device.create_command_pool(
+                &vk::CommandPoolCreateInfo::default()
+                    .flags(flags)
+                    .queue_family_index(queue_family),
+                None,
+            )
+        }?;
+
+        Ok(Self {
+            command_pool,
+            queue_family,
+            flags,
+            device,
+            debug_utils: render_system.debug_utils().cloned(),
+            conditional_rendering: render_system.conditional_rendering().cloned(),
+        })
+    }
+
+    #[inline]
+    pub fn handle(&self) -> vk::CommandPool {
+        self.command_pool
+    }
+
+    #[inline]
+    pub fn queue_family(&self) -> u32 {
+        self.queue_family
+    }
+
+    #[inline]
+    pub fn flags(&self) -> CommandPoolCreateFlags {
+        self.flags
+    }
+
+    /// The returned command buffers are freed when dropped.
+    pub fn allocate_command_buffers(
+        self: &Rc<Self>,
+        level: vk::CommandBufferLevel,
+        count: u32,
+    ) -> anyhow::Result<Vec<CommandBuffer>> {
+        Ok(self
+            .allocate_handles(level, count)?
+            .into_iter()
+            .map(|command_buffer| {
+                CommandBuffer::wrap(&self.device, command_buffer)
+                    .with_debug_utils(self.debug_utils.clone())
+                    .with_conditional_rendering(self.conditional_rendering.clone())
+                    .with_pool(self.clone())
+            })
+            .collect())
+    }
+
+    /// Allocates raw command buffers, which live until they're passed to `free_handles` or the pool is
+    /// destroyed.
+    pub fn allocate_handles(
+        &self,
+        level: vk::CommandBufferLevel,
+        count: u32,
+    ) -> anyhow::Result<Vec<vk::CommandBuffer>> {
+        unsafe {
+            self.device.allocate_command_buffers(
+                &CommandBufferAllocateInfo::default()
+                    .command_pool(self.command_pool)
+                    .level(level)
+                    .command_buffer_count(count),
+            )
+        }
+        .map_err(anyhow::Error::from)
+    }
+
+    pub fn free_handles(&self, command_buffers: &[vk::CommandBuffer]) {
+        unsafe {
+            self.device
+                .free_command_buffers(self.command_pool, command_buffers)
+        };
+    }
+
+    /// Resets every command buffer allocated from this pool to the initial state. None of them may be
+    /// pending execution.
+    pub fn reset(&self, release_resources: bool) -> anyhow::Result<()> {
+        let flags = if release_resources {
+            CommandPoolResetFlags::RELEASE_RESOURCES
+        } else {
+            CommandPoolResetFlags::empty()
+        };
+
+        unsafe { self.device.reset_command_pool(self.command_pool, flags) }
+            .map_err(anyhow::Error::from)
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.command_pool, name);
+    }
+}
+
+impl Drop for CommandPool {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_command_pool(self.command_pool, None) };
+    }
+}
@@ -9,12 +9,15 @@ use std::rc::Rc;
 
 pub struct DescriptorSetLayout {
     descriptor_set_layout: vk::DescriptorSetLayout,
+    // without immutable samplers, which aren't kept alive by the layout
+    bindings: Vec<vk::DescriptorSetLayoutBinding<'static>>,
     device: ash::Device,
 }
 
 pub struct DescriptorSetLayoutDescription<'a> {
     pub flags: vk::DescriptorSetLayoutCreateFlags,
     pub bindings: Vec<vk::DescriptorSetLayoutBinding<'a>>,
+    pub debug_name: Option<String>,
 }
 
 impl DescriptorSetLayout {
@@ -26,19 +29,49 @@ impl DescriptorSetLayout {
             .bindings(description.bindings.as_slice())
             .flags(description.flags);
 
-        Ok(unsafe {
+        let descriptor_set_layout = unsafe {
             let device = render_system.device().clone();
             Self {
                 descriptor_set_layout: device.create_descriptor_set_layout(&create_info, None)?,
+                bindings: description
+                    .bindings
+                    .iter()
+                    .map(|binding| {
+                        vk::DescriptorSetLayoutBinding::default()
+                            .binding(binding.binding)
+                            .descriptor_type(binding.descriptor_type)
+                            .descriptor_count(binding.descriptor_count)
+                            .stage_flags(binding.stage_flags)
+                    })
+                    .collect(),
                 device,
             }
-        })
+        };
+
+        if let Some(name) = description.debug_name.as_deref() {
+            descriptor_set_layout.set_debug_name(render_system, name);
+        }
+
+        Ok(descriptor_set_layout)
     }
 
     #[inline]
     pub fn handle(&self) -> vk::DescriptorSetLayout {
         self.descriptor_set_layout
     }
+
+    #[inline]
+    pub fn bindings(&self) -> &[vk::DescriptorSetLayoutBinding<'static>] {
+        &self.bindings
+    }
+
+    pub fn binding(&self, binding: u32) -> Option<&vk::DescriptorSetLayoutBinding<'static>> {
+        self.bindings.iter().find(|b| b.binding == binding)
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.descriptor_set_layout, name);
+    }
 }
 
 impl Drop for DescriptorSetLayout {
@@ -121,6 +154,10 @@ impl DescriptorPool {
         }
     }
 
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.descriptor_pool, name);
+    }
+
     pub fn reset(&self) -> anyhow::Result<()> {
         unsafe {
             Ok(self
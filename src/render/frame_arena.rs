@@ -0,0 +1,306 @@
+use crate::render::RenderSystem;
+use crate::render::resource::Buffer;
+use anyhow::anyhow;
+use ash::vk;
+use ash::vk::{BufferUsageFlags, MemoryPropertyFlags};
+use std::cell::{Cell, RefCell};
+
+/// A sub-allocation of a `FrameArena`. It's only valid until the frame in flight it was allocated in
+/// comes around again, or until the arena's buffer is recreated by `FrameArena::set_frames_in_flight`.
+#[derive(Copy, Clone, Debug)]
+pub struct ArenaAllocation {
+    pub buffer: vk::Buffer,
+    pub offset: u64,
+    pub size: u64,
+    mapped: *mut u8,
+}
+
+/// A host visible ring buffer split into one region per frame in flight, for per-frame uniforms and
+/// dynamic vertex data. Allocations are aligned to `minUniformBufferOffsetAlignment` (or the storage buffer
+/// equivalent, if larger) and are linear within a frame; a frame's region is recycled as a whole once its
+/// fence has signaled.
+///
+/// All regions share a single buffer, so one descriptor set with a `UNIFORM_BUFFER_DYNAMIC` binding can be
+/// used for every frame by passing `ArenaAllocation::dynamic_offset` when binding it. Attach it to a
+/// `PrimaryRenderer` with `set_frame_arena` to have it recycled automatically.
+pub struct FrameArena {
+    buffer: RefCell<Buffer>,
+    frames_in_flight: Cell<usize>,
+    bytes_per_frame: u64,
+    alignment: u64,
+    current_frame: Cell<usize>,
+    // offset of the next allocation within the current frame's region
+    head: Cell<u64>,
+}
+
+impl ArenaAllocation {
+    /// The offset to pass for a dynamic uniform or storage buffer binding. Fails if the arena is too large
+    /// for the allocation to be reachable with a 32-bit offset.
+    #[inline]
+    pub fn dynamic_offset(&self) -> anyhow::Result<u32> {
+        u32::try_from(self.offset).map_err(|_| {
+            anyhow!(
+                "Allocation at offset {} is out of reach of a dynamic offset",
+                self.offset
+            )
+        })
+    }
+
+    /// For non-dynamic bindings. Dynamic bindings are written with an offset of 0 and get `dynamic_offset`
+    /// when the descriptor set is bound.
+    #[inline]
+    pub fn descriptor_info(&self) -> vk::DescriptorBufferInfo {
+        vk::DescriptorBufferInfo {
+            buffer: self.buffer,
+            offset: self.offset,
+            range: self.size,
+        }
+    }
+
+    /// Writes `data` at `offset` within the allocation.
+    ///
+    /// # Safety
+    ///
+    /// The allocation must still be valid: its frame's region must not have been recycled by
+    /// `FrameArena::begin_frame`, which would overwrite data the GPU may still be reading, and the arena's
+    /// buffer must not have been recreated by `FrameArena::set_frames_in_flight`, which frees the memory
+    /// the allocation points to.
+    pub unsafe fn write(&self, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        let end = offset.checked_add(data.len() as u64);
+        if end.is_none_or(|end| end > self.size) {
+            return Err(anyhow!(
+                "Write of {} bytes at offset {} overflows allocation of size {}",
+                data.len(),
+                offset,
+                self.size
+            ));
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                data.as_ptr(),
+                self.mapped.add(offset as usize),
+                data.len(),
+            )
+        };
+        Ok(())
+    }
+}
+
+impl FrameArena {
+    pub fn new(
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+        bytes_per_frame: u64,
+    ) -> anyhow::Result<Self> {
+        let properties = unsafe {
+            render_system
+                .instance()
+                .get_physical_device_properties(render_system.physical_device())
+        };
+        let alignment = properties
+            .limits
+            .min_uniform_buffer_offset_alignment
+            .max(properties.limits.min_storage_buffer_offset_alignment);
+        let bytes_per_frame = bytes_per_frame.next_multiple_of(alignment);
+
+        Ok(Self {
+            buffer: RefCell::new(Self::create_buffer(
+                render_system,
+                frames_in_flight,
+                bytes_per_frame,
+            )?),
+            frames_in_flight: Cell::new(frames_in_flight),
+            bytes_per_frame,
+            alignment,
+            current_frame: Cell::new(0),
+            head: Cell::new(0),
+        })
+    }
+
+    fn create_buffer(
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+        bytes_per_frame: u64,
+    ) -> anyhow::Result<Buffer> {
+        if frames_in_flight == 0 {
+            return Err(anyhow!("At least one frame in flight is required"));
+        }
+
+        let buffer = Buffer::new(
+            render_system,
+            bytes_per_frame * frames_in_flight as u64,
+            BufferUsageFlags::UNIFORM_BUFFER
+                | BufferUsageFlags::STORAGE_BUFFER
+                | BufferUsageFlags::VERTEX_BUFFER
+                | BufferUsageFlags::INDEX_BUFFER
+                | BufferUsageFlags::INDIRECT_BUFFER,
+            MemoryPropertyFlags::HOST_VISIBLE | MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        buffer.set_debug_name(render_system, "frame arena");
+        Ok(buffer)
+    }
+
+    /// The buffer every allocation comes from. It only changes with `set_frames_in_flight`.
+    #[inline]
+    pub fn buffer_handle(&self) -> vk::Buffer {
+        self.buffer.borrow().handle()
+    }
+
+    #[inline]
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames_in_flight.get()
+    }
+
+    #[inline]
+    pub fn bytes_per_frame(&self) -> u64 {
+        self.bytes_per_frame
+    }
+
+    /// The alignment of every allocation's offset.
+    #[inline]
+    pub fn alignment(&self) -> u64 {
+        self.alignment
+    }
+
+    /// How many bytes of the current frame's region are in use.
+    #[inline]
+    pub fn used(&self) -> u64 {
+        self.head.get()
+    }
+
+    /// Recreates the buffer with a region per frame in flight. Every previous allocation and descriptor
+    /// referring to the old buffer is invalidated, so only call this while the device is idle.
+    pub fn set_frames_in_flight(
+        &self,
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+    ) -> anyhow::Result<()> {
+        if frames_in_flight == self.frames_in_flight.get() {
+            return Ok(());
+        }
+
+        *self.buffer.borrow_mut() =
+            Self::create_buffer(render_system, frames_in_flight, self.bytes_per_frame)?;
+        self.frames_in_flight.set(frames_in_flight);
+        self.current_frame.set(0);
+        self.head.set(0);
+        Ok(())
+    }
+
+    /// Starts allocating from `frame`'s region, discarding its previous allocations. The frame's previous
+    /// submission must have completed.
+    pub fn begin_frame(&self, frame: usize) {
+        self.current_frame.set(frame % self.frames_in_flight.get());
+        self.head.set(0);
+    }
+
+    pub fn allocate(&self, size: u64) -> anyhow::Result<ArenaAllocation> {
+        self.allocate_aligned(size, self.alignment)
+    }
+
+    /// `alignment` has to be a power of two. It's raised to the arena's alignment if it's lower.
+    pub fn allocate_aligned(&self, size: u64, alignment: u64) -> anyhow::Result<ArenaAllocation> {
+        let offset = suballocate(
+            self.head.get(),
+            size,
+            alignment.max(self.alignment),
+            self.bytes_per_frame,
+        )
+        .ok_or_else(|| {
+            anyhow!(
+                "Frame arena is out of memory: {} of {} bytes used, {} requested",
+                self.head.get(),
+                self.bytes_per_frame,
+                size
+            )
+        })?;
+        self.head.set(offset + size);
+
+        let buffer = self.buffer.borrow();
+        let offset = self.current_frame.get() as u64 * self.bytes_per_frame + offset;
+        Ok(ArenaAllocation {
+            buffer: buffer.handle(),
+            offset,
+            size,
+            mapped: unsafe {
+                buffer
+                    .mapped_ptr()
+                    .ok_or(anyhow!("Frame arena buffer is not mapped"))?
+                    .add(offset as usize)
+            },
+        })
+    }
+
+    /// Allocates room for `value` and copies it in.
+    pub fn push<T: bytemuck::Pod>(&self, value: &T) -> anyhow::Result<ArenaAllocation> {
+        self.push_slice(std::slice::from_ref(value))
+    }
+
+    /// Allocates room for `values` and copies them in, e.g. for dynamic vertex data.
+    pub fn push_slice<T: bytemuck::Pod>(&self, values: &[T]) -> anyhow::Result<ArenaAllocation> {
+        let data: &[u8] = bytemuck::cast_slice(values);
+        let allocation = self.allocate_aligned(data.len() as u64, align_of::<T>() as u64)?;
+        // freshly allocated from the current frame's region
+        unsafe { allocation.write(0, data) }?;
+        Ok(allocation)
+    }
+}
+
+// the offset of a `size` byte allocation aligned to `alignment` following `head`, or `None` if it doesn't
+// fit within `capacity`
+fn suballocate(head: u64, size: u64, alignment: u64, capacity: u64) -> Option<u64> {
+    let offset = head.checked_next_multiple_of(alignment)?;
+    let end = offset.checked_add(size)?;
+    (end <= capacity).then_some(offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn allocation(offset: u64, memory: &mut [u8]) -> ArenaAllocation {
+        ArenaAllocation {
+            buffer: vk::Buffer::from_raw(1),
+            offset,
+            size: memory.len() as u64,
+            mapped: memory.as_mut_ptr(),
+        }
+    }
+
+    #[test]
+    fn writes_within_bounds() {
+        let mut memory = [0u8; 8];
+        let allocation = allocation(256, &mut memory);
+        unsafe {
+            allocation.write(4, &[1, 2, 3, 4]).unwrap();
+            assert!(allocation.write(6, &[1, 2, 3]).is_err());
+            assert!(allocation.write(u64::MAX, &[1]).is_err());
+        }
+        assert_eq!(memory, [0, 0, 0, 0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn suballocates_aligned_ranges() {
+        assert_eq!(suballocate(0, 64, 256, 1024), Some(0));
+        assert_eq!(suballocate(64, 64, 256, 1024), Some(256));
+        assert_eq!(suballocate(64, 768, 256, 1024), Some(256));
+        assert_eq!(suballocate(64, 769, 256, 1024), None);
+    }
+
+    #[test]
+    fn rejects_overflowing_allocations() {
+        assert_eq!(suballocate(256, u64::MAX, 256, 1024), None);
+        assert_eq!(suballocate(0, u64::MAX, 256, u64::MAX), Some(0));
+        assert_eq!(suballocate(1, u64::MAX, 256, u64::MAX), None);
+        assert_eq!(suballocate(u64::MAX - 1, 1, 256, u64::MAX), None);
+    }
+
+    #[test]
+    fn rejects_unreachable_dynamic_offsets() {
+        let mut memory = [0u8; 8];
+        assert_eq!(allocation(256, &mut memory).dynamic_offset().unwrap(), 256);
+        assert!(allocation(1 << 32, &mut memory).dynamic_offset().is_err());
+    }
+}
@@ -0,0 +1,302 @@
+use crate::render::RenderSystem;
+use crate::render::pipeline::{GraphicsPipeline, GraphicsPipelineDescription};
+use crate::render::pipeline_file::{GraphicsPipelineDef, PipelineRegistry};
+use crate::render::shader::ShaderModule;
+use crate::render::shader_cache::ShaderPermutation;
+use ash::vk;
+use log::{error, info};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc;
+
+pub type ShaderStages = Vec<(vk::ShaderStageFlags, Rc<ShaderModule>, String)>;
+pub type PipelineBuilder = Box<dyn Fn(ShaderStages) -> GraphicsPipelineDescription>;
+
+/// A pipeline managed by a `HotReloader`. Fetch it with `get` every frame rather than holding on to the
+/// returned pipeline, since it's replaced whenever one of its sources changes.
+#[derive(Clone)]
+pub struct PipelineHandle {
+    current: Rc<RefCell<Rc<GraphicsPipeline>>>,
+}
+
+enum PipelineSource {
+    /// A pipeline description file, see `GraphicsPipelineDef`.
+    File(PathBuf),
+    /// Shader permutations (with paths relative to the registry's base directory), and a function
+    /// completing the description once they're loaded.
+    Code {
+        stages: Vec<ShaderPermutation>,
+        build: PipelineBuilder,
+    },
+}
+
+struct WatchedShader {
+    /// Every file it was compiled from, including itself.
+    includes: Vec<PathBuf>,
+}
+
+struct WatchedPipeline {
+    source: PipelineSource,
+    handle: PipelineHandle,
+    dependencies: HashSet<PathBuf>,
+}
+
+/// Watches the shaders and pipeline description files below a directory, recompiling shader modules and
+/// rebuilding every pipeline depending on them when they change.
+///
+/// Changes are only applied in `update`, which has to be called once per frame before recording it. A
+/// replaced pipeline is kept alive until every frame in flight that could still be using it has completed.
+/// If a shader fails to compile or a pipeline fails to build, the error is logged and the old pipeline is
+/// kept.
+pub struct HotReloader {
+    registry: PipelineRegistry,
+    pipelines: Vec<WatchedPipeline>,
+    /// Keyed by the stage and entry point too, since one file can be loaded for several of them.
+    shaders: HashMap<ShaderPermutation, WatchedShader>,
+    // replaced pipelines and the number of `update` calls left until they're no longer in flight
+    retired: Vec<(usize, Rc<GraphicsPipeline>)>,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    _watcher: RecommendedWatcher,
+}
+
+impl PipelineHandle {
+    #[inline]
+    pub fn get(&self) -> Rc<GraphicsPipeline> {
+        self.current.borrow().clone()
+    }
+}
+
+impl HotReloader {
+    /// Watches `registry`'s base directory recursively. Layouts and render passes referenced by pipeline
+    /// files have to be registered with it.
+    pub fn new(registry: PipelineRegistry) -> anyhow::Result<Self> {
+        // events carry absolute paths, so resolve shaders against an absolute base directory too
+        let mut registry = registry;
+        let base_dir = std::fs::canonicalize(registry.base_dir())?;
+        registry.set_base_dir(base_dir.clone());
+
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&base_dir, RecursiveMode::Recursive)?;
+
+        Ok(Self {
+            registry,
+            pipelines: Vec::new(),
+            shaders: HashMap::new(),
+            retired: Vec::new(),
+            events,
+            _watcher: watcher,
+        })
+    }
+
+    #[inline]
+    pub fn registry(&self) -> &PipelineRegistry {
+        &self.registry
+    }
+
+    #[inline]
+    pub fn registry_mut(&mut self) -> &mut PipelineRegistry {
+        &mut self.registry
+    }
+
+    /// Loads a pipeline description file and rebuilds the pipeline whenever the file or one of the
+    /// shaders it references changes.
+    pub fn load_pipeline(
+        &mut self,
+        render_system: &RenderSystem,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<PipelineHandle> {
+        let path = std::fs::canonicalize(path.as_ref())?;
+        self.watch(render_system, PipelineSource::File(path))
+    }
+
+    /// Loads `stages` and passes them to `build` for the rest of the description. The pipeline is rebuilt
+    /// with the same function whenever one of the shaders changes.
+    pub fn add_pipeline(
+        &mut self,
+        render_system: &RenderSystem,
+        stages: Vec<ShaderPermutation>,
+        build: impl Fn(ShaderStages) -> GraphicsPipelineDescription + 'static,
+    ) -> anyhow::Result<PipelineHandle> {
+        self.watch(
+            render_system,
+            PipelineSource::Code {
+                stages,
+                build: Box::new(build),
+            },
+        )
+    }
+
+    fn watch(
+        &mut self,
+        render_system: &RenderSystem,
+        source: PipelineSource,
+    ) -> anyhow::Result<PipelineHandle> {
+        let (pipeline, dependencies) = build(
+            &mut self.registry,
+            &mut self.shaders,
+            render_system,
+            &source,
+        )?;
+        let handle = PipelineHandle {
+            current: Rc::new(RefCell::new(Rc::new(pipeline))),
+        };
+        self.pipelines.push(WatchedPipeline {
+            source,
+            handle: handle.clone(),
+            dependencies,
+        });
+
+        Ok(handle)
+    }
+
+    /// Applies file changes since the last call and releases replaced pipelines that are no longer in
+    /// flight. Call once per frame, before recording it, with the renderer's `frames_in_flight`.
+    pub fn update(&mut self, render_system: &RenderSystem, frames_in_flight: usize) {
+        self.retired.retain_mut(|(frames_left, _)| {
+            *frames_left = frames_left.saturating_sub(1);
+            *frames_left > 0
+        });
+
+        let mut changed = HashSet::new();
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    changed.extend(event.paths.iter().map(|path| normalize(path)))
+                }
+                Ok(_) => (),
+                Err(e) => error!("File watcher error: {}", e),
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        // shaders whose source or one of its includes changed
+        let stale = self
+            .shaders
+            .iter()
+            .filter(|(permutation, shader)| {
+                changed.contains(&permutation.path)
+                    || shader.includes.iter().any(|i| changed.contains(i))
+            })
+            .map(|(permutation, _)| permutation.clone())
+            .collect::<Vec<_>>();
+
+        let mut failed = HashSet::new();
+        for permutation in stale {
+            match self.registry.reload_shader(render_system, &permutation) {
+                Ok(_) => info!(
+                    "Reloaded shader '{}' ({})",
+                    permutation.path.display(),
+                    permutation.entry_point
+                ),
+                Err(e) => {
+                    error!(
+                        "Failed to reload shader '{}':\n{}",
+                        permutation.path.display(),
+                        e
+                    );
+                    failed.insert(permutation.path);
+                }
+            }
+        }
+
+        for pipeline in self.pipelines.iter_mut() {
+            if pipeline.dependencies.is_disjoint(&changed)
+                || !pipeline.dependencies.is_disjoint(&failed)
+            {
+                continue;
+            }
+
+            match build(
+                &mut self.registry,
+                &mut self.shaders,
+                render_system,
+                &pipeline.source,
+            ) {
+                Ok((new, dependencies)) => {
+                    let old = pipeline.handle.current.replace(Rc::new(new));
+                    info!("Rebuilt pipeline {}", pipeline_name(&old));
+                    self.retired.push((frames_in_flight, old));
+                    pipeline.dependencies = dependencies;
+                }
+                Err(e) => error!(
+                    "Failed to rebuild pipeline {}, keeping the old one: {}",
+                    pipeline_name(&pipeline.handle.get()),
+                    e
+                ),
+            }
+        }
+    }
+}
+
+fn build(
+    registry: &mut PipelineRegistry,
+    shaders: &mut HashMap<ShaderPermutation, WatchedShader>,
+    render_system: &RenderSystem,
+    source: &PipelineSource,
+) -> anyhow::Result<(GraphicsPipeline, HashSet<PathBuf>)> {
+    let mut dependencies = HashSet::new();
+    let (description, permutations) = match source {
+        PipelineSource::File(path) => {
+            dependencies.insert(path.clone());
+            let def = GraphicsPipelineDef::load(path)?;
+            let mut description = def.to_description(render_system, registry)?;
+            if description.debug_name.is_none() {
+                description.debug_name = Some(path.to_string_lossy().into_owned());
+            }
+
+            let permutations: Vec<_> = def.stages.iter().map(|stage| stage.permutation()).collect();
+            (description, permutations)
+        }
+        PipelineSource::Code { stages, build } => {
+            let shader_stages = stages
+                .iter()
+                .map(|permutation| {
+                    Ok((
+                        permutation.stage,
+                        registry.shader(render_system, permutation)?,
+                        permutation.entry_point.clone(),
+                    ))
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            (build(shader_stages), stages.clone())
+        }
+    };
+
+    for (permutation, (_, module, _)) in permutations.iter().zip(description.shader_stages.iter()) {
+        // the same key the registry caches the module under, so reloads replace the module pipelines use
+        let permutation = registry.resolve_permutation(permutation);
+        let includes = module
+            .dependencies()
+            .iter()
+            .map(|dependency| normalize(dependency))
+            .collect::<Vec<_>>();
+
+        dependencies.extend(includes.iter().cloned());
+        dependencies.insert(permutation.path.clone());
+        shaders.insert(permutation, WatchedShader { includes });
+    }
+
+    Ok((
+        GraphicsPipeline::new(render_system, &description)?,
+        dependencies,
+    ))
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    // editors that save by renaming can leave the path briefly missing
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn pipeline_name(pipeline: &GraphicsPipeline) -> String {
+    pipeline.debug_name().map_or_else(
+        || format!("{:?}", pipeline.handle()),
+        |name| format!("'{}'", name),
+    )
+}
@@ -0,0 +1,100 @@
+use crate::render::command_buffer::CommandBuffer;
+use crate::render::command_pool::CommandPool;
+use crate::render::{QueueKind, RenderSystem};
+use ash::vk;
+use std::rc::Rc;
+
+/// The transient command pool and reusable fences `RenderSystem::immediate_submit` uses for one queue.
+pub(crate) struct ImmediateContext {
+    pub(crate) command_pool: Rc<CommandPool>,
+    /// Unsignaled fences of completed submissions.
+    pub(crate) free_fences: Vec<vk::Fence>,
+    device: ash::Device,
+}
+
+/// A command buffer submitted with `RenderSystem::immediate_submit_async`. Dropping the token waits for
+/// the submission to complete, so keep it around until its results are needed.
+pub struct SubmitToken<'a> {
+    render_system: &'a RenderSystem,
+    queue: QueueKind,
+    fence: vk::Fence,
+    // freed back to the pool once the submission has completed
+    _command_buffer: CommandBuffer,
+}
+
+impl ImmediateContext {
+    pub(crate) fn new(render_system: &RenderSystem, queue_family: u32) -> anyhow::Result<Self> {
+        let command_pool = CommandPool::new(
+            render_system,
+            queue_family,
+            vk::CommandPoolCreateFlags::TRANSIENT,
+        )?;
+        command_pool.set_debug_name(render_system, "immediate submit command pool");
+
+        Ok(Self {
+            command_pool: Rc::new(command_pool),
+            free_fences: Vec::new(),
+            device: render_system.device().clone(),
+        })
+    }
+}
+
+impl Drop for ImmediateContext {
+    fn drop(&mut self) {
+        unsafe {
+            for fence in self.free_fences.drain(..) {
+                self.device.destroy_fence(fence, None);
+            }
+        }
+    }
+}
+
+impl<'a> SubmitToken<'a> {
+    pub(crate) fn new(
+        render_system: &'a RenderSystem,
+        queue: QueueKind,
+        fence: vk::Fence,
+        command_buffer: CommandBuffer,
+    ) -> Self {
+        Self {
+            render_system,
+            queue,
+            fence,
+            _command_buffer: command_buffer,
+        }
+    }
+
+    /// Whether the submission has completed, without blocking.
+    pub fn is_complete(&self) -> bool {
+        unsafe { self.render_system.device().get_fence_status(self.fence) }.unwrap_or(false)
+    }
+
+    /// Blocks until the submission has completed.
+    pub fn wait(self) -> anyhow::Result<()> {
+        unsafe {
+            self.render_system
+                .device()
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+        }
+        .map_err(anyhow::Error::from)
+    }
+}
+
+impl Drop for SubmitToken<'_> {
+    fn drop(&mut self) {
+        let device = self.render_system.device();
+        unsafe {
+            // after a failed wait (i.e. a lost device) the fence can't be reused
+            if device
+                .wait_for_fences(&[self.fence], true, u64::MAX)
+                .and_then(|_| device.reset_fences(&[self.fence]))
+                .is_ok()
+            {
+                self.render_system
+                    .recycle_immediate_fence(self.queue, self.fence);
+            } else {
+                device.destroy_fence(self.fence, None);
+            }
+        }
+    }
+}
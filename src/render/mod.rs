@@ -1,20 +1,40 @@
 pub mod command_buffer;
+pub mod command_list;
+pub mod command_pool;
+pub mod frame_arena;
+pub mod hot_reload;
+pub mod immediate;
 pub mod primary_renderer;
 pub mod render_target;
 pub mod pipeline;
+pub mod pipeline_file;
 pub mod descriptor;
 pub mod shader;
-
-use crate::render::command_buffer::CommandBuffer;
+pub mod shader_cache;
+pub mod resource_state;
+pub mod resource;
+pub mod render_graph;
+pub mod render_pass;
+pub mod parallel;
+pub mod query;
+pub mod reflection;
+pub mod profiler;
+pub mod validation;
+pub mod vertex;
+
+use crate::render::command_buffer::CommandRecorder;
+use crate::render::immediate::{ImmediateContext, SubmitToken};
 use crate::window::WindowSystem;
 use anyhow::anyhow;
 use ash::vk;
 use ash::vk::{
-    ApplicationInfo, CommandBufferAllocateInfo, CommandPoolCreateFlags, CommandPoolCreateInfo,
-    DeviceCreateInfo, DeviceQueueCreateInfo, FenceCreateFlags, FenceCreateInfo, InstanceCreateInfo,
-    SemaphoreCreateFlags, SemaphoreCreateInfo, StructureType,
+    ApplicationInfo, DeviceCreateInfo, DeviceQueueCreateInfo, FenceCreateFlags, FenceCreateInfo,
+    InstanceCreateInfo, SemaphoreCreateFlags, SemaphoreCreateInfo, StructureType,
 };
 use log::{debug, info};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
 use std::ffi::{CStr, CString, c_char};
 use std::marker::PhantomData;
 
@@ -32,14 +52,35 @@ pub struct Queues {
     pub transfer: vk::Queue,
 }
 
+/// The queues commands can be submitted to with `RenderSystem::immediate_submit`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum QueueKind {
+    Main,
+    Transfer,
+}
+
+/// Device capabilities that are only enabled when the device supports them. Code relying on one of these
+/// has to work without it, usually by falling back to something simpler.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct OptionalFeatures {
+    /// VK_EXT_conditional_rendering
+    pub conditional_rendering: bool,
+}
+
 pub struct RenderSystem {
     entry: ash::Entry,
     instance: ash::Instance,
     physical_device: vk::PhysicalDevice,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    enabled_features: vk::PhysicalDeviceFeatures,
+    optional_features: OptionalFeatures,
     device: ash::Device,
     queue_family_info: QueueFamilyInfo,
     queues: Queues,
-    main_pool: vk::CommandPool,
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    conditional_rendering: Option<ash::ext::conditional_rendering::Device>,
+    // created on first use, since they need a `RenderSystem` themselves
+    immediate_contexts: RefCell<HashMap<QueueKind, ImmediateContext>>,
 }
 
 impl RenderSystem {
@@ -59,11 +100,20 @@ impl RenderSystem {
             .filter_map(Result::ok)
             .collect::<Vec<_>>();
 
-        let required_extensions_cstrs = required_extensions_cstrings
+        let mut required_extensions_cstrs = required_extensions_cstrings
             .iter()
             .map(|cs| cs.as_ptr())
             .collect::<Vec<_>>();
 
+        let debug_utils_available = unsafe { entry.enumerate_instance_extension_properties(None) }?
+            .iter()
+            .any(|e| e.extension_name_as_c_str() == Ok(ash::ext::debug_utils::NAME));
+        if debug_utils_available {
+            required_extensions_cstrs.push(ash::ext::debug_utils::NAME.as_ptr());
+        } else {
+            debug!("VK_EXT_debug_utils is unavailable, debug names and labels are disabled");
+        }
+
         let instance_create_info = InstanceCreateInfo::default()
             .application_info(&application_info)
             .enabled_extension_names(required_extensions_cstrs.as_slice());
@@ -86,6 +136,9 @@ impl RenderSystem {
             .to_str()?
         );
 
+        let memory_properties =
+            unsafe { instance.get_physical_device_memory_properties(physical_device) };
+
         let queue_family_properties =
             unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
         let mut main_queue: Option<u32> = None;
@@ -140,11 +193,40 @@ impl RenderSystem {
             transfer: transfer_queue.unwrap(),
         };
 
-        let device_extensions = DEVICE_EXTENSIONS
+        let available_device_extensions =
+            unsafe { instance.enumerate_device_extension_properties(physical_device) }?;
+        let device_extension_available = |name: &CStr| {
+            available_device_extensions
+                .iter()
+                .any(|e| e.extension_name_as_c_str() == Ok(name))
+        };
+
+        let mut supported_conditional_rendering =
+            vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default();
+        if device_extension_available(ash::ext::conditional_rendering::NAME) {
+            unsafe {
+                instance.get_physical_device_features2(
+                    physical_device,
+                    &mut vk::PhysicalDeviceFeatures2::default()
+                        .push_next(&mut supported_conditional_rendering),
+                )
+            };
+        }
+
+        let optional_features = OptionalFeatures {
+            conditional_rendering: supported_conditional_rendering.conditional_rendering
+                == vk::TRUE,
+        };
+        debug!("Optional features: {:?}", optional_features);
+
+        let mut device_extensions = DEVICE_EXTENSIONS
             .iter()
             .cloned()
             .map(CStr::as_ptr)
             .collect::<Vec<*const c_char>>();
+        if optional_features.conditional_rendering {
+            device_extensions.push(ash::ext::conditional_rendering::NAME.as_ptr());
+        }
 
         const QUEUE_PRIORITIES: [f32; 1] = [1.0];
 
@@ -186,19 +268,28 @@ impl RenderSystem {
         let mut vulkan_11_features =
             vk::PhysicalDeviceVulkan11Features::default().shader_draw_parameters(true);
 
+        let supported_features = unsafe { instance.get_physical_device_features(physical_device) };
+        let enabled_features = vk::PhysicalDeviceFeatures::default()
+            .fill_mode_non_solid(true)
+            .tessellation_shader(true)
+            .geometry_shader(true)
+            .large_points(true)
+            .wide_lines(true)
+            .multi_draw_indirect(true)
+            .occlusion_query_precise(supported_features.occlusion_query_precise == vk::TRUE)
+            .pipeline_statistics_query(supported_features.pipeline_statistics_query == vk::TRUE);
+
+        let mut conditional_rendering_features =
+            vk::PhysicalDeviceConditionalRenderingFeaturesEXT::default().conditional_rendering(true);
+
         let mut features = vk::PhysicalDeviceFeatures2::default()
-            .features(
-                vk::PhysicalDeviceFeatures::default()
-                    .fill_mode_non_solid(true)
-                    .tessellation_shader(true)
-                    .geometry_shader(true)
-                    .large_points(true)
-                    .wide_lines(true)
-                    .multi_draw_indirect(true),
-            )
+            .features(enabled_features)
             .push_next(&mut vulkan_11_features)
             .push_next(&mut vulkan_12_features)
             .push_next(&mut vulkan_13_features);
+        if optional_features.conditional_rendering {
+            features = features.push_next(&mut conditional_rendering_features);
+        }
 
         let device_create_info = DeviceCreateInfo::default()
             .enabled_extension_names(&device_extensions)
@@ -207,33 +298,31 @@ impl RenderSystem {
 
         let device = unsafe { instance.create_device(physical_device, &device_create_info, None) }?;
 
+        let debug_utils =
+            debug_utils_available.then(|| ash::ext::debug_utils::Device::new(&instance, &device));
+        let conditional_rendering = optional_features
+            .conditional_rendering
+            .then(|| ash::ext::conditional_rendering::Device::new(&instance, &device));
+
         let queues = Queues {
             main: unsafe { device.get_device_queue(queue_family_info.main, 0) },
             present: unsafe { device.get_device_queue(queue_family_info.present, 0) },
             transfer: unsafe { device.get_device_queue(queue_family_info.transfer, 0) },
         };
 
-        let main_pool = unsafe {
-            device.create_command_pool(
-                &CommandPoolCreateInfo {
-                    s_type: StructureType::COMMAND_POOL_CREATE_INFO,
-                    p_next: std::ptr::null_mut(),
-                    flags: CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
-                    queue_family_index: queue_family_info.main,
-                    _marker: PhantomData,
-                },
-                None,
-            )
-        }?;
-
         Ok(Self {
             entry,
             instance,
             physical_device,
+            memory_properties,
+            enabled_features,
+            optional_features,
             device,
             queue_family_info,
             queues,
-            main_pool,
+            debug_utils,
+            conditional_rendering,
+            immediate_contexts: RefCell::new(HashMap::new()),
         })
     }
 
@@ -253,6 +342,44 @@ impl RenderSystem {
         &self.device
     }
 
+    /// `None` when VK_EXT_debug_utils isn't supported by the instance.
+    pub fn debug_utils(&self) -> Option<&ash::ext::debug_utils::Device> {
+        self.debug_utils.as_ref()
+    }
+
+    /// Names a Vulkan object for tools like RenderDoc. Does nothing without VK_EXT_debug_utils.
+    #[inline]
+    pub fn set_debug_name<T: vk::Handle>(&self, handle: T, name: &str) {
+        set_debug_name(self.debug_utils.as_ref(), handle, name);
+    }
+
+    /// The core features the device was created with. Optional ones are only enabled when supported.
+    pub fn enabled_features(&self) -> &vk::PhysicalDeviceFeatures {
+        &self.enabled_features
+    }
+
+    pub fn optional_features(&self) -> &OptionalFeatures {
+        &self.optional_features
+    }
+
+    /// `None` unless `OptionalFeatures::conditional_rendering` is enabled.
+    pub fn conditional_rendering(&self) -> Option<&ash::ext::conditional_rendering::Device> {
+        self.conditional_rendering.as_ref()
+    }
+
+    pub fn memory_properties(&self) -> &vk::PhysicalDeviceMemoryProperties {
+        &self.memory_properties
+    }
+
+    #[inline]
+    pub fn allocate_memory(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> anyhow::Result<vk::DeviceMemory> {
+        allocate_memory(&self.device, &self.memory_properties, requirements, properties)
+    }
+
     pub fn create_semaphore(&self) -> anyhow::Result<vk::Semaphore> {
         const CREATE_INFO: SemaphoreCreateInfo = SemaphoreCreateInfo {
             s_type: StructureType::SEMAPHORE_CREATE_INFO,
@@ -288,30 +415,86 @@ impl RenderSystem {
         .map_err(anyhow::Error::from)
     }
 
-    pub fn create_command_buffers<const N: usize>(
+    pub fn queues(&self) -> &Queues {
+        &self.queues
+    }
+
+    /// Records commands with `f`, submits them to `queue` and waits for them to complete. Meant for uploads,
+    /// layout setup and readbacks outside of the frame loop, not for per-frame work.
+    pub fn immediate_submit(
         &self,
-        level: vk::CommandBufferLevel,
-    ) -> anyhow::Result<[CommandBuffer; N]> {
-        unsafe {
-            self.device
-                .allocate_command_buffers(&CommandBufferAllocateInfo {
-                    s_type: StructureType::COMMAND_BUFFER_ALLOCATE_INFO,
-                    p_next: std::ptr::null_mut(),
-                    command_pool: self.main_pool,
-                    level,
-                    command_buffer_count: N as u32,
-                    _marker: PhantomData,
-                })
-        }?
-        .into_iter()
-        .map(|c| CommandBuffer::wrap(&self.device, c))
-        .collect::<Vec<_>>()
-        .try_into()
-        .map_err(|_| anyhow!("Command buffer allocation failed"))
+        queue: QueueKind,
+        f: impl FnOnce(&CommandRecorder),
+    ) -> anyhow::Result<()> {
+        self.immediate_submit_async(queue, f)?.wait()
     }
 
-    pub fn queues(&self) -> &Queues {
-        &self.queues
+    /// Like `immediate_submit`, but returns as soon as the commands are submitted. The returned token
+    /// waits for them when dropped.
+    pub fn immediate_submit_async(
+        &self,
+        queue: QueueKind,
+        f: impl FnOnce(&CommandRecorder),
+    ) -> anyhow::Result<SubmitToken<'_>> {
+        let (queue_handle, queue_family) = match queue {
+            QueueKind::Main => (self.queues.main, self.queue_family_info.main),
+            QueueKind::Transfer => (self.queues.transfer, self.queue_family_info.transfer),
+        };
+
+        let (mut command_buffer, fence) = {
+            let mut contexts = self.immediate_contexts.borrow_mut();
+            let context = match contexts.entry(queue) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    entry.insert(ImmediateContext::new(self, queue_family)?)
+                }
+            };
+
+            let command_buffer = context
+                .command_pool
+                .allocate_command_buffers(vk::CommandBufferLevel::PRIMARY, 1)?
+                .pop()
+                .ok_or(anyhow!("Command buffer allocation failed"))?;
+            let fence = match context.free_fences.pop() {
+                Some(fence) => fence,
+                None => self.create_fence(false)?,
+            };
+            (command_buffer, fence)
+        };
+
+        // the recorder ends the command buffer when it's dropped at the end of the closure
+        let recorded = command_buffer
+            .begin(Some(
+                vk::CommandBufferBeginInfo::default()
+                    .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+            ))
+            .map(|cmd| f(&cmd));
+        if let Err(e) = recorded {
+            self.recycle_immediate_fence(queue, fence);
+            return Err(e);
+        }
+
+        let submitted = unsafe {
+            self.device.queue_submit(
+                queue_handle,
+                &[vk::SubmitInfo::default().command_buffers(&[command_buffer.handle()])],
+                fence,
+            )
+        };
+        if let Err(e) = submitted {
+            self.recycle_immediate_fence(queue, fence);
+            return Err(e.into());
+        }
+
+        Ok(SubmitToken::new(self, queue, fence, command_buffer))
+    }
+
+    /// Returns an unsignaled fence to the pool `immediate_submit` takes its fences from.
+    pub(crate) fn recycle_immediate_fence(&self, queue: QueueKind, fence: vk::Fence) {
+        match self.immediate_contexts.borrow_mut().get_mut(&queue) {
+            Some(context) => context.free_fences.push(fence),
+            None => unsafe { self.device.destroy_fence(fence, None) },
+        }
     }
 
     pub fn queue_families(&self) -> &QueueFamilyInfo {
@@ -325,10 +508,68 @@ impl RenderSystem {
     // pub fn update_descriptor_sets(&self, )
 }
 
+pub fn set_debug_name<T: vk::Handle>(
+    debug_utils: Option<&ash::ext::debug_utils::Device>,
+    handle: T,
+    name: &str,
+) {
+    let Some(debug_utils) = debug_utils else {
+        return;
+    };
+    let Ok(name) = CString::new(name) else {
+        return;
+    };
+
+    unsafe {
+        let _ = debug_utils.set_debug_utils_object_name(
+            &vk::DebugUtilsObjectNameInfoEXT::default()
+                .object_handle(handle)
+                .object_name(name.as_c_str()),
+        );
+    }
+}
+
+pub fn find_memory_type(
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    type_bits: u32,
+    properties: vk::MemoryPropertyFlags,
+) -> Option<u32> {
+    memory_properties.memory_types[..memory_properties.memory_type_count as usize]
+        .iter()
+        .enumerate()
+        .find(|(index, memory_type)| {
+            type_bits & (1 << index) != 0 && memory_type.property_flags.contains(properties)
+        })
+        .map(|(index, _)| index as u32)
+}
+
+pub fn allocate_memory(
+    device: &ash::Device,
+    memory_properties: &vk::PhysicalDeviceMemoryProperties,
+    requirements: vk::MemoryRequirements,
+    properties: vk::MemoryPropertyFlags,
+) -> anyhow::Result<vk::DeviceMemory> {
+    let memory_type_index =
+        find_memory_type(memory_properties, requirements.memory_type_bits, properties)
+            .ok_or(anyhow!("No memory type supports {:?}", properties))?;
+
+    unsafe {
+        device.allocate_memory(
+            &vk::MemoryAllocateInfo::default()
+                .allocation_size(requirements.size)
+                .memory_type_index(memory_type_index),
+            None,
+        )
+    }
+    .map_err(anyhow::Error::from)
+}
+
 impl Drop for RenderSystem {
     fn drop(&mut self) {
+        // the immediate submit pools and fences have to go before the device
+        self.immediate_contexts.get_mut().clear();
+
         unsafe {
-            self.device.destroy_command_pool(self.main_pool, None);
             self.device.destroy_device(None);
             self.instance.destroy_instance(None);
         }
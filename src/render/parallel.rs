@@ -0,0 +1,238 @@
+use crate::render::RenderSystem;
+use crate::render::command_buffer::{CommandBuffer, SecondaryRenderingRecorder};
+use crate::render::command_pool::CommandPool;
+use crate::render::pipeline::PipelineRenderCompatibility;
+use crate::render::render_target::FrameRenderInfo;
+use anyhow::anyhow;
+use ash::vk;
+use ash::vk::{
+    CommandBufferInheritanceRenderingInfo, CommandBufferLevel, CommandPoolCreateFlags,
+    RenderingFlags,
+};
+
+pub type SecondaryTask<'a> = Box<dyn FnOnce(&SecondaryRenderingRecorder) + Send + 'a>;
+
+/// What a secondary command buffer needs to know about the dynamic rendering scope it will be executed in.
+#[derive(Clone, Debug)]
+pub struct SecondaryInheritance {
+    pub flags: RenderingFlags,
+    pub view_mask: u32,
+    pub color_attachment_formats: Vec<vk::Format>,
+    pub depth_attachment_format: vk::Format,
+    pub stencil_attachment_format: vk::Format,
+    pub rasterization_samples: vk::SampleCountFlags,
+}
+
+struct ThreadCommandPool {
+    command_pool: CommandPool,
+    command_buffers: Vec<vk::CommandBuffer>,
+    used: usize,
+}
+
+/// Per-thread command pools for recording secondary command buffers in parallel, one set per frame in flight.
+///
+/// Call `begin_frame` with the renderer's current frame once that frame's fence has been waited on (i.e.
+/// from inside `PrimaryRenderer::render_to_target`), then `record` as many times as needed.
+pub struct ParallelRecorder {
+    pools: Vec<Vec<ThreadCommandPool>>,
+    thread_count: usize,
+    current_frame: usize,
+    device: ash::Device,
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    conditional_rendering: Option<ash::ext::conditional_rendering::Device>,
+}
+
+impl SecondaryInheritance {
+    pub fn from_frame_render_info(frame_render_info: &FrameRenderInfo) -> Self {
+        Self {
+            flags: RenderingFlags::empty(),
+            view_mask: 0,
+            color_attachment_formats: frame_render_info
+                .color_attachments
+                .iter()
+                .map(|a| a.format)
+                .collect(),
+            depth_attachment_format: vk::Format::UNDEFINED,
+            stencil_attachment_format: vk::Format::UNDEFINED,
+            rasterization_samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+
+    pub fn from_render_compatibility(
+        compatibility: &PipelineRenderCompatibility,
+        rasterization_samples: vk::SampleCountFlags,
+    ) -> Option<Self> {
+        match compatibility {
+            PipelineRenderCompatibility::RenderingInfo {
+                view_mask,
+                color_attachment_formats,
+                depth_attachment_format,
+                stencil_attachment_format,
+            } => Some(Self {
+                flags: RenderingFlags::empty(),
+                view_mask: *view_mask,
+                color_attachment_formats: color_attachment_formats.clone(),
+                depth_attachment_format: *depth_attachment_format,
+                stencil_attachment_format: *stencil_attachment_format,
+                rasterization_samples,
+            }),
+            PipelineRenderCompatibility::UseRenderPass(..) => None,
+        }
+    }
+
+    fn rendering_info(&self) -> CommandBufferInheritanceRenderingInfo<'_> {
+        CommandBufferInheritanceRenderingInfo::default()
+            .flags(self.flags & !RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS)
+            .view_mask(self.view_mask)
+            .color_attachment_formats(self.color_attachment_formats.as_slice())
+            .depth_attachment_format(self.depth_attachment_format)
+            .stencil_attachment_format(self.stencil_attachment_format)
+            .rasterization_samples(self.rasterization_samples)
+    }
+}
+
+impl ThreadCommandPool {
+    fn new(render_system: &RenderSystem) -> anyhow::Result<Self> {
+        Ok(Self {
+            command_pool: CommandPool::new(
+                render_system,
+                render_system.queue_families().main,
+                CommandPoolCreateFlags::TRANSIENT,
+            )?,
+            command_buffers: Vec::new(),
+            used: 0,
+        })
+    }
+
+    fn next_command_buffer(&mut self) -> anyhow::Result<vk::CommandBuffer> {
+        if self.used == self.command_buffers.len() {
+            let allocated = self
+                .command_pool
+                .allocate_handles(CommandBufferLevel::SECONDARY, 1)?;
+            self.command_buffers.extend(allocated);
+        }
+
+        self.used += 1;
+        Ok(self.command_buffers[self.used - 1])
+    }
+}
+
+impl ParallelRecorder {
+    /// `frames_in_flight` has to match the renderer's, see `PrimaryRenderer::frames_in_flight`.
+    pub fn new(
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+        thread_count: usize,
+    ) -> anyhow::Result<Self> {
+        let mut recorder = Self {
+            pools: Vec::new(),
+            thread_count: thread_count.max(1),
+            current_frame: 0,
+            device: render_system.device().clone(),
+            debug_utils: render_system.debug_utils().cloned(),
+            conditional_rendering: render_system.conditional_rendering().cloned(),
+        };
+        recorder.set_frames_in_flight(render_system, frames_in_flight)?;
+
+        Ok(recorder)
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.thread_count
+    }
+
+    /// Adds or removes per-frame pool sets. None of the removed pools' command buffers may be pending, so
+    /// call this after `PrimaryRenderer::set_frames_in_flight`.
+    pub fn set_frames_in_flight(
+        &mut self,
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+    ) -> anyhow::Result<()> {
+        self.pools.truncate(frames_in_flight);
+        for _ in self.pools.len()..frames_in_flight {
+            self.pools.push(
+                (0..self.thread_count)
+                    .map(|_| ThreadCommandPool::new(render_system))
+                    .try_collect()?,
+            );
+        }
+
+        self.current_frame = 0;
+        Ok(())
+    }
+
+    /// Resets every pool belonging to `frame`. The frame's previous submission must have completed.
+    pub fn begin_frame(&mut self, frame: usize) -> anyhow::Result<()> {
+        if frame >= self.pools.len() {
+            return Err(anyhow!("Frame index {} is out of range", frame));
+        }
+
+        self.current_frame = frame;
+        for pool in self.pools[frame].iter_mut() {
+            pool.command_pool.reset(false)?;
+            pool.used = 0;
+        }
+
+        Ok(())
+    }
+
+    /// Records each task into its own secondary command buffer, spreading the tasks across the worker
+    /// threads. The returned command buffers are in task order, ready for `execute_commands`.
+    pub fn record<'a>(
+        &mut self,
+        inheritance: &SecondaryInheritance,
+        tasks: Vec<SecondaryTask<'a>>,
+    ) -> anyhow::Result<Vec<vk::CommandBuffer>> {
+        let thread_count = self.thread_count();
+        let mut per_thread: Vec<Vec<(usize, SecondaryTask<'a>)>> =
+            (0..thread_count).map(|_| Vec::new()).collect();
+        for (index, task) in tasks.into_iter().enumerate() {
+            per_thread[index % thread_count].push((index, task));
+        }
+
+        let device = &self.device;
+        let debug_utils = &self.debug_utils;
+        let conditional_rendering = &self.conditional_rendering;
+        let recorded = std::thread::scope(|scope| {
+            let handles = self.pools[self.current_frame]
+                .iter_mut()
+                .zip(per_thread)
+                .filter(|(_, tasks)| !tasks.is_empty())
+                .map(|(pool, tasks)| {
+                    scope.spawn(move || -> anyhow::Result<Vec<(usize, vk::CommandBuffer)>> {
+                        let mut recorded = Vec::with_capacity(tasks.len());
+                        for (index, task) in tasks {
+                            let handle = pool.next_command_buffer()?;
+                            let mut command_buffer = CommandBuffer::wrap(device, handle)
+                                .with_debug_utils(debug_utils.clone())
+                                .with_conditional_rendering(conditional_rendering.clone());
+                            {
+                                let mut rendering_info = inheritance.rendering_info();
+                                let cmd = SecondaryRenderingRecorder::begin(
+                                    &mut command_buffer,
+                                    &mut rendering_info,
+                                )?;
+                                task(&cmd);
+                            }
+                            recorded.push((index, handle));
+                        }
+                        Ok(recorded)
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| {
+                    handle
+                        .join()
+                        .map_err(|_| anyhow!("Secondary command recording thread panicked"))?
+                })
+                .try_collect::<Vec<_>>()
+        })?;
+
+        let mut recorded = recorded.into_iter().flatten().collect::<Vec<_>>();
+        recorded.sort_by_key(|(index, _)| *index);
+        Ok(recorded.into_iter().map(|(_, handle)| handle).collect())
+    }
+}
@@ -1,22 +1,29 @@
 use crate::render::RenderSystem;
-use crate::render::descriptor::DescriptorSetLayout;
+use crate::render::descriptor::{DescriptorSetLayout, DescriptorSetLayoutDescription};
+use crate::render::reflection::ReflectedLayout;
+use crate::render::render_pass::RenderPass;
 use crate::render::shader::ShaderModule;
+use crate::render::validation::validate_graphics_pipeline;
 use anyhow::anyhow;
 use ash::vk;
 use ash::vk::{CullModeFlags, FrontFace, Offset2D, PipelineLayoutCreateFlags, PipelineLayoutCreateInfo, PolygonMode, Rect2D, SampleCountFlags, ShaderStageFlags, StructureType, Viewport};
 use std::ffi::CString;
 use std::marker::PhantomData;
 use std::ops::Range;
+use std::path::Path;
 use std::rc::Rc;
 
 pub struct PipelineLayout {
     pipeline_layout: vk::PipelineLayout,
+    descriptor_set_layouts: Vec<Rc<DescriptorSetLayout>>,
+    push_constant_ranges: Vec<(vk::ShaderStageFlags, Range<u32>)>,
     device: ash::Device,
 }
 
 pub struct PipelineLayoutDescription {
     pub push_constant_ranges: Vec<(vk::ShaderStageFlags, Range<u32>)>,
     pub descriptor_set_layouts: Vec<Rc<DescriptorSetLayout>>,
+    pub debug_name: Option<String>,
 }
 
 impl PipelineLayout {
@@ -44,7 +51,7 @@ impl PipelineLayout {
                 .map(|dsl| dsl.handle())
                 .collect::<Vec<_>>();
 
-            Ok(Self {
+            let pipeline_layout = Self {
                 pipeline_layout: device.create_pipeline_layout(
                     &PipelineLayoutCreateInfo {
                         s_type: StructureType::PIPELINE_LAYOUT_CREATE_INFO,
@@ -58,15 +65,72 @@ impl PipelineLayout {
                     },
                     None,
                 )?,
+                descriptor_set_layouts: description.descriptor_set_layouts.clone(),
+                push_constant_ranges: description.push_constant_ranges.clone(),
                 device,
-            })
+            };
+
+            if let Some(name) = description.debug_name.as_deref() {
+                pipeline_layout.set_debug_name(render_system, name);
+            }
+
+            Ok(pipeline_layout)
         }
     }
 
+    /// Creates a layout, and a descriptor set layout per set, declaring exactly what the shaders use.
+    /// Bindings and push constants shared between stages are merged, see `ReflectedLayout::merge`.
+    pub fn from_shaders(
+        render_system: &RenderSystem,
+        shaders: &[&ShaderModule],
+    ) -> anyhow::Result<Self> {
+        let reflections = shaders.iter().map(|shader| shader.reflection()).collect::<Vec<_>>();
+        let reflected = ReflectedLayout::merge(&reflections)?;
+
+        let descriptor_set_layouts = reflected
+            .sets
+            .into_iter()
+            .map(|bindings| {
+                DescriptorSetLayout::new(
+                    render_system,
+                    &DescriptorSetLayoutDescription {
+                        flags: vk::DescriptorSetLayoutCreateFlags::empty(),
+                        bindings,
+                        debug_name: None,
+                    },
+                )
+                .map(Rc::new)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        Self::new(
+            render_system,
+            &PipelineLayoutDescription {
+                push_constant_ranges: reflected.push_constant_ranges,
+                descriptor_set_layouts,
+                debug_name: None,
+            },
+        )
+    }
+
     #[inline]
     pub fn handle(&self) -> vk::PipelineLayout {
         self.pipeline_layout
     }
+
+    #[inline]
+    pub fn descriptor_set_layouts(&self) -> &[Rc<DescriptorSetLayout>] {
+        &self.descriptor_set_layouts
+    }
+
+    #[inline]
+    pub fn push_constant_ranges(&self) -> &[(vk::ShaderStageFlags, Range<u32>)] {
+        &self.push_constant_ranges
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.pipeline_layout, name);
+    }
 }
 
 impl Drop for PipelineLayout {
@@ -80,11 +144,15 @@ impl Drop for PipelineLayout {
 
 pub struct GraphicsPipeline {
     pipeline: ash::vk::Pipeline,
+    rendering_compatibility: PipelineRenderCompatibility,
+    debug_name: Option<String>,
     device: ash::Device,
 }
 
+#[derive(Clone)]
 pub enum PipelineRenderCompatibility {
-    UseRenderPass(vk::RenderPass, u32), // TODO: if we abstract render passes, replace this with the abstraction
+    /// A render pass and the index of the subpass the pipeline is used in.
+    UseRenderPass(Rc<RenderPass>, u32),
     RenderingInfo {
         view_mask: u32,
         color_attachment_formats: Vec<vk::Format>,
@@ -115,6 +183,64 @@ impl PipelineRenderCompatibility {
     }
 }
 
+impl std::fmt::Debug for PipelineRenderCompatibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UseRenderPass(render_pass, subpass) => f
+                .debug_tuple("UseRenderPass")
+                .field(&render_pass.handle())
+                .field(subpass)
+                .finish(),
+            Self::RenderingInfo {
+                view_mask,
+                color_attachment_formats,
+                depth_attachment_format,
+                stencil_attachment_format,
+            } => f
+                .debug_struct("RenderingInfo")
+                .field("view_mask", view_mask)
+                .field("color_attachment_formats", color_attachment_formats)
+                .field("depth_attachment_format", depth_attachment_format)
+                .field("stencil_attachment_format", stencil_attachment_format)
+                .finish(),
+        }
+    }
+}
+
+/// Render passes compare by identity, use `RenderPass::is_compatible_with` to check whether a pipeline
+/// can be used with a different one.
+impl PartialEq for PipelineRenderCompatibility {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::UseRenderPass(render_pass, subpass), Self::UseRenderPass(other_render_pass, other_subpass)) => {
+                Rc::ptr_eq(render_pass, other_render_pass) && subpass == other_subpass
+            }
+            (
+                Self::RenderingInfo {
+                    view_mask,
+                    color_attachment_formats,
+                    depth_attachment_format,
+                    stencil_attachment_format,
+                },
+                Self::RenderingInfo {
+                    view_mask: other_view_mask,
+                    color_attachment_formats: other_color_attachment_formats,
+                    depth_attachment_format: other_depth_attachment_format,
+                    stencil_attachment_format: other_stencil_attachment_format,
+                },
+            ) => {
+                view_mask == other_view_mask
+                    && color_attachment_formats == other_color_attachment_formats
+                    && depth_attachment_format == other_depth_attachment_format
+                    && stencil_attachment_format == other_stencil_attachment_format
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for PipelineRenderCompatibility {}
+
 pub struct VertexLayout {
     pub bindings: Vec<vk::VertexInputBindingDescription>,
     pub attributes: Vec<vk::VertexInputAttributeDescription>,
@@ -178,6 +304,8 @@ pub struct GraphicsPipelineDescription {
     pub depth_stencil: Option<DepthStencilDescription>,
     pub color_blending: ColorBlendingDescription,
     pub dynamic_states: Vec<vk::DynamicState>,
+
+    pub debug_name: Option<String>,
 }
 
 impl GraphicsPipeline {
@@ -185,6 +313,8 @@ impl GraphicsPipeline {
         render_system: &RenderSystem,
         description: &GraphicsPipelineDescription,
     ) -> anyhow::Result<Self> {
+        validate_graphics_pipeline(description)?;
+
         let vertex_description = vk::PipelineVertexInputStateCreateInfo::default()
             .vertex_binding_descriptions(description.vertex_layout.bindings.as_slice())
             .vertex_attribute_descriptions(description.vertex_layout.attributes.as_slice());
@@ -192,7 +322,7 @@ impl GraphicsPipeline {
         let (render_pass, subpass, mut rendering_state) = match &description.rendering_compatibility
         {
             PipelineRenderCompatibility::UseRenderPass(render_pass, subpass) => {
-                (render_pass.clone(), subpass.clone(), None)
+                (render_pass.handle(), *subpass, None)
             }
             PipelineRenderCompatibility::RenderingInfo {
                 view_mask,
@@ -321,23 +451,45 @@ impl GraphicsPipeline {
         }
 
         let device = render_system.device().clone();
-        unsafe {
-            Ok(Self {
+        let pipeline = unsafe {
+            Self {
                 pipeline: device
                     .create_graphics_pipelines(render_system.pipeline_cache(), &[create_info], None)
                     .map_err(|(_, e)| e)?
                     .first()
                     .cloned()
                     .ok_or(anyhow!("Failed to create graphics pipeline"))?,
+                rendering_compatibility: description.rendering_compatibility.clone(),
+                debug_name: description.debug_name.clone(),
                 device,
-            })
+            }
+        };
+
+        if let Some(name) = description.debug_name.as_deref() {
+            pipeline.set_debug_name(render_system, name);
         }
+
+        Ok(pipeline)
     }
 
     #[inline]
     pub fn handle(&self) -> vk::Pipeline {
         self.pipeline
     }
+
+    #[inline]
+    pub fn rendering_compatibility(&self) -> &PipelineRenderCompatibility {
+        &self.rendering_compatibility
+    }
+
+    #[inline]
+    pub fn debug_name(&self) -> Option<&str> {
+        self.debug_name.as_deref()
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.pipeline, name);
+    }
 }
 
 impl Drop for GraphicsPipeline {
@@ -441,4 +593,23 @@ pub fn standard_vertex_fragment_stages(vertex_shader: Rc<ShaderModule>, fragment
     ]
 }
 
+/// Loads each shader with `ShaderModule::load`, using the entry point of its inferred stage, e.g.
+/// `load_shader_stages(render_system, &["main.vert", "main.frag"])`.
+pub fn load_shader_stages(render_system: &RenderSystem, paths: &[impl AsRef<Path>]) -> anyhow::Result<Vec<(ShaderStageFlags, Rc<ShaderModule>, String)>> {
+    paths
+        .iter()
+        .map(|path| {
+            let (stage, module) = ShaderModule::load(render_system, path)?;
+            let entry_point = module
+                .reflection()
+                .entry_points
+                .iter()
+                .find(|entry_point| entry_point.stage == stage)
+                .map(|entry_point| entry_point.name.clone())
+                .ok_or_else(|| anyhow!("'{}' has no entry point for {:?}", path.as_ref().display(), stage))?;
+            Ok((stage, Rc::new(module), entry_point))
+        })
+        .collect()
+}
+
 
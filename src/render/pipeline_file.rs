@@ -0,0 +1,1121 @@
+use crate::render::RenderSystem;
+use crate::render::pipeline::{
+    ColorBlendingDescription, DepthStencilDescription, GraphicsPipeline,
+    GraphicsPipelineDescription, MultisamplingDescription, PipelineLayout,
+    PipelineRenderCompatibility, RasterizerDepthBias, RasterizerDescription, VertexLayout,
+};
+use crate::render::render_pass::RenderPass;
+use crate::render::shader::{ShaderCompileOptions, ShaderDefines, ShaderModule, normalize_path};
+use crate::render::shader_cache::{ShaderPermutation, ShaderPermutationCache};
+use anyhow::anyhow;
+use ash::vk;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A Vulkan enum that can be looked up by the name ash's `Debug` impl gives it, e.g. `"SRC_ALPHA"`.
+pub trait VkEnumName: Copy + Debug + Sized {
+    fn from_name(name: &str) -> Option<Self>;
+}
+
+/// A Vulkan bitmask that can be looked up by the names of its individual bits.
+pub trait VkFlagsName: Copy + Debug + Sized {
+    /// Named constants covering several bits, e.g. `FRONT_AND_BACK`, which ash's `Debug` impl never
+    /// prints on its own.
+    const COMBINED: &'static [(u32, &'static str)];
+
+    fn from_bits(bits: u32) -> Self;
+
+    fn to_bits(self) -> u32;
+}
+
+macro_rules! vk_enum_names {
+    ($($ty:ty => [$($range:expr),+ $(,)?]),* $(,)?) => {
+        $(impl VkEnumName for $ty {
+            fn from_name(name: &str) -> Option<Self> {
+                [$($range),+]
+                    .into_iter()
+                    .flatten()
+                    .map(<$ty>::from_raw)
+                    .find(|value| format!("{:?}", value) == name)
+            }
+        })*
+    };
+}
+
+vk_enum_names! {
+    vk::PolygonMode => [0..=2, 1000153000..=1000153000],
+    vk::FrontFace => [0..=1],
+    vk::CompareOp => [0..=7],
+    vk::StencilOp => [0..=7],
+    vk::LogicOp => [0..=15],
+    vk::BlendFactor => [0..=18],
+    vk::BlendOp => [0..=4, 1000148000..=1000148045],
+    vk::PrimitiveTopology => [0..=10],
+    vk::VertexInputRate => [0..=1],
+    vk::DynamicState => [
+        0..=8,
+        1000087000..=1000087000,
+        1000099000..=1000099002,
+        1000143000..=1000143000,
+        1000164004..=1000164004,
+        1000164006..=1000164006,
+        1000205000..=1000205001,
+        1000226000..=1000226000,
+        1000259000..=1000259000,
+        1000267000..=1000267011,
+        1000347000..=1000347000,
+        1000352000..=1000352000,
+        1000377000..=1000377004,
+        1000381000..=1000381000,
+        1000455002..=1000455032,
+        1000524000..=1000524000,
+    ],
+    vk::Format => [
+        0..=184,
+        1000054000..=1000054007,
+        1000066000..=1000066013,
+        1000156000..=1000156033,
+        1000330000..=1000330003,
+        1000340000..=1000340001,
+        1000464000..=1000464000,
+        1000470000..=1000470001,
+    ],
+}
+
+macro_rules! vk_flags_names {
+    ($($ty:ty => [$($combined:ident),* $(,)?]),* $(,)?) => {
+        $(impl VkFlagsName for $ty {
+            const COMBINED: &'static [(u32, &'static str)] =
+                &[$((<$ty>::$combined.as_raw(), stringify!($combined))),*];
+
+            #[inline]
+            fn from_bits(bits: u32) -> Self {
+                <$ty>::from_raw(bits)
+            }
+
+            #[inline]
+            fn to_bits(self) -> u32 {
+                self.as_raw()
+            }
+        })*
+    };
+}
+
+vk_flags_names! {
+    vk::CullModeFlags => [FRONT_AND_BACK],
+    vk::ColorComponentFlags => [],
+    vk::ShaderStageFlags => [ALL, ALL_GRAPHICS],
+}
+
+/// (De)serializes a Vulkan enum by name, e.g. `"TRIANGLE_LIST"`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VkEnum<T>(pub T);
+
+/// (De)serializes a Vulkan bitmask as its bit names separated by `|`, e.g. `"R | G | B | A"`. An empty
+/// string or `"NONE"` is an empty mask. Masks equal to a named combination such as `"ALL_GRAPHICS"` are
+/// written as that name, and such names are accepted alongside single bits.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct VkFlags<T>(pub T);
+
+impl<T: VkEnumName> Serialize for VkEnum<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format!("{:?}", self.0))
+    }
+}
+
+impl<'de, T: VkEnumName> Deserialize<'de> for VkEnum<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        T::from_name(name.trim()).map(Self).ok_or_else(|| {
+            D::Error::custom(format!(
+                "unknown {} '{}'",
+                std::any::type_name::<T>().rsplit("::").next().unwrap_or(""),
+                name
+            ))
+        })
+    }
+}
+
+impl<T: VkFlagsName> Serialize for VkFlags<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let bits = self.0.to_bits();
+        match T::COMBINED.iter().find(|&&(combined, _)| combined == bits) {
+            Some(&(_, name)) => serializer.serialize_str(name),
+            None => serializer.serialize_str(&format!("{:?}", self.0)),
+        }
+    }
+}
+
+impl<'de, T: VkFlagsName> Deserialize<'de> for VkFlags<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let names = String::deserialize(deserializer)?;
+        let mut bits = 0u32;
+        for name in names.split('|').map(str::trim) {
+            if name.is_empty() || name == "NONE" {
+                continue;
+            }
+            let bit = T::COMBINED
+                .iter()
+                .find(|&&(_, combined)| combined == name)
+                .map(|&(bits, _)| bits)
+                .or_else(|| {
+                    (0..u32::BITS)
+                        .map(|bit| 1u32 << bit)
+                        .find(|&bit| format!("{:?}", T::from_bits(bit)) == name)
+                })
+                .ok_or_else(|| {
+                    D::Error::custom(format!(
+                        "unknown {} bit '{}'",
+                        std::any::type_name::<T>().rsplit("::").next().unwrap_or(""),
+                        name
+                    ))
+                })?;
+            bits |= bit;
+        }
+        Ok(Self(T::from_bits(bits)))
+    }
+}
+
+/// Serializable mirror of `GraphicsPipelineDescription`. The layout, render pass and shader modules are
+/// referenced by name or path and resolved through a `PipelineRegistry`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GraphicsPipelineDef {
+    /// Name of a layout registered with `PipelineRegistry::register_layout`.
+    pub layout: String,
+    pub rendering: RenderingCompatibilityDef,
+    pub stages: Vec<ShaderStageDef>,
+    #[serde(default)]
+    pub vertex_layout: VertexLayoutDef,
+    #[serde(default = "default_topology")]
+    pub primitive_topology: VkEnum<vk::PrimitiveTopology>,
+    #[serde(default)]
+    pub allow_primitive_restart: bool,
+    #[serde(default)]
+    pub tessellator_patch_control_points: u32,
+    #[serde(default)]
+    pub viewports: Vec<ViewportDef>,
+    #[serde(default)]
+    pub rasterizer: RasterizerDef,
+    #[serde(default)]
+    pub multisampling: MultisamplingDef,
+    #[serde(default)]
+    pub depth_stencil: Option<DepthStencilDef>,
+    #[serde(default)]
+    pub color_blending: ColorBlendingDef,
+    #[serde(default)]
+    pub dynamic_states: Vec<VkEnum<vk::DynamicState>>,
+    #[serde(default)]
+    pub debug_name: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub enum RenderingCompatibilityDef {
+    /// Dynamic rendering with the given attachment formats.
+    Rendering {
+        #[serde(default)]
+        view_mask: u32,
+        #[serde(default)]
+        color_formats: Vec<VkEnum<vk::Format>>,
+        #[serde(default)]
+        depth_format: VkEnum<vk::Format>,
+        #[serde(default)]
+        stencil_format: VkEnum<vk::Format>,
+    },
+    /// Name of a render pass registered with `PipelineRegistry::register_render_pass`.
+    RenderPass {
+        name: String,
+        #[serde(default)]
+        subpass: u32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShaderStageDef {
+    pub stage: VkFlags<vk::ShaderStageFlags>,
+    /// Relative to the registry's base directory. The extension picks the language, see
+    /// `ShaderLanguage::from_path`: `.spv` files are loaded as is, `.hlsl` files are compiled as HLSL
+    /// and anything else as GLSL.
+    pub path: PathBuf,
+    #[serde(default = "default_entry_point")]
+    pub entry_point: String,
+    /// Preprocessor macros the shader is compiled with, on top of the registry's compile options. An
+    /// empty value defines the macro without one, like `#define NAME`.
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VertexLayoutDef {
+    #[serde(default)]
+    pub bindings: Vec<VertexBindingDef>,
+    #[serde(default)]
+    pub attributes: Vec<VertexAttributeDef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VertexBindingDef {
+    pub binding: u32,
+    pub stride: u32,
+    #[serde(default)]
+    pub input_rate: VkEnum<vk::VertexInputRate>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VertexAttributeDef {
+    pub location: u32,
+    #[serde(default)]
+    pub binding: u32,
+    pub format: VkEnum<vk::Format>,
+    pub offset: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ViewportDef {
+    #[serde(default)]
+    pub x: f32,
+    #[serde(default)]
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    #[serde(default)]
+    pub min_depth: f32,
+    #[serde(default = "default_max_depth")]
+    pub max_depth: f32,
+    /// Covers the whole viewport if omitted.
+    #[serde(default)]
+    pub scissor: Option<ScissorDef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScissorDef {
+    #[serde(default)]
+    pub x: i32,
+    #[serde(default)]
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RasterizerDef {
+    pub polygon_mode: VkEnum<vk::PolygonMode>,
+    pub cull_mode: VkFlags<vk::CullModeFlags>,
+    pub front_face: VkEnum<vk::FrontFace>,
+    pub clamp_depth: bool,
+    pub discard_output: bool,
+    pub depth_bias: Option<DepthBiasDef>,
+    pub line_width: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DepthBiasDef {
+    pub constant_factor: f32,
+    pub slope_factor: f32,
+    pub clamp: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MultisamplingDef {
+    /// Samples per pixel, a power of two.
+    pub samples: u32,
+    pub min_sample_shading: Option<f32>,
+    pub sample_mask: [u32; 2],
+    pub alpha_to_coverage: bool,
+    pub alpha_to_one: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DepthStencilDef {
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_compare_op: VkEnum<vk::CompareOp>,
+    pub depth_bounds_test: bool,
+    pub stencil_test: bool,
+    pub stencil_front: StencilOpStateDef,
+    pub stencil_back: StencilOpStateDef,
+    pub depth_bounds: [f32; 2],
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StencilOpStateDef {
+    pub fail_op: VkEnum<vk::StencilOp>,
+    pub pass_op: VkEnum<vk::StencilOp>,
+    pub depth_fail_op: VkEnum<vk::StencilOp>,
+    pub compare_op: VkEnum<vk::CompareOp>,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+    pub reference: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColorBlendingDef {
+    pub logic_op: Option<VkEnum<vk::LogicOp>>,
+    pub blend_constants: [f32; 4],
+    pub attachments: Vec<BlendAttachmentDef>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BlendAttachmentDef {
+    pub blend_enable: bool,
+    pub src_color_blend_factor: VkEnum<vk::BlendFactor>,
+    pub dst_color_blend_factor: VkEnum<vk::BlendFactor>,
+    pub color_blend_op: VkEnum<vk::BlendOp>,
+    pub src_alpha_blend_factor: VkEnum<vk::BlendFactor>,
+    pub dst_alpha_blend_factor: VkEnum<vk::BlendFactor>,
+    pub alpha_blend_op: VkEnum<vk::BlendOp>,
+    pub color_write_mask: VkFlags<vk::ColorComponentFlags>,
+}
+
+/// Resolves the names and paths in pipeline files. Layouts and render passes have to be registered up
+/// front; shader modules are loaded on first use and shared through a `ShaderPermutationCache` between
+/// pipelines using the same file with the same stage, entry point and defines.
+pub struct PipelineRegistry {
+    base_dir: PathBuf,
+    shaders: ShaderPermutationCache,
+    layouts: HashMap<String, Rc<PipelineLayout>>,
+    render_passes: HashMap<String, Rc<RenderPass>>,
+}
+
+fn default_topology() -> VkEnum<vk::PrimitiveTopology> {
+    VkEnum(vk::PrimitiveTopology::TRIANGLE_LIST)
+}
+
+fn default_entry_point() -> String {
+    "main".to_string()
+}
+
+fn default_max_depth() -> f32 {
+    1.0
+}
+
+impl Default for RasterizerDef {
+    fn default() -> Self {
+        let default = RasterizerDescription::default();
+        Self {
+            polygon_mode: VkEnum(default.polygon_mode),
+            cull_mode: VkFlags(default.cull_mode),
+            front_face: VkEnum(default.front_face),
+            clamp_depth: default.clamp_depth,
+            discard_output: default.discard_output,
+            depth_bias: None,
+            line_width: default.line_width,
+        }
+    }
+}
+
+impl Default for MultisamplingDef {
+    fn default() -> Self {
+        let default = MultisamplingDescription::default();
+        Self {
+            samples: default.rasterization_samples.as_raw(),
+            min_sample_shading: default.min_sample_shading,
+            sample_mask: default.sample_mask,
+            alpha_to_coverage: default.alpha_to_coverage,
+            alpha_to_one: default.alpha_to_one,
+        }
+    }
+}
+
+impl Default for DepthStencilDef {
+    fn default() -> Self {
+        Self {
+            depth_test: true,
+            depth_write: true,
+            depth_compare_op: VkEnum(vk::CompareOp::LESS),
+            depth_bounds_test: false,
+            stencil_test: false,
+            stencil_front: StencilOpStateDef::default(),
+            stencil_back: StencilOpStateDef::default(),
+            depth_bounds: [0.0, 1.0],
+        }
+    }
+}
+
+impl Default for StencilOpStateDef {
+    fn default() -> Self {
+        Self {
+            fail_op: VkEnum(vk::StencilOp::KEEP),
+            pass_op: VkEnum(vk::StencilOp::KEEP),
+            depth_fail_op: VkEnum(vk::StencilOp::KEEP),
+            compare_op: VkEnum(vk::CompareOp::ALWAYS),
+            compare_mask: 0xFF,
+            write_mask: 0xFF,
+            reference: 0,
+        }
+    }
+}
+
+impl Default for BlendAttachmentDef {
+    /// Blending disabled, writing every component.
+    fn default() -> Self {
+        Self {
+            blend_enable: false,
+            src_color_blend_factor: VkEnum(vk::BlendFactor::ONE),
+            dst_color_blend_factor: VkEnum(vk::BlendFactor::ZERO),
+            color_blend_op: VkEnum(vk::BlendOp::ADD),
+            src_alpha_blend_factor: VkEnum(vk::BlendFactor::ONE),
+            dst_alpha_blend_factor: VkEnum(vk::BlendFactor::ZERO),
+            alpha_blend_op: VkEnum(vk::BlendOp::ADD),
+            color_write_mask: VkFlags(vk::ColorComponentFlags::RGBA),
+        }
+    }
+}
+
+impl GraphicsPipelineDef {
+    pub fn from_ron(text: &str) -> anyhow::Result<Self> {
+        Ok(ron::from_str(text)?)
+    }
+
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(text)?)
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Picks the format from the extension: `.ron`, `.json` or `.toml`.
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match extension.to_ascii_lowercase().as_str() {
+            "ron" => Self::from_ron(&text),
+            "json" => Self::from_json(&text),
+            "toml" => Self::from_toml(&text),
+            _ => Err(anyhow!(
+                "Unknown pipeline file format '{}' of '{}'",
+                extension,
+                path.display()
+            )),
+        }
+        .map_err(|e| anyhow!("Failed to parse pipeline file '{}': {}", path.display(), e))
+    }
+
+    pub fn to_ron(&self) -> anyhow::Result<String> {
+        Ok(ron::ser::to_string_pretty(
+            self,
+            ron::ser::PrettyConfig::default(),
+        )?)
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn to_toml(&self) -> anyhow::Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+
+    /// Resolves the layout, render pass and shaders, loading any shader the registry doesn't have yet.
+    pub fn to_description(
+        &self,
+        render_system: &RenderSystem,
+        registry: &mut PipelineRegistry,
+    ) -> anyhow::Result<GraphicsPipelineDescription> {
+        let rendering_compatibility = match &self.rendering {
+            RenderingCompatibilityDef::Rendering {
+                view_mask,
+                color_formats,
+                depth_format,
+                stencil_format,
+            } => PipelineRenderCompatibility::RenderingInfo {
+                view_mask: *view_mask,
+                color_attachment_formats: color_formats.iter().map(|f| f.0).collect(),
+                depth_attachment_format: depth_format.0,
+                stencil_attachment_format: stencil_format.0,
+            },
+            RenderingCompatibilityDef::RenderPass { name, subpass } => {
+                PipelineRenderCompatibility::UseRenderPass(registry.render_pass(name)?, *subpass)
+            }
+        };
+
+        let shader_stages = self
+            .stages
+            .iter()
+            .map(|stage| {
+                Ok((
+                    stage.stage.0,
+                    registry.shader(render_system, &stage.permutation())?,
+                    stage.entry_point.clone(),
+                ))
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let samples = self.multisampling.samples;
+        if !samples.is_power_of_two() || samples > 64 {
+            return Err(anyhow!("Invalid sample count {}", samples));
+        }
+
+        Ok(GraphicsPipelineDescription {
+            layout: registry.layout(&self.layout)?,
+            rendering_compatibility,
+            shader_stages,
+            vertex_layout: VertexLayout {
+                bindings: self
+                    .vertex_layout
+                    .bindings
+                    .iter()
+                    .map(|b| vk::VertexInputBindingDescription {
+                        binding: b.binding,
+                        stride: b.stride,
+                        input_rate: b.input_rate.0,
+                    })
+                    .collect(),
+                attributes: self
+                    .vertex_layout
+                    .attributes
+                    .iter()
+                    .map(|a| vk::VertexInputAttributeDescription {
+                        location: a.location,
+                        binding: a.binding,
+                        format: a.format.0,
+                        offset: a.offset,
+                    })
+                    .collect(),
+            },
+            primitive_topology: self.primitive_topology.0,
+            allow_primitive_restart: self.allow_primitive_restart,
+            tessellator_patch_control_points: self.tessellator_patch_control_points,
+            viewports: self.viewports.iter().map(ViewportDef::to_vk).collect(),
+            rasterizer: RasterizerDescription {
+                polygon_mode: self.rasterizer.polygon_mode.0,
+                cull_mode: self.rasterizer.cull_mode.0,
+                front_face: self.rasterizer.front_face.0,
+                clamp_depth: self.rasterizer.clamp_depth,
+                discard_output: self.rasterizer.discard_output,
+                depth_bias: self
+                    .rasterizer
+                    .depth_bias
+                    .as_ref()
+                    .map(|bias| RasterizerDepthBias {
+                        constant_factor: bias.constant_factor,
+                        slope_facator: bias.slope_factor,
+                        clamp: bias.clamp,
+                    }),
+                line_width: self.rasterizer.line_width,
+            },
+            multisampling: MultisamplingDescription {
+                rasterization_samples: vk::SampleCountFlags::from_raw(samples),
+                min_sample_shading: self.multisampling.min_sample_shading,
+                sample_mask: self.multisampling.sample_mask,
+                alpha_to_coverage: self.multisampling.alpha_to_coverage,
+                alpha_to_one: self.multisampling.alpha_to_one,
+            },
+            depth_stencil: self.depth_stencil.as_ref().map(|depth_stencil| {
+                DepthStencilDescription {
+                    depth_test: depth_stencil.depth_test,
+                    depth_write: depth_stencil.depth_write,
+                    depth_compare_op: depth_stencil.depth_compare_op.0,
+                    depth_bounds_test: depth_stencil.depth_bounds_test,
+                    stencil_test: depth_stencil.stencil_test,
+                    stencil_front: depth_stencil.stencil_front.to_vk(),
+                    stencil_back: depth_stencil.stencil_back.to_vk(),
+                    depth_bounds: depth_stencil.depth_bounds[0]..depth_stencil.depth_bounds[1],
+                }
+            }),
+            color_blending: ColorBlendingDescription {
+                logic_op: self.color_blending.logic_op.map(|op| op.0),
+                blend_constants: self.color_blending.blend_constants,
+                attachments: self
+                    .color_blending
+                    .attachments
+                    .iter()
+                    .map(BlendAttachmentDef::to_vk)
+                    .collect(),
+            },
+            dynamic_states: self.dynamic_states.iter().map(|s| s.0).collect(),
+            debug_name: self.debug_name.clone(),
+        })
+    }
+}
+
+impl ShaderStageDef {
+    /// The variant of the shader this stage uses, with its path still relative to the registry's base
+    /// directory.
+    pub fn permutation(&self) -> ShaderPermutation {
+        let defines = self
+            .defines
+            .iter()
+            .map(|(name, value)| (name.clone(), Some(value.clone()).filter(|v| !v.is_empty())))
+            .collect::<ShaderDefines>();
+        ShaderPermutation {
+            path: self.path.clone(),
+            defines,
+            stage: self.stage.0,
+            entry_point: self.entry_point.clone(),
+        }
+    }
+}
+
+impl ViewportDef {
+    fn to_vk(&self) -> (vk::Viewport, vk::Rect2D) {
+        let scissor = self.scissor.as_ref().map_or_else(
+            || vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: self.x as i32,
+                    y: self.y as i32,
+                },
+                extent: vk::Extent2D {
+                    width: self.width as u32,
+                    height: self.height as u32,
+                },
+            },
+            |scissor| vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: scissor.x,
+                    y: scissor.y,
+                },
+                extent: vk::Extent2D {
+                    width: scissor.width,
+                    height: scissor.height,
+                },
+            },
+        );
+
+        (
+            vk::Viewport {
+                x: self.x,
+                y: self.y,
+                width: self.width,
+                height: self.height,
+                min_depth: self.min_depth,
+                max_depth: self.max_depth,
+            },
+            scissor,
+        )
+    }
+}
+
+impl StencilOpStateDef {
+    fn to_vk(&self) -> vk::StencilOpState {
+        vk::StencilOpState {
+            fail_op: self.fail_op.0,
+            pass_op: self.pass_op.0,
+            depth_fail_op: self.depth_fail_op.0,
+            compare_op: self.compare_op.0,
+            compare_mask: self.compare_mask,
+            write_mask: self.write_mask,
+            reference: self.reference,
+        }
+    }
+}
+
+impl BlendAttachmentDef {
+    fn to_vk(&self) -> vk::PipelineColorBlendAttachmentState {
+        vk::PipelineColorBlendAttachmentState {
+            blend_enable: self.blend_enable.into(),
+            src_color_blend_factor: self.src_color_blend_factor.0,
+            dst_color_blend_factor: self.dst_color_blend_factor.0,
+            color_blend_op: self.color_blend_op.0,
+            src_alpha_blend_factor: self.src_alpha_blend_factor.0,
+            dst_alpha_blend_factor: self.dst_alpha_blend_factor.0,
+            alpha_blend_op: self.alpha_blend_op.0,
+            color_write_mask: self.color_write_mask.0,
+        }
+    }
+}
+
+impl PipelineRegistry {
+    /// Shader paths in pipeline files are relative to `base_dir`.
+    pub fn new(base_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            base_dir: base_dir.into(),
+            shaders: ShaderPermutationCache::new(ShaderCompileOptions::default()),
+            layouts: HashMap::new(),
+            render_passes: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn base_dir(&self) -> &Path {
+        &self.base_dir
+    }
+
+    /// Drops every cached shader module, since their paths resolve differently afterwards.
+    pub fn set_base_dir(&mut self, base_dir: impl Into<PathBuf>) {
+        self.base_dir = base_dir.into();
+        self.shaders.clear();
+    }
+
+    #[inline]
+    pub fn compile_options(&self) -> &ShaderCompileOptions {
+        self.shaders.options()
+    }
+
+    /// Used for shaders loaded afterwards. Drops every cached shader module, so they're compiled with the
+    /// new options too.
+    pub fn set_compile_options(&mut self, compile_options: ShaderCompileOptions) {
+        self.shaders.set_options(compile_options);
+    }
+
+    /// The cache holding every shader module loaded through the registry, keyed by resolved permutations.
+    #[inline]
+    pub fn shader_cache(&self) -> &ShaderPermutationCache {
+        &self.shaders
+    }
+
+    /// The path a pipeline file's shader path refers to. It's canonicalized if it exists, and otherwise
+    /// has `.` and `..` resolved, so every spelling of a path maps to the same cached shader.
+    pub fn resolve(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = self.base_dir.join(path.as_ref());
+        std::fs::canonicalize(&path).unwrap_or_else(|_| normalize_path(&path))
+    }
+
+    /// `permutation` with its path resolved, the way it's keyed in the shader cache.
+    pub fn resolve_permutation(&self, permutation: &ShaderPermutation) -> ShaderPermutation {
+        ShaderPermutation {
+            path: self.resolve(&permutation.path),
+            ..permutation.clone()
+        }
+    }
+
+    pub fn register_layout(&mut self, name: impl Into<String>, layout: Rc<PipelineLayout>) {
+        self.layouts.insert(name.into(), layout);
+    }
+
+    pub fn register_render_pass(&mut self, name: impl Into<String>, render_pass: Rc<RenderPass>) {
+        self.render_passes.insert(name.into(), render_pass);
+    }
+
+    /// Makes pipeline files referring to `permutation` use `shader` instead of loading it.
+    pub fn register_shader(&mut self, permutation: &ShaderPermutation, shader: Rc<ShaderModule>) {
+        self.shaders
+            .insert(self.resolve_permutation(permutation), shader);
+    }
+
+    pub fn layout(&self, name: &str) -> anyhow::Result<Rc<PipelineLayout>> {
+        self.layouts
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("No pipeline layout named '{}' is registered", name))
+    }
+
+    pub fn render_pass(&self, name: &str) -> anyhow::Result<Rc<RenderPass>> {
+        self.render_passes
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("No render pass named '{}' is registered", name))
+    }
+
+    /// Returns the variant of the shader described by `permutation`, whose path is relative to the base
+    /// directory, loading it if it hasn't been loaded yet.
+    pub fn shader(
+        &mut self,
+        render_system: &RenderSystem,
+        permutation: &ShaderPermutation,
+    ) -> anyhow::Result<Rc<ShaderModule>> {
+        let permutation = self.resolve_permutation(permutation);
+        self.shaders.get(render_system, &permutation)
+    }
+
+    /// Loads the variant of the shader described by `permutation` again. The cached module is only
+    /// replaced if loading succeeds, so pipelines built afterwards keep using the old one on a compile
+    /// error.
+    pub fn reload_shader(
+        &mut self,
+        render_system: &RenderSystem,
+        permutation: &ShaderPermutation,
+    ) -> anyhow::Result<Rc<ShaderModule>> {
+        let permutation = self.resolve_permutation(permutation);
+        self.shaders.reload(render_system, &permutation)
+    }
+
+    /// Drops every cached shader module, so the next pipeline referring to them reloads them.
+    pub fn clear_shaders(&mut self) {
+        self.shaders.clear();
+    }
+
+    pub fn load_graphics_pipeline(
+        &mut self,
+        render_system: &RenderSystem,
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<GraphicsPipeline> {
+        let path = path.as_ref();
+        let mut description =
+            GraphicsPipelineDef::load(path)?.to_description(render_system, self)?;
+        if description.debug_name.is_none() {
+            description.debug_name = Some(path.to_string_lossy().into_owned());
+        }
+        GraphicsPipeline::new(render_system, &description)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline() -> GraphicsPipelineDef {
+        GraphicsPipelineDef {
+            layout: "lit".to_string(),
+            rendering: RenderingCompatibilityDef::Rendering {
+                view_mask: 0,
+                color_formats: vec![VkEnum(vk::Format::B8G8R8A8_SRGB)],
+                depth_format: VkEnum(vk::Format::D32_SFLOAT),
+                stencil_format: VkEnum(vk::Format::UNDEFINED),
+            },
+            stages: vec![
+                ShaderStageDef {
+                    stage: VkFlags(vk::ShaderStageFlags::VERTEX),
+                    path: PathBuf::from("shaders/lit.vert"),
+                    entry_point: "main".to_string(),
+                    defines: BTreeMap::new(),
+                },
+                ShaderStageDef {
+                    stage: VkFlags(vk::ShaderStageFlags::FRAGMENT),
+                    path: PathBuf::from("shaders/lit.frag"),
+                    entry_point: "shade".to_string(),
+                    defines: BTreeMap::from([
+                        ("SHADOWS".to_string(), String::new()),
+                        ("SAMPLES".to_string(), "4".to_string()),
+                    ]),
+                },
+            ],
+            vertex_layout: VertexLayoutDef {
+                bindings: vec![VertexBindingDef {
+                    binding: 0,
+                    stride: 32,
+                    input_rate: VkEnum(vk::VertexInputRate::VERTEX),
+                }],
+                attributes: vec![
+                    VertexAttributeDef {
+                        location: 0,
+                        binding: 0,
+                        format: VkEnum(vk::Format::R32G32B32_SFLOAT),
+                        offset: 0,
+                    },
+                    VertexAttributeDef {
+                        location: 1,
+                        binding: 0,
+                        format: VkEnum(vk::Format::R32G32_SFLOAT),
+                        offset: 24,
+                    },
+                ],
+            },
+            primitive_topology: VkEnum(vk::PrimitiveTopology::TRIANGLE_STRIP),
+            allow_primitive_restart: true,
+            tessellator_patch_control_points: 0,
+            viewports: vec![ViewportDef {
+                x: 0.0,
+                y: 0.0,
+                width: 1280.0,
+                height: 720.0,
+                min_depth: 0.0,
+                max_depth: 1.0,
+                scissor: Some(ScissorDef {
+                    x: 16,
+                    y: 16,
+                    width: 1248,
+                    height: 688,
+                }),
+            }],
+            rasterizer: RasterizerDef {
+                polygon_mode: VkEnum(vk::PolygonMode::LINE),
+                cull_mode: VkFlags(vk::CullModeFlags::FRONT_AND_BACK),
+                front_face: VkEnum(vk::FrontFace::CLOCKWISE),
+                clamp_depth: true,
+                discard_output: false,
+                depth_bias: Some(DepthBiasDef {
+                    constant_factor: 1.25,
+                    slope_factor: 1.75,
+                    clamp: 0.5,
+                }),
+                line_width: 2.0,
+            },
+            multisampling: MultisamplingDef {
+                samples: 4,
+                min_sample_shading: Some(0.5),
+                sample_mask: [0xFFFF_FFFF, 0],
+                alpha_to_coverage: true,
+                alpha_to_one: false,
+            },
+            depth_stencil: Some(DepthStencilDef {
+                depth_compare_op: VkEnum(vk::CompareOp::GREATER_OR_EQUAL),
+                stencil_test: true,
+                stencil_front: StencilOpStateDef {
+                    pass_op: VkEnum(vk::StencilOp::REPLACE),
+                    reference: 1,
+                    ..StencilOpStateDef::default()
+                },
+                ..DepthStencilDef::default()
+            }),
+            color_blending: ColorBlendingDef {
+                logic_op: None,
+                blend_constants: [0.0, 0.25, 0.5, 1.0],
+                attachments: vec![BlendAttachmentDef {
+                    blend_enable: true,
+                    src_color_blend_factor: VkEnum(vk::BlendFactor::SRC_ALPHA),
+                    dst_color_blend_factor: VkEnum(vk::BlendFactor::ONE_MINUS_SRC_ALPHA),
+                    color_blend_op: VkEnum(vk::BlendOp::MULTIPLY_EXT),
+                    color_write_mask: VkFlags(
+                        vk::ColorComponentFlags::R
+                            | vk::ColorComponentFlags::G
+                            | vk::ColorComponentFlags::B,
+                    ),
+                    ..BlendAttachmentDef::default()
+                }],
+            },
+            dynamic_states: vec![
+                VkEnum(vk::DynamicState::VIEWPORT),
+                VkEnum(vk::DynamicState::LINE_STIPPLE_KHR),
+                VkEnum(vk::DynamicState::COLOR_WRITE_ENABLE_EXT),
+                VkEnum(vk::DynamicState::COLOR_BLEND_EQUATION_EXT),
+            ],
+            debug_name: Some("lit".to_string()),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_every_format() {
+        let pipeline = pipeline();
+
+        let ron = pipeline.to_ron().unwrap();
+        assert_eq!(
+            GraphicsPipelineDef::from_ron(&ron).unwrap(),
+            pipeline,
+            "{}",
+            ron
+        );
+
+        let json = pipeline.to_json().unwrap();
+        assert_eq!(
+            GraphicsPipelineDef::from_json(&json).unwrap(),
+            pipeline,
+            "{}",
+            json
+        );
+
+        let toml = pipeline.to_toml().unwrap();
+        assert_eq!(
+            GraphicsPipelineDef::from_toml(&toml).unwrap(),
+            pipeline,
+            "{}",
+            toml
+        );
+    }
+
+    #[test]
+    fn stages_request_permutations_with_their_defines() {
+        let pipeline = pipeline();
+        let permutation = pipeline.stages[1].permutation();
+        assert_eq!(
+            permutation,
+            ShaderPermutation::new("shaders/lit.frag", vk::ShaderStageFlags::FRAGMENT)
+                .with_define("SAMPLES", Some("4"))
+                .with_define("SHADOWS", None)
+                .with_entry_point("shade")
+        );
+
+        let registry = PipelineRegistry::new("res");
+        assert_eq!(
+            registry.resolve_permutation(&permutation).path,
+            Path::new("res/shaders/lit.frag")
+        );
+    }
+
+    #[test]
+    fn resolves_every_spelling_of_a_shader_path_alike() {
+        let dir = std::env::temp_dir().join(format!("vkism-registry-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("pipelines")).unwrap();
+        std::fs::create_dir_all(dir.join("shaders")).unwrap();
+        std::fs::write(dir.join("shaders/lit.frag"), "").unwrap();
+
+        let registry = PipelineRegistry::new(dir.join("pipelines"));
+        let permutation =
+            ShaderPermutation::new("../shaders/lit.frag", vk::ShaderStageFlags::FRAGMENT);
+        let resolved = registry.resolve_permutation(&permutation);
+        assert_eq!(
+            resolved.path,
+            std::fs::canonicalize(dir.join("shaders/lit.frag")).unwrap()
+        );
+        // the hot reloader reloads shaders by their resolved permutation
+        assert_eq!(registry.resolve_permutation(&resolved), resolved);
+
+        // paths that don't exist (yet) are still normalized
+        assert_eq!(
+            registry.resolve("../shaders/./missing.frag"),
+            normalize_path(&dir.join("shaders/missing.frag"))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_combined_flag_names() {
+        let parse = |text: &str| {
+            serde_json::from_str::<VkFlags<vk::ShaderStageFlags>>(&format!("\"{}\"", text))
+                .unwrap()
+                .0
+        };
+        assert_eq!(parse("ALL_GRAPHICS"), vk::ShaderStageFlags::ALL_GRAPHICS);
+        assert_eq!(parse("ALL"), vk::ShaderStageFlags::ALL);
+        assert_eq!(
+            parse("COMPUTE | ALL_GRAPHICS"),
+            vk::ShaderStageFlags::COMPUTE | vk::ShaderStageFlags::ALL_GRAPHICS
+        );
+        assert_eq!(parse(""), vk::ShaderStageFlags::empty());
+
+        let cull_mode: VkFlags<vk::CullModeFlags> =
+            serde_json::from_str("\"FRONT_AND_BACK\"").unwrap();
+        assert_eq!(cull_mode.0, vk::CullModeFlags::FRONT_AND_BACK);
+        assert_eq!(
+            serde_json::to_string(&cull_mode).unwrap(),
+            "\"FRONT_AND_BACK\""
+        );
+        assert_eq!(
+            serde_json::to_string(&VkFlags(vk::ShaderStageFlags::ALL)).unwrap(),
+            "\"ALL\""
+        );
+        assert!(serde_json::from_str::<VkFlags<vk::CullModeFlags>>("\"SIDEWAYS\"").is_err());
+    }
+
+    #[test]
+    fn parses_extension_enum_names() {
+        for name in [
+            "LINE_STIPPLE_KHR",
+            "LINE_STIPPLE_ENABLE_EXT",
+            "LINE_RASTERIZATION_MODE_EXT",
+            "COLOR_WRITE_ENABLE_EXT",
+            "VERTEX_INPUT_EXT",
+            "FRAGMENT_SHADING_RATE_KHR",
+            "ATTACHMENT_FEEDBACK_LOOP_ENABLE_EXT",
+        ] {
+            let state = vk::DynamicState::from_name(name);
+            assert_eq!(
+                state.map(|state| format!("{:?}", state)).as_deref(),
+                Some(name)
+            );
+        }
+        assert_eq!(
+            vk::PolygonMode::from_name("FILL_RECTANGLE_NV"),
+            Some(vk::PolygonMode::FILL_RECTANGLE_NV)
+        );
+        assert_eq!(
+            vk::BlendOp::from_name("BLUE_EXT"),
+            Some(vk::BlendOp::BLUE_EXT)
+        );
+        assert_eq!(
+            vk::Format::from_name("A8_UNORM_KHR"),
+            Some(vk::Format::A8_UNORM_KHR)
+        );
+    }
+}
@@ -1,76 +1,377 @@
 use crate::render::RenderSystem;
-use crate::render::command_buffer::{CommandBuffer, CommandRecorder, ImageTransition};
-use crate::render::render_target::{
-    FrameRenderAttachmentImageStateExternal, FrameRenderInfo, FrameSyncInfo, RenderTarget,
-    RenderTargetExt,
-};
+use crate::render::command_buffer::{CommandBuffer, CommandRecorder, GenericCommandRecorder};
+use crate::render::command_pool::CommandPool;
+use crate::render::frame_arena::FrameArena;
+use crate::render::profiler::GpuProfiler;
+use crate::render::render_graph::{RenderGraph, TransientResourcePool};
+use crate::render::render_target::{FrameRenderInfo, FrameSyncInfo, RenderTarget, RenderTargetExt};
+use crate::render::resource::format_aspect;
+use crate::render::resource_state::{ResourceState, ResourceStateTracker, ResourceUsage};
+use crate::render::validation::AttachmentFormats;
+use anyhow::anyhow;
 use ash::vk::{self, PipelineStageFlags};
 use ash::vk::{
-    AccessFlags, AccessFlags2, AttachmentLoadOp, AttachmentStoreOp, ClearValue,
-    CommandBufferBeginInfo, CommandBufferUsageFlags, ImageAspectFlags, ImageLayout,
+    AttachmentLoadOp, AttachmentStoreOp, ClearValue, CommandBufferBeginInfo,
+    CommandBufferUsageFlags, CommandPoolCreateFlags, ImageAspectFlags, ImageLayout,
     ImageSubresourceRange, PipelineStageFlags2, Rect2D, RenderingAttachmentInfo, RenderingInfo,
 };
+use log::error;
+use std::rc::Rc;
 
 use super::command_buffer::DynamicRenderingRecorder;
 
-pub const MAX_FRAMES_IN_FLIGHT: usize = 2;
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+const COLOR_SUBRESOURCE_RANGE: ImageSubresourceRange = ImageSubresourceRange {
+    aspect_mask: ImageAspectFlags::COLOR,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+/// A clear value together with the kind of attachment it clears.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AttachmentClearValue {
+    Float([f32; 4]),
+    Int([i32; 4]),
+    Uint([u32; 4]),
+    DepthStencil { depth: f32, stencil: u32 },
+}
+
+/// How one of the target's attachments is loaded at the start of the frame and stored at its end.
+/// `clear_value` is only used with `AttachmentLoadOp::CLEAR`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct AttachmentOps {
+    pub load_op: AttachmentLoadOp,
+    pub store_op: AttachmentStoreOp,
+    pub clear_value: AttachmentClearValue,
+}
+
+/// How `PrimaryRenderer` begins rendering to its target each frame.
+#[derive(Clone, Debug)]
+pub struct FrameRenderSettings {
+    pub render_area: Option<vk::Rect2D>,
+    pub rendering_flags: vk::RenderingFlags,
+    /// Indexed like the target's color attachments; attachments without an entry are loaded and stored.
+    pub color_attachments: Vec<AttachmentOps>,
+    /// Used when the target has a depth/stencil attachment. Cleared to a depth of 1 by default.
+    pub depth_stencil_attachment: AttachmentOps,
+}
+
+impl From<AttachmentClearValue> for ClearValue {
+    fn from(value: AttachmentClearValue) -> Self {
+        match value {
+            AttachmentClearValue::Float(float32) => ClearValue {
+                color: vk::ClearColorValue { float32 },
+            },
+            AttachmentClearValue::Int(int32) => ClearValue {
+                color: vk::ClearColorValue { int32 },
+            },
+            AttachmentClearValue::Uint(uint32) => ClearValue {
+                color: vk::ClearColorValue { uint32 },
+            },
+            AttachmentClearValue::DepthStencil { depth, stencil } => ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue { depth, stencil },
+            },
+        }
+    }
+}
+
+impl AttachmentOps {
+    pub fn load() -> Self {
+        Self::default()
+    }
 
-pub type FrameSet<T> = [T; MAX_FRAMES_IN_FLIGHT];
+    pub fn clear(value: AttachmentClearValue) -> Self {
+        Self {
+            load_op: AttachmentLoadOp::CLEAR,
+            clear_value: value,
+            ..Self::default()
+        }
+    }
+
+    pub fn dont_care() -> Self {
+        Self {
+            load_op: AttachmentLoadOp::DONT_CARE,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_store_op(self, store_op: AttachmentStoreOp) -> Self {
+        Self { store_op, ..self }
+    }
+}
+
+impl Default for AttachmentOps {
+    fn default() -> Self {
+        Self {
+            load_op: AttachmentLoadOp::LOAD,
+            store_op: AttachmentStoreOp::STORE,
+            clear_value: AttachmentClearValue::Float([0.0; 4]),
+        }
+    }
+}
+
+impl Default for FrameRenderSettings {
+    fn default() -> Self {
+        Self {
+            render_area: None,
+            rendering_flags: vk::RenderingFlags::empty(),
+            color_attachments: Vec::new(),
+            depth_stencil_attachment: AttachmentOps::clear(AttachmentClearValue::DepthStencil {
+                depth: 1.0,
+                stencil: 0,
+            }),
+        }
+    }
+}
+
+impl FrameRenderSettings {
+    pub fn color_attachment(&self, index: usize) -> AttachmentOps {
+        self.color_attachments
+            .get(index)
+            .copied()
+            .unwrap_or_default()
+    }
+}
 
 pub struct PrimaryRenderer {
     device: ash::Device,
     graphics_queue: vk::Queue,
-    frame_sync_infos: FrameSet<FrameSyncInfo>,
-    fences: FrameSet<vk::Fence>,
-    command_buffers: FrameSet<CommandBuffer>,
+    frames: Vec<FrameResources>,
 
-    render_area: Option<vk::Rect2D>,
-    color_clear_values: Vec<Option<[f32; 4]>>,
+    settings: FrameRenderSettings,
+    resource_states: ResourceStateTracker,
+    profiler: Option<Rc<GpuProfiler>>,
+    frame_arena: Option<Rc<FrameArena>>,
 
     current_frame: usize,
 }
 
+/// Everything `PrimaryRenderer` keeps once per frame in flight.
+struct FrameResources {
+    sync_info: FrameSyncInfo,
+    fence: vk::Fence,
+    // reset wholesale once the frame's fence has signaled
+    command_pool: Rc<CommandPool>,
+    command_buffer: CommandBuffer,
+    transient_pool: TransientResourcePool,
+    device: ash::Device,
+}
+
+impl FrameResources {
+    fn new(render_system: &RenderSystem, index: usize) -> anyhow::Result<Self> {
+        let command_pool = Rc::new(CommandPool::new(
+            render_system,
+            render_system.queue_families().main,
+            CommandPoolCreateFlags::TRANSIENT,
+        )?);
+        command_pool.set_debug_name(render_system, &format!("frame {} command pool", index));
+
+        let command_buffer = command_pool
+            .allocate_command_buffers(vk::CommandBufferLevel::PRIMARY, 1)?
+            .pop()
+            .ok_or(anyhow!("Command buffer allocation failed"))?;
+        command_buffer.set_debug_name(render_system, &format!("frame {} command buffer", index));
+
+        Ok(Self {
+            sync_info: FrameSyncInfo::new(render_system)?,
+            fence: render_system.create_fence(true)?,
+            command_pool,
+            command_buffer,
+            transient_pool: TransientResourcePool::new(render_system),
+            device: render_system.device().clone(),
+        })
+    }
+}
+
+impl Drop for FrameResources {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .destroy_semaphore(self.sync_info.image_available, None);
+            self.device
+                .destroy_semaphore(self.sync_info.render_finished, None);
+            self.device.destroy_fence(self.fence, None);
+        }
+    }
+}
+
 impl PrimaryRenderer {
-    pub fn new(render_system: &RenderSystem) -> anyhow::Result<PrimaryRenderer> {
-        let frame_sync_infos = std::array::try_from_fn(|_| FrameSyncInfo::new(render_system))?;
-        let fences = std::array::try_from_fn(|_| render_system.create_fence(true))?;
-        let command_buffers = render_system
-            .create_command_buffers::<MAX_FRAMES_IN_FLIGHT>(vk::CommandBufferLevel::PRIMARY)?;
+    /// `frames_in_flight` is how many frames the CPU may record ahead of the GPU. 1 gives the lowest
+    /// latency, 3 the most throughput; `DEFAULT_FRAMES_IN_FLIGHT` is a good middle ground.
+    pub fn new(
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+    ) -> anyhow::Result<PrimaryRenderer> {
+        if frames_in_flight == 0 {
+            return Err(anyhow!("At least one frame in flight is required"));
+        }
 
         Ok(Self {
             device: render_system.device().clone(),
             graphics_queue: render_system.queues().main,
-            frame_sync_infos,
-            fences,
-            command_buffers,
-            render_area: None,
-            color_clear_values: Vec::new(),
+            frames: (0..frames_in_flight)
+                .map(|index| FrameResources::new(render_system, index))
+                .try_collect()?,
+            settings: FrameRenderSettings::default(),
+            resource_states: ResourceStateTracker::new(),
+            profiler: None,
+            frame_arena: None,
             current_frame: 0,
         })
     }
 
     pub fn begin_frame(&mut self) {
-        let fence = self.fences[self.current_frame];
+        let frame = &self.frames[self.current_frame];
         unsafe {
-            let _ = self.device.wait_for_fences(&[fence], true, u64::MAX);
-            let _ = self.device.reset_fences(&[fence]);
+            let _ = self.device.wait_for_fences(&[frame.fence], true, u64::MAX);
+            let _ = self.device.reset_fences(&[frame.fence]);
+        }
+
+        if let Err(e) = frame.command_pool.reset(false) {
+            error!("Failed to reset frame command pool: {}", e);
+        }
+        if let Some(frame_arena) = self.frame_arena.as_ref() {
+            frame_arena.begin_frame(self.current_frame);
         }
     }
 
     pub fn end_frame(&mut self) {
-        self.current_frame = (self.current_frame + 1) % MAX_FRAMES_IN_FLIGHT;
+        self.current_frame = (self.current_frame + 1) % self.frames.len();
     }
 
+    #[inline]
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Waits for the device to go idle, then creates or destroys per-frame resources to match
+    /// `frames_in_flight`. An attached profiler and frame arena are resized along with them.
+    pub fn set_frames_in_flight(
+        &mut self,
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+    ) -> anyhow::Result<()> {
+        if frames_in_flight == 0 {
+            return Err(anyhow!("At least one frame in flight is required"));
+        }
+        if frames_in_flight == self.frames.len() {
+            return Ok(());
+        }
+
+        unsafe { self.device.device_wait_idle() }?;
+
+        // the dropped frames' transient resources are destroyed with them
+        for frame in self.frames.iter().skip(frames_in_flight) {
+            frame
+                .transient_pool
+                .forget_states(&mut self.resource_states);
+        }
+        self.frames.truncate(frames_in_flight);
+        for index in self.frames.len()..frames_in_flight {
+            let mut frame = FrameResources::new(render_system, index)?;
+            frame.command_buffer.set_profiler(self.profiler.clone());
+            self.frames.push(frame);
+        }
+        if let Some(profiler) = self.profiler.as_ref() {
+            profiler.set_frames_in_flight(render_system, frames_in_flight)?;
+        }
+        if let Some(frame_arena) = self.frame_arena.as_ref() {
+            frame_arena.set_frames_in_flight(render_system, frames_in_flight)?;
+        }
+
+        self.current_frame = 0;
+        Ok(())
+    }
+
+    /// Shorthand for clearing a float color attachment, or loading it when `value` is `None`.
     pub fn set_clear_color(&mut self, index: usize, value: Option<[f32; 4]>) {
-        if self.color_clear_values.len() <= index {
-            self.color_clear_values.resize(index + 1, None);
+        self.set_color_attachment_ops(
+            index,
+            value.map_or_else(AttachmentOps::load, |c| {
+                AttachmentOps::clear(AttachmentClearValue::Float(c))
+            }),
+        );
+    }
+
+    /// Takes effect from the next frame on.
+    pub fn set_color_attachment_ops(&mut self, index: usize, ops: AttachmentOps) {
+        if self.settings.color_attachments.len() <= index {
+            self.settings
+                .color_attachments
+                .resize(index + 1, AttachmentOps::default());
         }
 
-        self.color_clear_values[index] = value;
+        self.settings.color_attachments[index] = ops;
+    }
+
+    pub fn set_depth_stencil_attachment_ops(&mut self, ops: AttachmentOps) {
+        self.settings.depth_stencil_attachment = ops;
     }
 
     pub fn set_render_area(&mut self, area: Option<vk::Rect2D>) {
-        self.render_area = area;
+        self.settings.render_area = area;
+    }
+
+    /// Set `RenderingFlags::CONTENTS_SECONDARY_COMMAND_BUFFERS` to record the frame with secondary command
+    /// buffers (see `ParallelRecorder`) instead of inline commands.
+    pub fn set_rendering_flags(&mut self, flags: vk::RenderingFlags) {
+        self.settings.rendering_flags = flags;
+    }
+
+    /// Every frame is recorded inside a "frame" scope of `profiler`, and render graph passes in a scope of
+    /// their own.
+    /// It needs at least as many frame slots as this renderer has frames in flight.
+    pub fn set_profiler(&mut self, profiler: Option<Rc<GpuProfiler>>) -> anyhow::Result<()> {
+        if let Some(profiler) = profiler.as_ref() {
+            if profiler.frames_in_flight() < self.frames.len() {
+                return Err(anyhow!(
+                    "The profiler has {} frame slots but the renderer has {} frames in flight",
+                    profiler.frames_in_flight(),
+                    self.frames.len()
+                ));
+            }
+        }
+
+        for frame in self.frames.iter_mut() {
+            frame.command_buffer.set_profiler(profiler.clone());
+        }
+        self.profiler = profiler;
+        Ok(())
+    }
+
+    /// The arena's region for the current frame is recycled in `begin_frame`, once the frame's fence has
+    /// signaled. It needs at least as many regions as this renderer has frames in flight.
+    pub fn set_frame_arena(&mut self, frame_arena: Option<Rc<FrameArena>>) -> anyhow::Result<()> {
+        if let Some(frame_arena) = frame_arena.as_ref() {
+            if frame_arena.frames_in_flight() < self.frames.len() {
+                return Err(anyhow!(
+                    "The frame arena has {} regions but the renderer has {} frames in flight",
+                    frame_arena.frames_in_flight(),
+                    self.frames.len()
+                ));
+            }
+        }
+
+        self.frame_arena = frame_arena;
+        Ok(())
+    }
+
+    #[inline]
+    pub fn frame_arena(&self) -> Option<&Rc<FrameArena>> {
+        self.frame_arena.as_ref()
+    }
+
+    #[inline]
+    pub fn settings(&self) -> &FrameRenderSettings {
+        &self.settings
+    }
+
+    /// The index of the frame in flight that the next `render_to_target` call will use.
+    pub fn current_frame(&self) -> usize {
+        self.current_frame
     }
 
     pub fn render_to_target(
@@ -79,176 +380,528 @@ impl PrimaryRenderer {
         f: impl FnOnce(&DynamicRenderingRecorder, &FrameRenderInfo),
     ) {
         self.begin_frame();
+        let frame = &mut self.frames[self.current_frame];
 
-        RenderTargetExt::render_frame(
-            target,
-            &self.frame_sync_infos[self.current_frame],
-            |render_info| {
-                let cmd = &mut self.command_buffers[self.current_frame];
-                {
-                    let Ok(cmd) = cmd.begin(Some(
-                        CommandBufferBeginInfo::default()
-                            .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                    )) else {
-                        return;
-                    };
+        RenderTargetExt::render_frame(target, &frame.sync_info, |render_info| {
+            let cmd = &mut frame.command_buffer;
+            {
+                let Ok(cmd) = cmd.begin(Some(
+                    CommandBufferBeginInfo::default()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )) else {
+                    return;
+                };
 
-                    let image_transitions_1 = render_info
-                        .color_attachments
-                        .iter()
-                        .filter_map(|attachment| {
-                            if attachment.initial_state.layout
-                                == ImageLayout::COLOR_ATTACHMENT_OPTIMAL
-                                && attachment.initial_state.access
-                                    == AccessFlags2::COLOR_ATTACHMENT_WRITE
-                                && attachment.initial_state.queue_family == 0
-                            {
-                                return None;
-                            }
-                            Some(ImageTransition {
-                                image: attachment.image,
-                                subresource_range: ImageSubresourceRange {
-                                    aspect_mask: ImageAspectFlags::COLOR,
-                                    base_mip_level: 0,
-                                    level_count: 1,
-                                    base_array_layer: 0,
-                                    layer_count: 1,
-                                },
-                                src_state: (
-                                    PipelineStageFlags2::TOP_OF_PIPE,
-                                    attachment.initial_state.layout,
-                                    attachment.initial_state.access,
-                                    attachment.initial_state.queue_family,
-                                ),
-                                dst_state: (
-                                    PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                                    AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                                    0,
-                                ),
-                            })
-                        })
-                        .collect::<Vec<_>>();
-
-                    let image_transitions_2 = render_info
-                        .color_attachments
-                        .iter()
-                        .filter_map(|attachment| {
-                            if attachment.final_state.layout
-                                == ImageLayout::COLOR_ATTACHMENT_OPTIMAL
-                                && attachment.final_state.access
-                                    == AccessFlags2::COLOR_ATTACHMENT_WRITE
-                                && attachment.final_state.queue_family == 0
-                            {
-                                return None;
-                            }
-
-                            Some(ImageTransition {
-                                image: attachment.image,
-                                subresource_range: ImageSubresourceRange {
-                                    aspect_mask: ImageAspectFlags::COLOR,
-                                    base_mip_level: 0,
-                                    level_count: 1,
-                                    base_array_layer: 0,
-                                    layer_count: 1,
-                                },
-                                src_state: (
-                                    PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT,
-                                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-                                    AccessFlags2::COLOR_ATTACHMENT_WRITE,
-                                    0,
-                                ),
-                                dst_state: (
-                                    PipelineStageFlags2::BOTTOM_OF_PIPE,
-                                    attachment.final_state.layout,
-                                    attachment.final_state.access,
-                                    attachment.final_state.queue_family,
-                                ),
-                            })
-                        })
-                        .collect::<Vec<_>>();
-
-                    if !image_transitions_1.is_empty() {
-                        cmd.image_transitions(image_transitions_1.as_slice())
-                    }
+                if let Some(profiler) = self.profiler.as_ref() {
+                    profiler.begin_frame(&cmd, self.current_frame);
+                }
+                let cmd = cmd.profile_scope("frame");
 
-                    {
-                        let color_attachments = render_info
-                            .color_attachments
-                            .iter()
-                            .enumerate()
-                            .map(|(i, attachment)| {
-                                let clear_value =
-                                    self.color_clear_values.get(i).cloned().unwrap_or(None).map(
-                                        |c| ClearValue {
-                                            color: vk::ClearColorValue { float32: c },
-                                        },
-                                    );
-
-                                RenderingAttachmentInfo::default()
-                                    .image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-                                    .load_op(match clear_value {
-                                        None => AttachmentLoadOp::LOAD,
-                                        Some(_) => AttachmentLoadOp::CLEAR,
-                                    })
-                                    .clear_value(clear_value.unwrap_or(ClearValue::default()))
-                                    .store_op(AttachmentStoreOp::STORE)
-                                    .image_view(attachment.image_view)
-                            })
-                            .collect::<Vec<_>>();
-
-                        let rendering_info = RenderingInfo::default()
-                            .render_area(
-                                self.render_area.unwrap_or(Rect2D::from(render_info.extent)),
-                            )
-                            .color_attachments(color_attachments.as_slice())
-                            .layer_count(1)
-                            .view_mask(0);
-
-                        {
-                            let cmd = cmd.begin_rendering(rendering_info);
-                            f(&cmd, render_info);
-                        }
-                    }
+                record_frame(
+                    &cmd,
+                    render_info,
+                    &self.settings,
+                    &mut self.resource_states,
+                    f,
+                );
+            }
+
+            submit_frame(
+                &self.device,
+                self.graphics_queue,
+                &frame.sync_info,
+                frame.fence,
+                cmd.handle(),
+            );
+        });
+
+        self.end_frame();
+    }
+
+    /// Builds a render graph for this frame with `build`, then compiles and records it. Errors while
+    /// compiling or executing the graph are logged and the frame is presented without its passes.
+    pub fn render_graph_to_target<'a>(
+        &mut self,
+        target: &mut dyn RenderTarget,
+        build: impl FnOnce(&mut RenderGraph<'a>, &FrameRenderInfo),
+    ) {
+        self.begin_frame();
+        let frame = &mut self.frames[self.current_frame];
+
+        RenderTargetExt::render_frame(target, &frame.sync_info, |render_info| {
+            let mut graph = RenderGraph::new();
+            build(&mut graph, render_info);
 
-                    if !image_transitions_2.is_empty() {
-                        cmd.image_transitions(image_transitions_2.as_slice())
+            // the frame still has to be submitted so its fence signals, so fall back to a graph that
+            // only transitions the backbuffers
+            let (graph, compiled) = match graph.compile() {
+                Ok(compiled) => (graph, compiled),
+                Err(e) => {
+                    error!("Failed to compile render graph: {}", e);
+                    let mut fallback = RenderGraph::new();
+                    for index in 0..render_info.color_attachments.len() {
+                        fallback.backbuffer(index);
                     }
+                    let Ok(compiled) = fallback.compile() else {
+                        return;
+                    };
+                    (fallback, compiled)
                 }
+            };
 
-                unsafe {
-                    let _ = self.device.queue_submit(
-                        self.graphics_queue,
-                        &[vk::SubmitInfo::default()
-                            .command_buffers(&[cmd.handle()])
-                            .wait_semaphores(&[
-                                self.frame_sync_infos[self.current_frame].image_available
-                            ])
-                            .wait_dst_stage_mask(&[PipelineStageFlags::TOP_OF_PIPE])
-                            .signal_semaphores(&[
-                                self.frame_sync_infos[self.current_frame].render_finished
-                            ])],
-                        self.fences[self.current_frame],
-                    );
+            let cmd = &mut frame.command_buffer;
+            {
+                let Ok(cmd) = cmd.begin(Some(
+                    CommandBufferBeginInfo::default()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )) else {
+                    return;
                 };
-            },
-        );
+
+                if let Some(profiler) = self.profiler.as_ref() {
+                    profiler.begin_frame(&cmd, self.current_frame);
+                }
+                let cmd = cmd.profile_scope("frame");
+
+                if let Err(e) = graph.execute(
+                    &compiled,
+                    &cmd,
+                    render_info,
+                    &mut frame.transient_pool,
+                    &mut self.resource_states,
+                ) {
+                    error!("Failed to execute render graph: {}", e);
+                }
+            }
+
+            submit_frame(
+                &self.device,
+                self.graphics_queue,
+                &frame.sync_info,
+                frame.fence,
+                cmd.handle(),
+            );
+        });
 
         self.end_frame();
     }
 }
 
-impl Drop for PrimaryRenderer {
-    fn drop(&mut self) {
-        unsafe {
-            for sync in &self.frame_sync_infos {
-                self.device.destroy_semaphore(sync.image_available, None);
-                self.device.destroy_semaphore(sync.render_finished, None);
+/// Records a frame into `cmd`: transitions the target's attachments for rendering, runs `f` inside a
+/// rendering scope and transitions them to their final states. This doesn't touch the device, so it can be
+/// recorded into a `CommandBuffer::recording` to inspect the barriers it emits.
+pub fn record_frame(
+    cmd: &CommandRecorder,
+    render_info: &FrameRenderInfo,
+    settings: &FrameRenderSettings,
+    resource_states: &mut ResourceStateTracker,
+    f: impl FnOnce(&DynamicRenderingRecorder, &FrameRenderInfo),
+) {
+    let depth_stencil = render_info
+        .depth_stencil_attachment
+        .as_ref()
+        .map(|attachment| {
+            (
+                attachment,
+                depth_stencil_subresource_range(attachment.format),
+            )
+        });
+
+    for attachment in &render_info.color_attachments {
+        resource_states.register_image(
+            attachment.image,
+            ImageAspectFlags::COLOR,
+            1,
+            1,
+            ResourceState::from_external(
+                attachment.initial_state,
+                PipelineStageFlags2::TOP_OF_PIPE,
+            ),
+        );
+        resource_states.use_image(
+            attachment.image,
+            COLOR_SUBRESOURCE_RANGE,
+            ResourceUsage::ColorAttachmentWrite,
+        );
+    }
+    if let Some((attachment, subresource_range)) = depth_stencil {
+        resource_states.register_image(
+            attachment.image,
+            subresource_range.aspect_mask,
+            1,
+            1,
+            ResourceState::from_external(
+                attachment.initial_state,
+                PipelineStageFlags2::TOP_OF_PIPE,
+            ),
+        );
+        resource_states.use_image(
+            attachment.image,
+            subresource_range,
+            ResourceUsage::DepthStencilAttachmentWrite,
+        );
+    }
+
+    cmd.resource_barriers(resource_states);
+
+    {
+        let attachment_info = |image_view, layout, ops: AttachmentOps| {
+            RenderingAttachmentInfo::default()
+                .image_view(image_view)
+                .image_layout(layout)
+                .load_op(ops.load_op)
+                .store_op(ops.store_op)
+                .clear_value(ops.clear_value.into())
+        };
+
+        let color_attachments = render_info
+            .color_attachments
+            .iter()
+            .enumerate()
+            .map(|(i, attachment)| {
+                attachment_info(
+                    attachment.image_view,
+                    ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    settings.color_attachment(i),
+                )
+            })
+            .collect::<Vec<_>>();
+        let depth_stencil_attachment = depth_stencil.map(|(attachment, _)| {
+            attachment_info(
+                attachment.image_view,
+                ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                settings.depth_stencil_attachment,
+            )
+        });
+
+        let mut formats = AttachmentFormats {
+            color: render_info
+                .color_attachments
+                .iter()
+                .map(|a| a.format)
+                .collect(),
+            ..AttachmentFormats::default()
+        };
+
+        let mut rendering_info = RenderingInfo::default()
+            .flags(settings.rendering_flags)
+            .render_area(
+                settings
+                    .render_area
+                    .unwrap_or(Rect2D::from(render_info.extent)),
+            )
+            .color_attachments(color_attachments.as_slice())
+            .layer_count(1)
+            .view_mask(0);
+        if let (Some((attachment, subresource_range)), Some(depth_stencil_attachment)) =
+            (depth_stencil, depth_stencil_attachment.as_ref())
+        {
+            if subresource_range
+                .aspect_mask
+                .contains(ImageAspectFlags::DEPTH)
+            {
+                rendering_info = rendering_info.depth_attachment(depth_stencil_attachment);
+                formats.depth = attachment.format;
             }
+            if subresource_range
+                .aspect_mask
+                .contains(ImageAspectFlags::STENCIL)
+            {
+                rendering_info = rendering_info.stencil_attachment(depth_stencil_attachment);
+                formats.stencil = attachment.format;
+            }
+        }
+
+        {
+            let cmd = cmd.begin_rendering_with_formats(rendering_info, &formats);
+            f(&cmd, render_info);
+        }
+    }
+
+    for attachment in &render_info.color_attachments {
+        resource_states.use_image(
+            attachment.image,
+            COLOR_SUBRESOURCE_RANGE,
+            ResourceState::from_external(
+                attachment.final_state,
+                PipelineStageFlags2::BOTTOM_OF_PIPE,
+            ),
+        );
+    }
+    if let Some((attachment, subresource_range)) = depth_stencil {
+        resource_states.use_image(
+            attachment.image,
+            subresource_range,
+            ResourceState::from_external(
+                attachment.final_state,
+                PipelineStageFlags2::BOTTOM_OF_PIPE,
+            ),
+        );
+    }
+
+    cmd.resource_barriers(resource_states);
 
-            for fence in self.fences {
-                self.device.destroy_fence(fence, None);
+    for attachment in render_info
+        .color_attachments
+        .iter()
+        .chain(render_info.depth_stencil_attachment.iter())
+    {
+        resource_states.forget_image(attachment.image);
+    }
+}
+
+fn depth_stencil_subresource_range(format: vk::Format) -> ImageSubresourceRange {
+    ImageSubresourceRange {
+        aspect_mask: format_aspect(format),
+        ..COLOR_SUBRESOURCE_RANGE
+    }
+}
+
+fn submit_frame(
+    device: &ash::Device,
+    queue: vk::Queue,
+    sync_info: &FrameSyncInfo,
+    fence: vk::Fence,
+    command_buffer: vk::CommandBuffer,
+) {
+    unsafe {
+        let _ = device.queue_submit(
+            queue,
+            &[vk::SubmitInfo::default()
+                .command_buffers(&[command_buffer])
+                .wait_semaphores(&[sync_info.image_available])
+                .wait_dst_stage_mask(&[PipelineStageFlags::TOP_OF_PIPE])
+                .signal_semaphores(&[sync_info.render_finished])],
+            fence,
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::command_list::{CommandList, RecordedCommand, RecordedImageBarrier};
+    use crate::render::render_target::{
+        FrameRenderAttachment, FrameRenderAttachmentImageStateExternal,
+    };
+    use ash::vk::{AccessFlags2, Handle};
+
+    const UNDEFINED: FrameRenderAttachmentImageStateExternal =
+        FrameRenderAttachmentImageStateExternal {
+            layout: ImageLayout::UNDEFINED,
+            access: AccessFlags2::NONE,
+            queue_family: vk::QUEUE_FAMILY_IGNORED,
+        };
+
+    const PRESENT: FrameRenderAttachmentImageStateExternal =
+        FrameRenderAttachmentImageStateExternal {
+            layout: ImageLayout::PRESENT_SRC_KHR,
+            access: AccessFlags2::NONE,
+            queue_family: vk::QUEUE_FAMILY_IGNORED,
+        };
+
+    fn attachment(
+        image: u64,
+        format: vk::Format,
+        final_state: FrameRenderAttachmentImageStateExternal,
+    ) -> FrameRenderAttachment {
+        FrameRenderAttachment {
+            image: vk::Image::from_raw(image),
+            image_view: vk::ImageView::from_raw(image + 100),
+            format,
+            initial_state: UNDEFINED,
+            final_state,
+        }
+    }
+
+    fn render_info(
+        color_attachments: Vec<FrameRenderAttachment>,
+        depth_stencil_attachment: Option<FrameRenderAttachment>,
+    ) -> FrameRenderInfo {
+        FrameRenderInfo {
+            color_attachments,
+            depth_stencil_attachment,
+            extent: vk::Extent2D {
+                width: 640,
+                height: 480,
+            },
+            image_index: 0,
+        }
+    }
+
+    fn record(
+        render_info: &FrameRenderInfo,
+        settings: &FrameRenderSettings,
+        tracker: &mut ResourceStateTracker,
+    ) -> CommandList {
+        let mut command_buffer = CommandBuffer::recording();
+        {
+            let cmd = command_buffer.begin(None).unwrap();
+            record_frame(&cmd, render_info, settings, tracker, |_, _| {});
+        }
+        command_buffer.take_command_list().unwrap()
+    }
+
+    fn image_barriers(command: &RecordedCommand) -> &[RecordedImageBarrier] {
+        match command {
+            RecordedCommand::PipelineBarrier {
+                image_barriers,
+                buffer_barriers,
+                memory_barriers,
+                ..
+            } => {
+                assert!(buffer_barriers.is_empty() && memory_barriers.is_empty());
+                image_barriers
             }
+            command => panic!("Expected a barrier, got {:?}", command),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn transitions_color_attachments_for_rendering_and_presentation() {
+        let info = render_info(
+            vec![attachment(1, vk::Format::B8G8R8A8_SRGB, PRESENT)],
+            None,
+        );
+        let mut tracker = ResourceStateTracker::new();
+        let list = record(&info, &FrameRenderSettings::default(), &mut tracker);
+
+        let commands = list.commands();
+        assert_eq!(commands.len(), 4);
+        assert!(matches!(commands[1], RecordedCommand::BeginRendering(_)));
+        assert_eq!(commands[2], RecordedCommand::EndRendering);
+
+        let before = image_barriers(&commands[0]);
+        assert_eq!(before.len(), 1);
+        assert_eq!(before[0].image, vk::Image::from_raw(1));
+        assert_eq!(before[0].old_layout, ImageLayout::UNDEFINED);
+        assert_eq!(before[0].new_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(before[0].src_stage_mask, PipelineStageFlags2::TOP_OF_PIPE);
+        assert_eq!(
+            before[0].dst_stage_mask,
+            PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(
+            before[0].dst_access_mask,
+            AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(before[0].subresource_range, COLOR_SUBRESOURCE_RANGE.into());
+
+        let after = image_barriers(&commands[3]);
+        assert_eq!(after.len(), 1);
+        assert_eq!(after[0].old_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(after[0].new_layout, ImageLayout::PRESENT_SRC_KHR);
+        assert_eq!(
+            after[0].src_stage_mask,
+            PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(
+            after[0].src_access_mask,
+            AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(after[0].dst_stage_mask, PipelineStageFlags2::BOTTOM_OF_PIPE);
+        assert_eq!(after[0].dst_access_mask, AccessFlags2::NONE);
+
+        // the target's images are external, so the tracker forgets them again
+        assert!(!tracker.has_pending_barriers());
+        assert_eq!(
+            record(&info, &FrameRenderSettings::default(), &mut tracker),
+            list
+        );
+    }
+
+    #[test]
+    fn transitions_depth_stencil_attachments_with_both_aspects() {
+        let depth_stencil_final = FrameRenderAttachmentImageStateExternal {
+            layout: ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            access: AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            queue_family: vk::QUEUE_FAMILY_IGNORED,
+        };
+        let info = render_info(
+            vec![attachment(1, vk::Format::B8G8R8A8_SRGB, PRESENT)],
+            Some(attachment(
+                2,
+                vk::Format::D24_UNORM_S8_UINT,
+                depth_stencil_final,
+            )),
+        );
+        let list = record(
+            &info,
+            &FrameRenderSettings::default(),
+            &mut ResourceStateTracker::new(),
+        );
+        let commands = list.commands();
+
+        let before = image_barriers(&commands[0]);
+        assert_eq!(before.len(), 2);
+        let depth_stencil = before
+            .iter()
+            .find(|b| b.image == vk::Image::from_raw(2))
+            .unwrap();
+        assert_eq!(
+            depth_stencil.new_layout,
+            ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            depth_stencil.subresource_range.aspect_mask,
+            ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+        );
+        assert_eq!(
+            depth_stencil.dst_stage_mask,
+            PipelineStageFlags2::EARLY_FRAGMENT_TESTS | PipelineStageFlags2::LATE_FRAGMENT_TESTS
+        );
+
+        let RecordedCommand::BeginRendering(rendering_info) = &commands[1] else {
+            panic!("Expected BeginRendering, got {:?}", commands[1]);
+        };
+        let depth = rendering_info.depth_attachment.unwrap();
+        assert_eq!(rendering_info.stencil_attachment, Some(depth));
+        assert_eq!(depth.image_view, vk::ImageView::from_raw(102));
+        assert_eq!(depth.load_op, AttachmentLoadOp::CLEAR);
+        assert_eq!(rendering_info.color_attachments.len(), 1);
+        assert_eq!(
+            rendering_info.color_attachments[0].load_op,
+            AttachmentLoadOp::LOAD
+        );
+
+        // the depth/stencil attachment stays in its layout, but its writes still have to finish
+        let after = image_barriers(&commands[3]);
+        assert_eq!(after.len(), 2);
+        let depth_stencil = after
+            .iter()
+            .find(|b| b.image == vk::Image::from_raw(2))
+            .unwrap();
+        assert_eq!(depth_stencil.old_layout, depth_stencil.new_layout);
+        assert_eq!(
+            depth_stencil.dst_stage_mask,
+            PipelineStageFlags2::BOTTOM_OF_PIPE
+        );
+    }
+
+    #[test]
+    fn recorded_frames_survive_serialization() {
+        let info = render_info(
+            vec![attachment(1, vk::Format::B8G8R8A8_SRGB, PRESENT)],
+            Some(attachment(2, vk::Format::D32_SFLOAT, PRESENT)),
+        );
+        let settings = FrameRenderSettings {
+            render_area: Some(Rect2D {
+                offset: vk::Offset2D { x: -4, y: 8 },
+                extent: vk::Extent2D {
+                    width: 320,
+                    height: 240,
+                },
+            }),
+            color_attachments: vec![AttachmentOps::clear(AttachmentClearValue::Float([
+                0.25, 0.5, 1.0, 1.0,
+            ]))],
+            ..FrameRenderSettings::default()
+        };
+        let list = record(&info, &settings, &mut ResourceStateTracker::new());
+
+        let json = serde_json::to_string(&list).unwrap();
+        assert_eq!(serde_json::from_str::<CommandList>(&json).unwrap(), list);
+        let ron = ron::to_string(&list).unwrap();
+        assert_eq!(ron::from_str::<CommandList>(&ron).unwrap(), list);
+    }
+}
@@ -0,0 +1,508 @@
+use crate::render::RenderSystem;
+use crate::render::command_buffer::{CommandBuffer, CommandRecorder};
+use crate::render::command_list::RecordedCommand;
+use crate::render::query::{QueryKind, QueryPool};
+use anyhow::anyhow;
+use ash::vk::PipelineStageFlags2;
+use log::debug;
+use serde_json::{Value, json};
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const HISTORY_LENGTH: usize = 120;
+
+/// One profiled scope of a completed frame. CPU times are relative to the start of the frame's recording,
+/// GPU times to the frame's first timestamp.
+#[derive(Clone, Debug)]
+pub struct ProfileNode {
+    pub name: String,
+    pub cpu_start: Duration,
+    pub cpu_duration: Duration,
+    pub gpu_start: Option<Duration>,
+    pub gpu_duration: Option<Duration>,
+    pub children: Vec<ProfileNode>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ProfilerFrame {
+    pub frame_number: u64,
+    pub cpu_start: Instant,
+    pub roots: Vec<ProfileNode>,
+}
+
+struct ScopeRecord {
+    name: String,
+    parent: Option<usize>,
+    queries: Option<(u32, u32)>,
+    cpu_begin: Duration,
+    cpu_end: Duration,
+}
+
+struct FrameRecording {
+    query_pool: Option<QueryPool>,
+    scopes: Vec<ScopeRecord>,
+    stack: Vec<usize>,
+    next_query: u32,
+    cpu_start: Instant,
+    frame_number: u64,
+    pending: bool,
+}
+
+/// Timestamp-query based GPU profiler with CPU timings for the same scopes.
+///
+/// Results are read back when a frame slot comes around again, i.e. one frame in flight count later,
+/// after its fence has been waited on, so reading them never stalls. Attach it to a `PrimaryRenderer` with
+/// `set_profiler` and open scopes with `profile_scope` on any recorder.
+pub struct GpuProfiler {
+    frames: RefCell<Vec<RefCell<FrameRecording>>>,
+    current_frame: Cell<usize>,
+    max_scopes_per_frame: u32,
+    timestamps_supported: bool,
+    frame_counter: Cell<u64>,
+    // nanoseconds per timestamp tick
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    history: RefCell<VecDeque<ProfilerFrame>>,
+}
+
+impl GpuProfiler {
+    /// Needs a frame slot per frame in flight of the renderer it's attached to.
+    pub fn new(
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+        max_scopes_per_frame: u32,
+    ) -> anyhow::Result<Self> {
+        let (properties, queue_family_properties) = unsafe {
+            (
+                render_system
+                    .instance()
+                    .get_physical_device_properties(render_system.physical_device()),
+                render_system
+                    .instance()
+                    .get_physical_device_queue_family_properties(render_system.physical_device()),
+            )
+        };
+
+        let timestamp_valid_bits = queue_family_properties
+            .get(render_system.queue_families().main as usize)
+            .map_or(0, |p| p.timestamp_valid_bits);
+        if timestamp_valid_bits == 0 {
+            debug!("Main queue doesn't support timestamps, only CPU timings will be profiled");
+        }
+
+        let profiler = Self {
+            frames: RefCell::new(Vec::new()),
+            current_frame: Cell::new(0),
+            max_scopes_per_frame,
+            timestamps_supported: timestamp_valid_bits > 0,
+            frame_counter: Cell::new(0),
+            timestamp_period: properties.limits.timestamp_period as f64,
+            timestamp_mask: if timestamp_valid_bits >= 64 {
+                u64::MAX
+            } else {
+                (1u64 << timestamp_valid_bits) - 1
+            },
+            history: RefCell::new(VecDeque::with_capacity(HISTORY_LENGTH)),
+        };
+        profiler.set_frames_in_flight(render_system, frames_in_flight)?;
+
+        Ok(profiler)
+    }
+
+    #[inline]
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.borrow().len()
+    }
+
+    /// Adds or removes frame slots. Results still pending in removed slots are lost, so only call this
+    /// while the device is idle, like `PrimaryRenderer::set_frames_in_flight` does.
+    pub fn set_frames_in_flight(
+        &self,
+        render_system: &RenderSystem,
+        frames_in_flight: usize,
+    ) -> anyhow::Result<()> {
+        if frames_in_flight == 0 {
+            return Err(anyhow!("At least one frame slot is required"));
+        }
+
+        let mut frames = self.frames.borrow_mut();
+        frames.truncate(frames_in_flight);
+        for index in frames.len()..frames_in_flight {
+            let query_pool = if self.timestamps_supported {
+                let query_pool = QueryPool::new(
+                    render_system,
+                    QueryKind::Timestamp,
+                    self.max_scopes_per_frame * 2,
+                )?;
+                query_pool
+                    .set_debug_name(render_system, &format!("profiler timestamps {}", index));
+                Some(query_pool)
+            } else {
+                None
+            };
+
+            frames.push(RefCell::new(FrameRecording {
+                query_pool,
+                scopes: Vec::new(),
+                stack: Vec::new(),
+                next_query: 0,
+                cpu_start: Instant::now(),
+                frame_number: 0,
+                pending: false,
+            }));
+        }
+
+        if self.current_frame.get() >= frames_in_flight {
+            self.current_frame.set(0);
+        }
+        Ok(())
+    }
+
+    /// Collects the results previously recorded in `frame`'s slot and resets it for recording. Must be
+    /// called at the start of the frame's command buffer, after its fence has been waited on.
+    pub fn begin_frame(&self, cmd: &CommandRecorder, frame: usize) {
+        let frames = self.frames.borrow();
+        let frame = frame % frames.len();
+        let mut recording = frames[frame].borrow_mut();
+
+        if recording.pending {
+            let completed = self.collect(&recording);
+            let mut history = self.history.borrow_mut();
+            if history.len() == HISTORY_LENGTH {
+                history.pop_front();
+            }
+            history.push_back(completed);
+        }
+
+        if let Some(query_pool) = recording.query_pool.as_ref() {
+            cmd.reset_query_pool(query_pool, 0, query_pool.query_count());
+        }
+
+        recording.scopes.clear();
+        recording.stack.clear();
+        recording.next_query = 0;
+        recording.cpu_start = Instant::now();
+        recording.frame_number = self.frame_counter.get();
+        recording.pending = true;
+
+        self.frame_counter.set(self.frame_counter.get() + 1);
+        self.current_frame.set(frame);
+    }
+
+    /// The most recent frame whose results have been read back.
+    pub fn last_frame(&self) -> Option<ProfilerFrame> {
+        self.history.borrow().back().cloned()
+    }
+
+    /// Up to the last 120 completed frames, oldest first.
+    pub fn history(&self) -> Vec<ProfilerFrame> {
+        self.history.borrow().iter().cloned().collect()
+    }
+
+    pub(crate) fn begin_scope(&self, command_buffer: &CommandBuffer, name: &str) -> usize {
+        let frames = self.frames.borrow();
+        let mut recording = frames[self.current_frame.get()].borrow_mut();
+        let recording = &mut *recording;
+
+        let queries = match recording.query_pool.as_ref() {
+            Some(query_pool) if recording.next_query + 2 <= query_pool.query_count() => {
+                let begin = recording.next_query;
+                recording.next_query += 2;
+                command_buffer.record(RecordedCommand::WriteTimestamp {
+                    stage: PipelineStageFlags2::TOP_OF_PIPE,
+                    query_pool: query_pool.handle(),
+                    query: begin,
+                });
+                Some((begin, begin + 1))
+            }
+            _ => None,
+        };
+
+        let cpu_begin = recording.cpu_start.elapsed();
+        recording.scopes.push(ScopeRecord {
+            name: name.to_string(),
+            parent: recording.stack.last().copied(),
+            queries,
+            cpu_begin,
+            cpu_end: cpu_begin,
+        });
+
+        let scope = recording.scopes.len() - 1;
+        recording.stack.push(scope);
+        scope
+    }
+
+    pub(crate) fn end_scope(&self, command_buffer: &CommandBuffer, scope: usize) {
+        let frames = self.frames.borrow();
+        let mut recording = frames[self.current_frame.get()].borrow_mut();
+        let recording = &mut *recording;
+        let Some(record) = recording.scopes.get_mut(scope) else {
+            return;
+        };
+
+        if let (Some((_, end)), Some(query_pool)) = (record.queries, recording.query_pool.as_ref()) {
+            command_buffer.record(RecordedCommand::WriteTimestamp {
+                stage: PipelineStageFlags2::BOTTOM_OF_PIPE,
+                query_pool: query_pool.handle(),
+                query: end,
+            });
+        }
+
+        record.cpu_end = recording.cpu_start.elapsed();
+        if let Some(position) = recording.stack.iter().rposition(|&s| s == scope) {
+            recording.stack.truncate(position);
+        }
+    }
+
+    fn collect(&self, recording: &FrameRecording) -> ProfilerFrame {
+        let timestamps = match recording.query_pool.as_ref() {
+            Some(query_pool) if recording.next_query > 0 => query_pool
+                .timestamps(0, recording.next_query)
+                .unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        let ticks = |query: u32| {
+            timestamps
+                .get(query as usize)
+                .copied()
+                .flatten()
+                .map(|t| t & self.timestamp_mask)
+        };
+        let frame_origin = (0..recording.next_query).filter_map(ticks).min().unwrap_or(0);
+        let to_duration = |ticks: u64| {
+            Duration::from_nanos((ticks as f64 * self.timestamp_period) as u64)
+        };
+
+        let roots = build_tree(&recording.scopes, |scope| {
+            scope.queries.and_then(|(begin, end)| {
+                let (begin, end) = (ticks(begin)?, ticks(end)?);
+                Some((
+                    to_duration(begin.saturating_sub(frame_origin)),
+                    to_duration(end.saturating_sub(begin)),
+                ))
+            })
+        });
+
+        ProfilerFrame {
+            frame_number: recording.frame_number,
+            cpu_start: recording.cpu_start,
+            roots,
+        }
+    }
+}
+
+/// Nests the scopes of a frame under their parents. `gpu_times` gives a scope's GPU start and duration, if
+/// its timestamps were written.
+fn build_tree(
+    scopes: &[ScopeRecord],
+    gpu_times: impl Fn(&ScopeRecord) -> Option<(Duration, Duration)>,
+) -> Vec<ProfileNode> {
+    let mut nodes = scopes
+        .iter()
+        .map(|scope| {
+            let gpu = gpu_times(scope);
+            Some(ProfileNode {
+                name: scope.name.clone(),
+                cpu_start: scope.cpu_begin,
+                cpu_duration: scope.cpu_end.saturating_sub(scope.cpu_begin),
+                gpu_start: gpu.map(|(start, _)| start),
+                gpu_duration: gpu.map(|(_, duration)| duration),
+                children: Vec::new(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // children always come after their parent, so attach them back to front
+    let mut roots = Vec::new();
+    for index in (0..nodes.len()).rev() {
+        let node = nodes[index].take().unwrap();
+        match scopes[index].parent {
+            Some(parent) => nodes[parent].as_mut().unwrap().children.insert(0, node),
+            None => roots.insert(0, node),
+        }
+    }
+    roots
+}
+
+impl ProfileNode {
+    pub fn find(&self, name: &str) -> Option<&ProfileNode> {
+        if self.name == name {
+            return Some(self);
+        }
+        self.children.iter().find_map(|c| c.find(name))
+    }
+}
+
+impl ProfilerFrame {
+    pub fn find(&self, name: &str) -> Option<&ProfileNode> {
+        self.roots.iter().find_map(|r| r.find(name))
+    }
+
+    /// Total GPU time of the frame's top-level scopes.
+    pub fn gpu_time(&self) -> Option<Duration> {
+        self.roots.iter().map(|r| r.gpu_duration).sum()
+    }
+
+    pub fn cpu_time(&self) -> Duration {
+        self.roots.iter().map(|r| r.cpu_duration).sum()
+    }
+}
+
+/// Exports frames in the Chrome trace-event format (load it in `chrome://tracing` or Perfetto). CPU scopes
+/// are on thread 0 and GPU scopes on thread 1. The GPU clock isn't calibrated against the CPU clock, so each
+/// frame's GPU scopes are placed relative to that frame's CPU start.
+pub fn chrome_trace<'a>(frames: impl IntoIterator<Item = &'a ProfilerFrame>) -> String {
+    fn write_node(events: &mut Vec<Value>, node: &ProfileNode, frame_offset: Duration) {
+        let micros = |d: Duration| d.as_secs_f64() * 1_000_000.0;
+
+        events.push(json!({
+            "name": node.name,
+            "cat": "cpu",
+            "ph": "X",
+            "ts": micros(frame_offset + node.cpu_start),
+            "dur": micros(node.cpu_duration),
+            "pid": 0,
+            "tid": 0,
+        }));
+        if let (Some(start), Some(duration)) = (node.gpu_start, node.gpu_duration) {
+            events.push(json!({
+                "name": node.name,
+                "cat": "gpu",
+                "ph": "X",
+                "ts": micros(frame_offset + start),
+                "dur": micros(duration),
+                "pid": 0,
+                "tid": 1,
+            }));
+        }
+
+        for child in &node.children {
+            write_node(events, child, frame_offset);
+        }
+    }
+
+    let mut events = vec![
+        json!({"name": "thread_name", "ph": "M", "pid": 0, "tid": 0, "args": {"name": "CPU"}}),
+        json!({"name": "thread_name", "ph": "M", "pid": 0, "tid": 1, "args": {"name": "GPU"}}),
+    ];
+
+    let mut origin = None;
+    for frame in frames {
+        let origin = *origin.get_or_insert(frame.cpu_start);
+        let frame_offset = frame.cpu_start.saturating_duration_since(origin);
+        for root in &frame.roots {
+            write_node(&mut events, root, frame_offset);
+        }
+    }
+
+    json!({ "traceEvents": events }).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(name: &str, parent: Option<usize>, cpu_begin: u64, cpu_end: u64) -> ScopeRecord {
+        ScopeRecord {
+            name: name.to_string(),
+            parent,
+            queries: None,
+            cpu_begin: Duration::from_micros(cpu_begin),
+            cpu_end: Duration::from_micros(cpu_end),
+        }
+    }
+
+    fn node(
+        name: &str,
+        cpu_start: u64,
+        gpu: Option<(u64, u64)>,
+        children: Vec<ProfileNode>,
+    ) -> ProfileNode {
+        ProfileNode {
+            name: name.to_string(),
+            cpu_start: Duration::from_micros(cpu_start),
+            cpu_duration: Duration::from_micros(10),
+            gpu_start: gpu.map(|(start, _)| Duration::from_micros(start)),
+            gpu_duration: gpu.map(|(_, duration)| Duration::from_micros(duration)),
+            children,
+        }
+    }
+
+    #[test]
+    fn nests_scopes_under_their_parents_in_recording_order() {
+        let scopes = [
+            scope("frame", None, 0, 100),
+            scope("shadows", Some(0), 5, 20),
+            scope("cascade", Some(1), 6, 10),
+            scope("lighting", Some(0), 20, 90),
+            scope("ui", None, 100, 120),
+        ];
+        let roots = build_tree(&scopes, |scope| {
+            (scope.name == "lighting")
+                .then_some((Duration::from_micros(3), Duration::from_micros(7)))
+        });
+
+        let names =
+            |nodes: &[ProfileNode]| nodes.iter().map(|n| n.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&roots), ["frame", "ui"]);
+        assert_eq!(names(&roots[0].children), ["shadows", "lighting"]);
+        assert_eq!(names(&roots[0].children[0].children), ["cascade"]);
+        assert!(roots[1].children.is_empty());
+
+        let lighting = roots[0].find("lighting").unwrap();
+        assert_eq!(lighting.cpu_start, Duration::from_micros(20));
+        assert_eq!(lighting.cpu_duration, Duration::from_micros(70));
+        assert_eq!(lighting.gpu_start, Some(Duration::from_micros(3)));
+        assert_eq!(lighting.gpu_duration, Some(Duration::from_micros(7)));
+        assert_eq!(roots[0].find("cascade").unwrap().gpu_start, None);
+    }
+
+    #[test]
+    fn traces_both_timelines_relative_to_the_first_frame() {
+        let start = Instant::now();
+        let frames = [
+            ProfilerFrame {
+                frame_number: 0,
+                cpu_start: start,
+                roots: vec![node(
+                    "draw \"opaque\"\n",
+                    0,
+                    Some((2, 5)),
+                    vec![node("cull", 1, None, Vec::new())],
+                )],
+            },
+            ProfilerFrame {
+                frame_number: 1,
+                cpu_start: start + Duration::from_millis(16),
+                roots: vec![node("present", 4, None, Vec::new())],
+            },
+        ];
+
+        let trace: Value = serde_json::from_str(&chrome_trace(&frames)).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        let spans = events
+            .iter()
+            .filter(|e| e["ph"] == "X")
+            .map(|e| {
+                (
+                    e["name"].as_str().unwrap(),
+                    e["tid"].as_u64().unwrap(),
+                    e["ts"].as_f64().unwrap(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(events.iter().filter(|e| e["ph"] == "M").count(), 2);
+        assert_eq!(
+            spans,
+            [
+                ("draw \"opaque\"\n", 0, 0.0),
+                ("draw \"opaque\"\n", 1, 2.0),
+                ("cull", 0, 1.0),
+                ("present", 0, 16_004.0),
+            ]
+        );
+    }
+}
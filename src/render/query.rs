@@ -0,0 +1,313 @@
+use crate::render::RenderSystem;
+use anyhow::anyhow;
+use ash::vk;
+use ash::vk::{QueryPipelineStatisticFlags, QueryPoolCreateInfo, QueryResultFlags};
+
+/// Pipeline statistics in the order Vulkan writes them, i.e. by flag bit.
+const PIPELINE_STATISTICS: [QueryPipelineStatisticFlags; 11] = [
+    QueryPipelineStatisticFlags::INPUT_ASSEMBLY_VERTICES,
+    QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES,
+    QueryPipelineStatisticFlags::VERTEX_SHADER_INVOCATIONS,
+    QueryPipelineStatisticFlags::GEOMETRY_SHADER_INVOCATIONS,
+    QueryPipelineStatisticFlags::GEOMETRY_SHADER_PRIMITIVES,
+    QueryPipelineStatisticFlags::CLIPPING_INVOCATIONS,
+    QueryPipelineStatisticFlags::CLIPPING_PRIMITIVES,
+    QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS,
+    QueryPipelineStatisticFlags::TESSELLATION_CONTROL_SHADER_PATCHES,
+    QueryPipelineStatisticFlags::TESSELLATION_EVALUATION_SHADER_INVOCATIONS,
+    QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS,
+];
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum QueryKind {
+    Timestamp,
+    /// Counts the samples passing the depth and stencil tests. Begin the query with
+    /// `QueryControlFlags::PRECISE` for exact counts, otherwise any non-zero value only means "visible".
+    Occlusion,
+    PipelineStatistics(QueryPipelineStatisticFlags),
+}
+
+/// The counters of a pipeline statistics query. Statistics the pool wasn't created with are `None`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PipelineStatistics {
+    pub input_assembly_vertices: Option<u64>,
+    pub input_assembly_primitives: Option<u64>,
+    pub vertex_shader_invocations: Option<u64>,
+    pub geometry_shader_invocations: Option<u64>,
+    pub geometry_shader_primitives: Option<u64>,
+    pub clipping_invocations: Option<u64>,
+    pub clipping_primitives: Option<u64>,
+    pub fragment_shader_invocations: Option<u64>,
+    pub tessellation_control_shader_patches: Option<u64>,
+    pub tessellation_evaluation_shader_invocations: Option<u64>,
+    pub compute_shader_invocations: Option<u64>,
+}
+
+pub struct QueryPool {
+    query_pool: vk::QueryPool,
+    kind: QueryKind,
+    query_count: u32,
+    precise: bool,
+    device: ash::Device,
+}
+
+impl QueryKind {
+    pub fn query_type(&self) -> vk::QueryType {
+        match self {
+            QueryKind::Timestamp => vk::QueryType::TIMESTAMP,
+            QueryKind::Occlusion => vk::QueryType::OCCLUSION,
+            QueryKind::PipelineStatistics(_) => vk::QueryType::PIPELINE_STATISTICS,
+        }
+    }
+
+    /// How many values a single query of this kind produces, not counting availability.
+    pub fn values_per_query(&self) -> u32 {
+        match self {
+            QueryKind::Timestamp | QueryKind::Occlusion => 1,
+            QueryKind::PipelineStatistics(flags) => flags.as_raw().count_ones(),
+        }
+    }
+}
+
+impl PipelineStatistics {
+    fn from_values(flags: QueryPipelineStatisticFlags, values: &[u64]) -> Self {
+        let mut values = values.iter().copied();
+        let mut counters = [None; PIPELINE_STATISTICS.len()];
+        for (counter, flag) in counters.iter_mut().zip(PIPELINE_STATISTICS) {
+            if flags.contains(flag) {
+                *counter = values.next();
+            }
+        }
+
+        let [
+            input_assembly_vertices,
+            input_assembly_primitives,
+            vertex_shader_invocations,
+            geometry_shader_invocations,
+            geometry_shader_primitives,
+            clipping_invocations,
+            clipping_primitives,
+            fragment_shader_invocations,
+            tessellation_control_shader_patches,
+            tessellation_evaluation_shader_invocations,
+            compute_shader_invocations,
+        ] = counters;
+
+        Self {
+            input_assembly_vertices,
+            input_assembly_primitives,
+            vertex_shader_invocations,
+            geometry_shader_invocations,
+            geometry_shader_primitives,
+            clipping_invocations,
+            clipping_primitives,
+            fragment_shader_invocations,
+            tessellation_control_shader_patches,
+            tessellation_evaluation_shader_invocations,
+            compute_shader_invocations,
+        }
+    }
+}
+
+impl QueryPool {
+    pub fn new(
+        render_system: &RenderSystem,
+        kind: QueryKind,
+        query_count: u32,
+    ) -> anyhow::Result<Self> {
+        let pipeline_statistics = match kind {
+            QueryKind::PipelineStatistics(flags) => {
+                if render_system.enabled_features().pipeline_statistics_query == vk::FALSE {
+                    return Err(anyhow!("Pipeline statistics queries are not supported"));
+                }
+                if flags.is_empty() {
+                    return Err(anyhow!("No pipeline statistics requested"));
+                }
+                flags
+            }
+            _ => QueryPipelineStatisticFlags::empty(),
+        };
+
+        let precise = kind == QueryKind::Occlusion
+            && render_system.enabled_features().occlusion_query_precise == vk::TRUE;
+
+        let device = render_system.device().clone();
+        let query_pool = unsafe {
+            device.create_query_pool(
+                &QueryPoolCreateInfo::default()
+                    .query_type(kind.query_type())
+                    .query_count(query_count)
+                    .pipeline_statistics(pipeline_statistics),
+                None,
+            )
+        }?;
+
+        Ok(Self {
+            query_pool,
+            kind,
+            query_count,
+            precise,
+            device,
+        })
+    }
+
+    #[inline]
+    pub fn handle(&self) -> vk::QueryPool {
+        self.query_pool
+    }
+
+    #[inline]
+    pub fn kind(&self) -> QueryKind {
+        self.kind
+    }
+
+    #[inline]
+    pub fn query_type(&self) -> vk::QueryType {
+        self.kind.query_type()
+    }
+
+    #[inline]
+    pub fn query_count(&self) -> u32 {
+        self.query_count
+    }
+
+    /// Whether queries of this pool can begin with `QueryControlFlags::PRECISE`, i.e. it's an occlusion
+    /// query pool and the device's `occlusionQueryPrecise` feature is enabled.
+    #[inline]
+    pub fn supports_precise(&self) -> bool {
+        self.precise
+    }
+
+    /// Reads back the raw ticks of `count` timestamp queries starting at `first` without waiting. Queries
+    /// whose results aren't available yet are `None`.
+    pub fn timestamps(&self, first: u32, count: u32) -> anyhow::Result<Vec<Option<u64>>> {
+        self.expect_kind(QueryKind::Timestamp)?;
+        self.single_values(first, count)
+    }
+
+    /// Reads back the sample counts of `count` occlusion queries starting at `first` without waiting.
+    pub fn occlusion_results(&self, first: u32, count: u32) -> anyhow::Result<Vec<Option<u64>>> {
+        self.expect_kind(QueryKind::Occlusion)?;
+        self.single_values(first, count)
+    }
+
+    /// Reads back `count` pipeline statistics queries starting at `first` without waiting.
+    pub fn pipeline_statistics(
+        &self,
+        first: u32,
+        count: u32,
+    ) -> anyhow::Result<Vec<Option<PipelineStatistics>>> {
+        let QueryKind::PipelineStatistics(flags) = self.kind else {
+            return Err(anyhow!(
+                "Expected a pipeline statistics query pool, got {:?}",
+                self.kind
+            ));
+        };
+
+        Ok(self
+            .raw_results(first, count)?
+            .into_iter()
+            .map(|values| values.map(|values| PipelineStatistics::from_values(flags, &values)))
+            .collect())
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.query_pool, name);
+    }
+
+    fn expect_kind(&self, kind: QueryKind) -> anyhow::Result<()> {
+        if self.kind != kind {
+            return Err(anyhow!("Expected a {:?} query pool, got {:?}", kind, self.kind));
+        }
+        Ok(())
+    }
+
+    fn single_values(&self, first: u32, count: u32) -> anyhow::Result<Vec<Option<u64>>> {
+        Ok(self
+            .raw_results(first, count)?
+            .into_iter()
+            .map(|values| values.map(|values| values[0]))
+            .collect())
+    }
+
+    /// Every query's values followed by its availability, using a stride wide enough for all of them.
+    fn raw_results(&self, first: u32, count: u32) -> anyhow::Result<Vec<Option<Vec<u64>>>> {
+        let end = first.checked_add(count);
+        if end.is_none_or(|end| end > self.query_count) {
+            return Err(anyhow!(
+                "Queries {}..{} are out of range for a pool of {}",
+                first,
+                first as u64 + count as u64,
+                self.query_count
+            ));
+        }
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let stride = self.kind.values_per_query() as usize + 1;
+        let mut data = vec![0u64; stride * count as usize];
+
+        // ash's wrapper derives the stride from the element type, which doesn't fit pipeline statistics
+        let result = unsafe {
+            (self.device.fp_v1_0().get_query_pool_results)(
+                self.device.handle(),
+                self.query_pool,
+                first,
+                count,
+                std::mem::size_of_val(data.as_slice()),
+                data.as_mut_ptr().cast(),
+                (stride * size_of::<u64>()) as vk::DeviceSize,
+                QueryResultFlags::TYPE_64 | QueryResultFlags::WITH_AVAILABILITY,
+            )
+        };
+        match result {
+            vk::Result::SUCCESS | vk::Result::NOT_READY => {}
+            e => return Err(e.into()),
+        }
+
+        Ok(data
+            .chunks_exact(stride)
+            .map(|query| {
+                let (values, available) = query.split_at(stride - 1);
+                (available[0] != 0).then(|| values.to_vec())
+            })
+            .collect())
+    }
+}
+
+impl Drop for QueryPool {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_statistics_land_in_their_own_counters() {
+        // The values come back in flag bit order, only for the statistics the pool was created with
+        let flags = QueryPipelineStatisticFlags::INPUT_ASSEMBLY_PRIMITIVES
+            | QueryPipelineStatisticFlags::FRAGMENT_SHADER_INVOCATIONS
+            | QueryPipelineStatisticFlags::COMPUTE_SHADER_INVOCATIONS;
+        let statistics = PipelineStatistics::from_values(flags, &[3, 1200, 64]);
+
+        assert_eq!(
+            statistics,
+            PipelineStatistics {
+                input_assembly_primitives: Some(3),
+                fragment_shader_invocations: Some(1200),
+                compute_shader_invocations: Some(64),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn empty_statistics_leave_every_counter_unset() {
+        let statistics = PipelineStatistics::from_values(QueryPipelineStatisticFlags::empty(), &[]);
+        assert_eq!(statistics, PipelineStatistics::default());
+    }
+}
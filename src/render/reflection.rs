@@ -0,0 +1,1448 @@
+use anyhow::anyhow;
+use ash::vk;
+use spirv::{Decoration, Dim, ExecutionMode, ExecutionModel, Op, StorageClass};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+
+/// The interface of a SPIR-V module, as declared by its entry points and global variables.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub entry_points: Vec<EntryPoint>,
+    /// Sorted by set and binding.
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+    pub push_constants: Option<BlockLayout>,
+}
+
+#[derive(Clone, Debug)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: vk::ShaderStageFlags,
+    /// User-defined inputs sorted by location; built-ins are left out.
+    pub inputs: Vec<InterfaceVariable>,
+    /// User-defined outputs sorted by location; built-ins are left out.
+    pub outputs: Vec<InterfaceVariable>,
+    /// The workgroup size of compute, task and mesh shaders.
+    pub local_size: Option<[u32; 3]>,
+    /// The set and binding of every descriptor the entry point uses. `None` for modules older than
+    /// SPIR-V 1.4, whose entry points only list their inputs and outputs.
+    pub descriptors: Option<Vec<(u32, u32)>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct InterfaceVariable {
+    pub name: String,
+    pub location: u32,
+    pub component: u32,
+    /// The number of consecutive locations it takes up, e.g. one per array element or matrix column.
+    pub locations: u32,
+    /// The index of fragment outputs, which is 1 for the second source of dual-source blending.
+    pub index: u32,
+    /// The format of each location for scalars, vectors and arrays of them; `UNDEFINED` otherwise, e.g.
+    /// for matrices and structs.
+    pub format: vk::Format,
+}
+
+#[derive(Clone, Debug)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// The variable's name, or its block's type name if the variable is anonymous.
+    pub name: String,
+    pub descriptor_type: vk::DescriptorType,
+    /// The number of descriptors, which is 0 for runtime-sized arrays.
+    pub count: u32,
+    /// The size of uniform and storage buffer blocks, not counting a trailing runtime-sized array.
+    pub block_size: Option<u32>,
+}
+
+/// The layout of a block with explicit offsets, e.g. a push constant block.
+#[derive(Clone, Debug)]
+pub struct BlockLayout {
+    pub name: String,
+    /// The end of the last member.
+    pub size: u32,
+    pub members: Vec<BlockMember>,
+}
+
+#[derive(Clone, Debug)]
+pub struct BlockMember {
+    pub name: String,
+    /// GLSL-style, e.g. `vec4[2]` or `row_major mat4x3`.
+    pub type_name: String,
+    pub offset: u32,
+    pub size: u32,
+}
+
+/// The descriptor bindings and push constant ranges of several shader stages combined, from which a
+/// pipeline layout can be created.
+#[derive(Clone, Debug, Default)]
+pub struct ReflectedLayout {
+    /// Indexed by set number. Sets none of the shaders use in between used ones are empty.
+    pub sets: Vec<Vec<vk::DescriptorSetLayoutBinding<'static>>>,
+    pub push_constant_ranges: Vec<(vk::ShaderStageFlags, Range<u32>)>,
+}
+
+enum Type {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: Option<Dim>, sampled: u32 },
+    Sampler,
+    SampledImage,
+    AccelerationStructure,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Decorations {
+    set: Option<u32>,
+    binding: Option<u32>,
+    location: Option<u32>,
+    component: Option<u32>,
+    offset: Option<u32>,
+    array_stride: Option<u32>,
+    matrix_stride: Option<u32>,
+    row_major: bool,
+    index: Option<u32>,
+    built_in: bool,
+    buffer_block: bool,
+}
+
+struct RawEntryPoint {
+    model: ExecutionModel,
+    function: u32,
+    name: String,
+    interface: Vec<u32>,
+}
+
+struct Variable {
+    id: u32,
+    pointer_type: u32,
+    storage_class: StorageClass,
+}
+
+/// Everything reflection needs from the instruction stream, indexed by result id.
+#[derive(Default)]
+struct Module {
+    version: u32,
+    names: HashMap<u32, String>,
+    member_names: HashMap<(u32, u32), String>,
+    decorations: HashMap<u32, Decorations>,
+    member_decorations: HashMap<(u32, u32), Decorations>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<Variable>,
+    entry_points: Vec<RawEntryPoint>,
+    local_sizes: HashMap<u32, [u32; 3]>,
+    // LocalSizeId operands, resolved once every constant is known
+    local_size_ids: HashMap<u32, [u32; 3]>,
+}
+
+impl ShaderReflection {
+    /// Reflects a SPIR-V module. Only fails if `code` isn't a well-formed instruction stream; constructs
+    /// reflection doesn't understand are skipped.
+    pub fn new(code: &[u32]) -> anyhow::Result<Self> {
+        let module = Module::parse(code)?;
+
+        let entry_points = module
+            .entry_points
+            .iter()
+            .map(|entry_point| EntryPoint {
+                name: entry_point.name.clone(),
+                stage: stage_flags(entry_point.model),
+                inputs: module.interface_variables(entry_point, StorageClass::Input),
+                outputs: module.interface_variables(entry_point, StorageClass::Output),
+                local_size: module
+                    .local_sizes
+                    .get(&entry_point.function)
+                    .copied()
+                    .or_else(|| {
+                        module
+                            .local_size_ids
+                            .get(&entry_point.function)
+                            .map(|ids| ids.map(|id| module.constant(id)))
+                    }),
+                descriptors: (module.version >= 0x0001_0400).then(|| {
+                    module
+                        .variables
+                        .iter()
+                        .filter(|variable| entry_point.interface.contains(&variable.id))
+                        .filter_map(|variable| module.descriptor_binding(variable))
+                        .map(|binding| (binding.set, binding.binding))
+                        .collect()
+                }),
+            })
+            .collect();
+
+        let mut descriptor_bindings = module
+            .variables
+            .iter()
+            .filter_map(|variable| module.descriptor_binding(variable))
+            .collect::<Vec<_>>();
+        descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+
+        let push_constants = module
+            .variables
+            .iter()
+            .find(|variable| variable.storage_class == StorageClass::PushConstant)
+            .and_then(|variable| module.block_layout(module.pointee(variable.pointer_type)?));
+
+        Ok(Self {
+            entry_points,
+            descriptor_bindings,
+            push_constants,
+        })
+    }
+
+    /// Every stage an entry point of the module is for.
+    pub fn stages(&self) -> vk::ShaderStageFlags {
+        self.entry_points
+            .iter()
+            .fold(vk::ShaderStageFlags::empty(), |stages, entry_point| {
+                stages | entry_point.stage
+            })
+    }
+
+    pub fn entry_point(&self, name: &str) -> Option<&EntryPoint> {
+        self.entry_points
+            .iter()
+            .find(|entry_point| entry_point.name == name)
+    }
+
+    /// The inputs of the first vertex shader entry point.
+    pub fn vertex_inputs(&self) -> &[InterfaceVariable] {
+        self.entry_points
+            .iter()
+            .find(|entry_point| entry_point.stage == vk::ShaderStageFlags::VERTEX)
+            .map_or(&[], |entry_point| entry_point.inputs.as_slice())
+    }
+
+    /// The outputs of the first fragment shader entry point.
+    pub fn fragment_outputs(&self) -> &[InterfaceVariable] {
+        self.entry_points
+            .iter()
+            .find(|entry_point| entry_point.stage == vk::ShaderStageFlags::FRAGMENT)
+            .map_or(&[], |entry_point| entry_point.outputs.as_slice())
+    }
+}
+
+impl EntryPoint {
+    /// Whether the entry point uses the descriptor at `set` and `binding`, or `None` if the module
+    /// doesn't say.
+    pub fn uses_descriptor(&self, set: u32, binding: u32) -> Option<bool> {
+        self.descriptors
+            .as_ref()
+            .map(|descriptors| descriptors.contains(&(set, binding)))
+    }
+}
+
+impl ReflectedLayout {
+    /// Merges the declarations of every shader. A binding declared by several stages has to have the
+    /// same type and count in all of them. Push constant members of different stages that share a name
+    /// or overlapping bytes have to be the same member: same name, type, offset and size.
+    pub fn merge(shaders: &[&ShaderReflection]) -> anyhow::Result<Self> {
+        let mut bindings = BTreeMap::<(u32, u32), (vk::DescriptorSetLayoutBinding, String)>::new();
+        let mut push_constant_members = Vec::<(&BlockMember, vk::ShaderStageFlags)>::new();
+        let mut push_constant_ranges = Vec::<(vk::ShaderStageFlags, Range<u32>)>::new();
+        let mut seen_stages = vk::ShaderStageFlags::empty();
+
+        for shader in shaders {
+            let stages = shader.stages();
+            if seen_stages.intersects(stages) {
+                return Err(anyhow!(
+                    "Stage {:?} is provided by more than one shader",
+                    seen_stages & stages
+                ));
+            }
+            seen_stages |= stages;
+
+            for declared in shader.descriptor_bindings.iter() {
+                if declared.count == 0 {
+                    return Err(anyhow!(
+                        "Descriptor set {} binding {} ('{}') is a runtime-sized array, which needs a \
+                         hand-written layout",
+                        declared.set,
+                        declared.binding,
+                        declared.name
+                    ));
+                }
+
+                let (binding, name) = bindings
+                    .entry((declared.set, declared.binding))
+                    .or_insert_with(|| {
+                        (
+                            vk::DescriptorSetLayoutBinding::default()
+                                .binding(declared.binding)
+                                .descriptor_type(declared.descriptor_type)
+                                .descriptor_count(declared.count),
+                            declared.name.clone(),
+                        )
+                    });
+                if binding.descriptor_type != declared.descriptor_type
+                    || binding.descriptor_count != declared.count
+                {
+                    return Err(anyhow!(
+                        "Descriptor set {} binding {} is declared as '{}': {:?}[{}] in {:?} but as \
+                         '{}': {:?}[{}] in {:?}",
+                        declared.set,
+                        declared.binding,
+                        name,
+                        binding.descriptor_type,
+                        binding.descriptor_count,
+                        binding.stage_flags,
+                        declared.name,
+                        declared.descriptor_type,
+                        declared.count,
+                        stages
+                    ));
+                }
+                binding.stage_flags |= stages;
+            }
+
+            let Some(block) = shader.push_constants.as_ref() else {
+                continue;
+            };
+            let members = block
+                .members
+                .iter()
+                .filter(|member| !member.name.is_empty());
+            for member in members.clone() {
+                let conflict = push_constant_members.iter().find(|(other, _)| {
+                    let overlaps = other.offset < member.offset + member.size
+                        && member.offset < other.offset + other.size;
+                    let same = other.name == member.name
+                        && other.type_name == member.type_name
+                        && other.offset == member.offset
+                        && other.size == member.size;
+                    (overlaps || other.name == member.name) && !same
+                });
+                if let Some((other, other_stages)) = conflict {
+                    return Err(anyhow!(
+                        "Push constant '{}': {} at bytes {}..{} in {:?} conflicts with '{}': {} at \
+                         bytes {}..{} in {:?}",
+                        other.name,
+                        other.type_name,
+                        other.offset,
+                        other.offset + other.size,
+                        other_stages,
+                        member.name,
+                        member.type_name,
+                        member.offset,
+                        member.offset + member.size,
+                        stages
+                    ));
+                }
+            }
+            push_constant_members.extend(members.map(|member| (member, stages)));
+
+            // every stage may only appear in one range, so stages using the same bytes share one
+            let start = block
+                .members
+                .iter()
+                .map(|member| member.offset)
+                .min()
+                .unwrap_or(0);
+            match push_constant_ranges
+                .iter_mut()
+                .find(|(_, range)| *range == (start..block.size))
+            {
+                Some((range_stages, _)) => *range_stages |= stages,
+                None => push_constant_ranges.push((stages, start..block.size)),
+            }
+        }
+
+        let set_count = bindings.keys().map(|(set, _)| set + 1).max().unwrap_or(0);
+        let mut sets = vec![Vec::new(); set_count as usize];
+        for ((set, _), (binding, _)) in bindings {
+            sets[set as usize].push(binding);
+        }
+
+        Ok(Self {
+            sets,
+            push_constant_ranges,
+        })
+    }
+}
+
+impl Module {
+    fn parse(code: &[u32]) -> anyhow::Result<Self> {
+        if code.len() < 5 {
+            return Err(anyhow!("SPIR-V module is too short to have a header"));
+        }
+        if code[0] != spirv::MAGIC_NUMBER {
+            return Err(anyhow!(
+                "Not a SPIR-V module, magic number is {:#x}",
+                code[0]
+            ));
+        }
+
+        let mut module = Self {
+            version: code[1],
+            ..Self::default()
+        };
+        let mut words = &code[5..];
+        while let Some(&first) = words.first() {
+            let word_count = (first >> 16) as usize;
+            if word_count == 0 || word_count > words.len() {
+                return Err(anyhow!(
+                    "Malformed SPIR-V instruction with word count {} at word {}",
+                    word_count,
+                    code.len() - words.len()
+                ));
+            }
+
+            if let Some(op) = Op::from_u32(first & 0xFFFF) {
+                module.instruction(op, &words[1..word_count]);
+            }
+            words = &words[word_count..];
+        }
+
+        Ok(module)
+    }
+
+    fn instruction(&mut self, op: Op, operands: &[u32]) {
+        // missing operands read as 0 instead of failing, validation is the driver's job
+        let operand = |index: usize| operands.get(index).copied().unwrap_or(0);
+        let id = operand(0);
+
+        match op {
+            Op::Name => {
+                self.names
+                    .insert(id, parse_string(operands.get(1..).unwrap_or(&[])).0);
+            }
+            Op::MemberName if operands.len() > 2 => {
+                self.member_names
+                    .insert((id, operand(1)), parse_string(&operands[2..]).0);
+            }
+            Op::Decorate => {
+                let decorations = self.decorations.entry(id).or_default();
+                decorate(decorations, operand(1), operand(2));
+            }
+            Op::MemberDecorate => {
+                let decorations = self.member_decorations.entry((id, operand(1))).or_default();
+                decorate(decorations, operand(2), operand(3));
+            }
+            Op::EntryPoint if operands.len() > 2 => {
+                let (name, length) = parse_string(&operands[2..]);
+                if let Some(model) = ExecutionModel::from_u32(id) {
+                    self.entry_points.push(RawEntryPoint {
+                        model,
+                        function: operand(1),
+                        name,
+                        interface: operands[(2 + length).min(operands.len())..].to_vec(),
+                    });
+                }
+            }
+            Op::ExecutionMode if operand(1) == ExecutionMode::LocalSize as u32 => {
+                self.local_sizes
+                    .insert(id, [operand(2), operand(3), operand(4)]);
+            }
+            Op::ExecutionModeId if operand(1) == ExecutionMode::LocalSizeId as u32 => {
+                self.local_size_ids
+                    .insert(id, [operand(2), operand(3), operand(4)]);
+            }
+            Op::TypeBool => {
+                self.types.insert(id, Type::Bool);
+            }
+            Op::TypeInt => {
+                self.types.insert(
+                    id,
+                    Type::Int {
+                        width: operand(1),
+                        signed: operand(2) != 0,
+                    },
+                );
+            }
+            Op::TypeFloat => {
+                self.types.insert(id, Type::Float { width: operand(1) });
+            }
+            Op::TypeVector => {
+                self.types.insert(
+                    id,
+                    Type::Vector {
+                        component: operand(1),
+                        count: operand(2),
+                    },
+                );
+            }
+            Op::TypeMatrix => {
+                self.types.insert(
+                    id,
+                    Type::Matrix {
+                        column: operand(1),
+                        count: operand(2),
+                    },
+                );
+            }
+            Op::TypeImage => {
+                self.types.insert(
+                    id,
+                    Type::Image {
+                        dim: Dim::from_u32(operand(2)),
+                        sampled: operand(6),
+                    },
+                );
+            }
+            Op::TypeSampler => {
+                self.types.insert(id, Type::Sampler);
+            }
+            Op::TypeSampledImage => {
+                self.types.insert(id, Type::SampledImage);
+            }
+            Op::TypeAccelerationStructureKHR => {
+                self.types.insert(id, Type::AccelerationStructure);
+            }
+            Op::TypeArray => {
+                self.types.insert(
+                    id,
+                    Type::Array {
+                        element: operand(1),
+                        length: operand(2),
+                    },
+                );
+            }
+            Op::TypeRuntimeArray => {
+                self.types.insert(
+                    id,
+                    Type::RuntimeArray {
+                        element: operand(1),
+                    },
+                );
+            }
+            Op::TypeStruct => {
+                self.types.insert(
+                    id,
+                    Type::Struct {
+                        members: operands[1.min(operands.len())..].to_vec(),
+                    },
+                );
+            }
+            Op::TypePointer => {
+                self.types.insert(
+                    id,
+                    Type::Pointer {
+                        pointee: operand(2),
+                    },
+                );
+            }
+            // 64-bit constants only keep their low word, which is plenty for array lengths
+            Op::Constant | Op::SpecConstant => {
+                self.constants.insert(operand(1), operand(2));
+            }
+            Op::Variable => {
+                if let Some(storage_class) = StorageClass::from_u32(operand(2)) {
+                    self.variables.push(Variable {
+                        id: operand(1),
+                        pointer_type: id,
+                        storage_class,
+                    });
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn constant(&self, id: u32) -> u32 {
+        self.constants.get(&id).copied().unwrap_or(0)
+    }
+
+    fn name(&self, id: u32) -> String {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn pointee(&self, pointer_type: u32) -> Option<u32> {
+        match self.types.get(&pointer_type)? {
+            Type::Pointer { pointee } => Some(*pointee),
+            _ => None,
+        }
+    }
+
+    fn decorations(&self, id: u32) -> Option<&Decorations> {
+        self.decorations.get(&id)
+    }
+
+    fn member_decorations(&self, id: u32, member: u32) -> Option<&Decorations> {
+        self.member_decorations.get(&(id, member))
+    }
+
+    /// Strips array types, returning the element type and the total number of elements (0 if any of
+    /// the arrays is runtime-sized).
+    fn strip_arrays(&self, mut ty: u32) -> (u32, u32) {
+        let mut count = 1;
+        loop {
+            match self.types.get(&ty) {
+                Some(Type::Array { element, length }) => {
+                    count *= self.constant(*length);
+                    ty = *element;
+                }
+                Some(Type::RuntimeArray { element }) => {
+                    count = 0;
+                    ty = *element;
+                }
+                _ => return (ty, count),
+            }
+        }
+    }
+
+    fn is_built_in(&self, variable: &Variable) -> bool {
+        if self.decorations(variable.id).is_some_and(|d| d.built_in) {
+            return true;
+        }
+
+        // e.g. gl_PerVertex, possibly arrayed for tessellation and geometry shaders
+        let Some(pointee) = self.pointee(variable.pointer_type) else {
+            return false;
+        };
+        let ty = self.strip_arrays(pointee).0;
+        match self.types.get(&ty) {
+            Some(Type::Struct { members }) => (0..members.len() as u32).any(|member| {
+                self.member_decorations(ty, member)
+                    .is_some_and(|d| d.built_in)
+            }),
+            _ => false,
+        }
+    }
+
+    fn interface_variables(
+        &self,
+        entry_point: &RawEntryPoint,
+        storage_class: StorageClass,
+    ) -> Vec<InterfaceVariable> {
+        let mut variables = self
+            .variables
+            .iter()
+            .filter(|variable| {
+                variable.storage_class == storage_class
+                    && entry_point.interface.contains(&variable.id)
+                    && !self.is_built_in(variable)
+            })
+            .filter_map(|variable| {
+                let decorations = self.decorations(variable.id)?;
+                let ty = self.pointee(variable.pointer_type)?;
+                Some(InterfaceVariable {
+                    name: self.name(variable.id),
+                    location: decorations.location?,
+                    component: decorations.component.unwrap_or(0),
+                    locations: self.locations(ty),
+                    index: decorations.index.unwrap_or(0),
+                    format: self.format(self.strip_arrays(ty).0),
+                })
+            })
+            .collect::<Vec<_>>();
+        variables.sort_by_key(|variable| (variable.location, variable.component));
+        variables
+    }
+
+    fn descriptor_binding(&self, variable: &Variable) -> Option<DescriptorBinding> {
+        let decorations = self.decorations(variable.id)?;
+        let (set, binding) = (decorations.set.unwrap_or(0), decorations.binding?);
+        let (ty, count) = self.strip_arrays(self.pointee(variable.pointer_type)?);
+        let type_decorations = self.decorations(ty);
+
+        let descriptor_type = match (variable.storage_class, self.types.get(&ty)?) {
+            (StorageClass::UniformConstant, Type::Sampler) => vk::DescriptorType::SAMPLER,
+            (StorageClass::UniformConstant, Type::SampledImage) => {
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER
+            }
+            (StorageClass::UniformConstant, Type::Image { dim, sampled }) => match (dim, sampled) {
+                (Some(Dim::DimBuffer), 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (Some(Dim::DimBuffer), _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (Some(Dim::DimSubpassData), _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            (StorageClass::UniformConstant, Type::AccelerationStructure) => {
+                vk::DescriptorType::ACCELERATION_STRUCTURE_KHR
+            }
+            (StorageClass::Uniform, Type::Struct { .. })
+                if type_decorations.is_some_and(|d| d.buffer_block) =>
+            {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            (StorageClass::Uniform, Type::Struct { .. }) => vk::DescriptorType::UNIFORM_BUFFER,
+            (StorageClass::StorageBuffer, Type::Struct { .. }) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            _ => return None,
+        };
+
+        let name = self.name(variable.id);
+        Some(DescriptorBinding {
+            set,
+            binding,
+            name: if name.is_empty() { self.name(ty) } else { name },
+            descriptor_type,
+            count,
+            block_size: matches!(
+                descriptor_type,
+                vk::DescriptorType::UNIFORM_BUFFER | vk::DescriptorType::STORAGE_BUFFER
+            )
+            .then(|| self.size(ty, None)),
+        })
+    }
+
+    fn block_layout(&self, ty: u32) -> Option<BlockLayout> {
+        let Type::Struct { members } = self.types.get(&ty)? else {
+            return None;
+        };
+
+        let members = members
+            .iter()
+            .enumerate()
+            .map(|(index, member)| {
+                let decorations = self.member_decorations(ty, index as u32);
+                BlockMember {
+                    name: self
+                        .member_names
+                        .get(&(ty, index as u32))
+                        .cloned()
+                        .unwrap_or_default(),
+                    type_name: self.type_name(*member, decorations),
+                    offset: decorations.and_then(|d| d.offset).unwrap_or(0),
+                    size: self.size(*member, decorations),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Some(BlockLayout {
+            name: self.name(ty),
+            size: members
+                .iter()
+                .map(|member| member.offset + member.size)
+                .max()
+                .unwrap_or(0),
+            members,
+        })
+    }
+
+    /// The size of a type in a block with explicit layout. `member` holds the decorations of the struct
+    /// member the type belongs to, which carry the matrix layout.
+    fn size(&self, ty: u32, member: Option<&Decorations>) -> u32 {
+        match self.types.get(&ty) {
+            Some(Type::Bool) => 4,
+            Some(Type::Int { width, .. } | Type::Float { width }) => width / 8,
+            Some(Type::Vector { component, count }) => self.size(*component, None) * count,
+            Some(Type::Matrix { column, count }) => {
+                let rows = match self.types.get(column) {
+                    Some(Type::Vector { count, .. }) => *count,
+                    _ => 1,
+                };
+                match member.and_then(|d| d.matrix_stride) {
+                    Some(stride) if member.is_some_and(|d| d.row_major) => stride * rows,
+                    Some(stride) => stride * count,
+                    None => self.size(*column, None) * count,
+                }
+            }
+            Some(Type::Array { element, length }) => {
+                let stride = self
+                    .decorations(ty)
+                    .and_then(|d| d.array_stride)
+                    .unwrap_or_else(|| self.size(*element, member));
+                stride * self.constant(*length)
+            }
+            Some(Type::Struct { members }) => members
+                .iter()
+                .enumerate()
+                .map(|(index, member)| {
+                    let decorations = self.member_decorations(ty, index as u32);
+                    decorations.and_then(|d| d.offset).unwrap_or(0)
+                        + self.size(*member, decorations)
+                })
+                .max()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// Names a block member's type, e.g. to tell members of different stages sharing bytes apart.
+    fn type_name(&self, ty: u32, member: Option<&Decorations>) -> String {
+        // the name of a scalar type and the prefix of vectors and matrices of it
+        let scalar = |ty: u32| -> (String, String) {
+            match self.types.get(&ty) {
+                Some(Type::Bool) => ("bool".to_string(), "b".to_string()),
+                Some(Type::Float { width: 32 }) => ("float".to_string(), String::new()),
+                Some(Type::Float { width: 64 }) => ("double".to_string(), "d".to_string()),
+                Some(Type::Float { width }) => (format!("float{}_t", width), format!("f{}", width)),
+                Some(Type::Int { width, signed }) => {
+                    let (name, prefix) = if *signed { ("int", "i") } else { ("uint", "u") };
+                    if *width == 32 {
+                        (name.to_string(), prefix.to_string())
+                    } else {
+                        (
+                            format!("{}{}_t", name, width),
+                            format!("{}{}", prefix, width),
+                        )
+                    }
+                }
+                _ => ("?".to_string(), "?".to_string()),
+            }
+        };
+
+        match self.types.get(&ty) {
+            Some(Type::Vector { component, count }) => {
+                format!("{}vec{}", scalar(*component).1, count)
+            }
+            Some(Type::Matrix { column, count }) => {
+                let (prefix, rows) = match self.types.get(column) {
+                    Some(Type::Vector { component, count }) => (scalar(*component).1, *count),
+                    _ => ("?".to_string(), 1),
+                };
+                let shape = if rows == *count {
+                    format!("{}", rows)
+                } else {
+                    format!("{}x{}", count, rows)
+                };
+                let layout = if member.is_some_and(|d| d.row_major) {
+                    "row_major "
+                } else {
+                    ""
+                };
+                format!("{}{}mat{}", layout, prefix, shape)
+            }
+            Some(Type::Array { element, length }) => format!(
+                "{}[{}]",
+                self.type_name(*element, member),
+                self.constant(*length)
+            ),
+            Some(Type::RuntimeArray { element }) => {
+                format!("{}[]", self.type_name(*element, member))
+            }
+            Some(Type::Struct { .. }) => match self.name(ty) {
+                name if name.is_empty() => "struct".to_string(),
+                name => name,
+            },
+            _ => scalar(ty).0,
+        }
+    }
+
+    /// The number of interface locations a type takes up. 64-bit vectors with more than 2 components
+    /// take up two.
+    fn locations(&self, ty: u32) -> u32 {
+        match self.types.get(&ty) {
+            Some(Type::Vector { component, count }) if *count > 2 => {
+                match self.types.get(component) {
+                    Some(Type::Float { width: 64 } | Type::Int { width: 64, .. }) => 2,
+                    _ => 1,
+                }
+            }
+            Some(Type::Matrix { column, count }) => self.locations(*column) * count,
+            Some(Type::Array { element, length }) => {
+                self.locations(*element) * self.constant(*length)
+            }
+            Some(Type::Struct { members }) => {
+                members.iter().map(|member| self.locations(*member)).sum()
+            }
+            _ => 1,
+        }
+    }
+
+    fn format(&self, ty: u32) -> vk::Format {
+        let (component, count) = match self.types.get(&ty) {
+            Some(Type::Vector { component, count }) => (*component, *count),
+            _ => (ty, 1),
+        };
+        if !(1..=4).contains(&count) {
+            return vk::Format::UNDEFINED;
+        }
+
+        use vk::Format as F;
+        let formats = match self.types.get(&component) {
+            Some(Type::Float { width: 16 }) => [
+                F::R16_SFLOAT,
+                F::R16G16_SFLOAT,
+                F::R16G16B16_SFLOAT,
+                F::R16G16B16A16_SFLOAT,
+            ],
+            Some(Type::Float { width: 32 }) => [
+                F::R32_SFLOAT,
+                F::R32G32_SFLOAT,
+                F::R32G32B32_SFLOAT,
+                F::R32G32B32A32_SFLOAT,
+            ],
+            Some(Type::Float { width: 64 }) => [
+                F::R64_SFLOAT,
+                F::R64G64_SFLOAT,
+                F::R64G64B64_SFLOAT,
+                F::R64G64B64A64_SFLOAT,
+            ],
+            Some(Type::Int {
+                width: 16,
+                signed: true,
+            }) => [
+                F::R16_SINT,
+                F::R16G16_SINT,
+                F::R16G16B16_SINT,
+                F::R16G16B16A16_SINT,
+            ],
+            Some(Type::Int {
+                width: 16,
+                signed: false,
+            }) => [
+                F::R16_UINT,
+                F::R16G16_UINT,
+                F::R16G16B16_UINT,
+                F::R16G16B16A16_UINT,
+            ],
+            Some(Type::Int {
+                width: 32,
+                signed: true,
+            }) => [
+                F::R32_SINT,
+                F::R32G32_SINT,
+                F::R32G32B32_SINT,
+                F::R32G32B32A32_SINT,
+            ],
+            Some(Type::Int {
+                width: 32,
+                signed: false,
+            }) => [
+                F::R32_UINT,
+                F::R32G32_UINT,
+                F::R32G32B32_UINT,
+                F::R32G32B32A32_UINT,
+            ],
+            Some(Type::Int {
+                width: 64,
+                signed: true,
+            }) => [
+                F::R64_SINT,
+                F::R64G64_SINT,
+                F::R64G64B64_SINT,
+                F::R64G64B64A64_SINT,
+            ],
+            Some(Type::Int {
+                width: 64,
+                signed: false,
+            }) => [
+                F::R64_UINT,
+                F::R64G64_UINT,
+                F::R64G64B64_UINT,
+                F::R64G64B64A64_UINT,
+            ],
+            _ => return vk::Format::UNDEFINED,
+        };
+        formats[count as usize - 1]
+    }
+}
+
+fn decorate(decorations: &mut Decorations, decoration: u32, value: u32) {
+    match Decoration::from_u32(decoration) {
+        Some(Decoration::DescriptorSet) => decorations.set = Some(value),
+        Some(Decoration::Binding) => decorations.binding = Some(value),
+        Some(Decoration::Location) => decorations.location = Some(value),
+        Some(Decoration::Component) => decorations.component = Some(value),
+        Some(Decoration::Index) => decorations.index = Some(value),
+        Some(Decoration::Offset) => decorations.offset = Some(value),
+        Some(Decoration::ArrayStride) => decorations.array_stride = Some(value),
+        Some(Decoration::MatrixStride) => decorations.matrix_stride = Some(value),
+        Some(Decoration::RowMajor) => decorations.row_major = true,
+        Some(Decoration::BuiltIn) => decorations.built_in = true,
+        Some(Decoration::BufferBlock) => decorations.buffer_block = true,
+        _ => (),
+    }
+}
+
+/// Decodes a nul-terminated literal string, returning it and the number of words it took up.
+fn parse_string(words: &[u32]) -> (String, usize) {
+    let mut bytes = Vec::new();
+    for (index, word) in words.iter().enumerate() {
+        for byte in word.to_le_bytes() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), index + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+fn stage_flags(model: ExecutionModel) -> vk::ShaderStageFlags {
+    match model {
+        ExecutionModel::Vertex => vk::ShaderStageFlags::VERTEX,
+        ExecutionModel::TessellationControl => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        ExecutionModel::TessellationEvaluation => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        ExecutionModel::Geometry => vk::ShaderStageFlags::GEOMETRY,
+        ExecutionModel::Fragment => vk::ShaderStageFlags::FRAGMENT,
+        ExecutionModel::GLCompute | ExecutionModel::Kernel => vk::ShaderStageFlags::COMPUTE,
+        ExecutionModel::TaskNV | ExecutionModel::TaskEXT => vk::ShaderStageFlags::TASK_EXT,
+        ExecutionModel::MeshNV | ExecutionModel::MeshEXT => vk::ShaderStageFlags::MESH_EXT,
+        ExecutionModel::RayGenerationNV => vk::ShaderStageFlags::RAYGEN_KHR,
+        ExecutionModel::IntersectionNV => vk::ShaderStageFlags::INTERSECTION_KHR,
+        ExecutionModel::AnyHitNV => vk::ShaderStageFlags::ANY_HIT_KHR,
+        ExecutionModel::ClosestHitNV => vk::ShaderStageFlags::CLOSEST_HIT_KHR,
+        ExecutionModel::MissNV => vk::ShaderStageFlags::MISS_KHR,
+        ExecutionModel::CallableNV => vk::ShaderStageFlags::CALLABLE_KHR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflect(
+        source: &str,
+        kind: shaderc::ShaderKind,
+        language: shaderc::SourceLanguage,
+        entry_point: &str,
+        target: Option<shaderc::EnvVersion>,
+    ) -> ShaderReflection {
+        let compiler = shaderc::Compiler::new().unwrap();
+        let mut options = shaderc::CompileOptions::new().unwrap();
+        options.set_source_language(language);
+        if let Some(target) = target {
+            options.set_target_env(shaderc::TargetEnv::Vulkan, target as u32);
+        }
+        let spirv = compiler
+            .compile_into_spirv(source, kind, "test", entry_point, Some(&options))
+            .unwrap();
+        ShaderReflection::new(spirv.as_binary()).unwrap()
+    }
+
+    fn reflect_glsl(source: &str, kind: shaderc::ShaderKind) -> ShaderReflection {
+        reflect(source, kind, shaderc::SourceLanguage::GLSL, "main", None)
+    }
+
+    fn interface(variables: &[InterfaceVariable]) -> Vec<(&str, u32, u32, u32, vk::Format)> {
+        variables
+            .iter()
+            .map(|v| {
+                (
+                    v.name.as_str(),
+                    v.location,
+                    v.component,
+                    v.locations,
+                    v.format,
+                )
+            })
+            .collect()
+    }
+
+    fn bindings(
+        reflection: &ShaderReflection,
+    ) -> Vec<(u32, u32, &str, vk::DescriptorType, u32, Option<u32>)> {
+        reflection
+            .descriptor_bindings
+            .iter()
+            .map(|b| {
+                (
+                    b.set,
+                    b.binding,
+                    b.name.as_str(),
+                    b.descriptor_type,
+                    b.count,
+                    b.block_size,
+                )
+            })
+            .collect()
+    }
+
+    const VERTEX: &str = r#"
+        #version 450
+        layout(location = 0) in vec3 position;
+        layout(location = 1) in ivec2 bone_ids;
+        layout(location = 2) in uint flags;
+        layout(location = 3) in mat2 rotation;
+        layout(location = 0) out vec2 uv;
+        layout(location = 1, component = 2) out float fade;
+
+        layout(push_constant) uniform Push {
+            mat4 mvp;
+            layout(row_major) mat4x3 rows;
+            vec4 tint[2];
+            float scale;
+        } pc;
+
+        void main() {
+            vec3 moved = pc.rows * vec4(position, 1.0) + vec3(bone_ids, flags);
+            uv = rotation * moved.xy + pc.tint[1].xy;
+            fade = pc.tint[0].w * pc.scale;
+            gl_Position = pc.mvp * vec4(moved, 1.0);
+        }
+    "#;
+
+    #[test]
+    fn reflects_vertex_interface() {
+        let reflection = reflect_glsl(VERTEX, shaderc::ShaderKind::Vertex);
+
+        assert_eq!(reflection.entry_points.len(), 1);
+        let entry_point = &reflection.entry_points[0];
+        assert_eq!(entry_point.name, "main");
+        assert_eq!(entry_point.stage, vk::ShaderStageFlags::VERTEX);
+        assert_eq!(entry_point.local_size, None);
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::VERTEX);
+
+        assert_eq!(
+            interface(reflection.vertex_inputs()),
+            vec![
+                ("position", 0, 0, 1, vk::Format::R32G32B32_SFLOAT),
+                ("bone_ids", 1, 0, 1, vk::Format::R32G32_SINT),
+                ("flags", 2, 0, 1, vk::Format::R32_UINT),
+                ("rotation", 3, 0, 2, vk::Format::UNDEFINED),
+            ]
+        );
+        // gl_Position is a built-in and left out
+        assert_eq!(
+            interface(&entry_point.outputs),
+            vec![
+                ("uv", 0, 0, 1, vk::Format::R32G32_SFLOAT),
+                ("fade", 1, 2, 1, vk::Format::R32_SFLOAT),
+            ]
+        );
+        assert!(reflection.descriptor_bindings.is_empty());
+    }
+
+    #[test]
+    fn reflects_push_constant_layout() {
+        let reflection = reflect_glsl(VERTEX, shaderc::ShaderKind::Vertex);
+        let push_constants = reflection.push_constants.unwrap();
+
+        assert_eq!(push_constants.name, "Push");
+        let members = push_constants
+            .members
+            .iter()
+            .map(|m| (m.name.as_str(), m.type_name.as_str(), m.offset, m.size))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            vec![
+                ("mvp", "mat4", 0, 64),
+                // three rows of four floats
+                ("rows", "row_major mat4x3", 64, 48),
+                ("tint", "vec4[2]", 112, 32),
+                ("scale", "float", 144, 4),
+            ]
+        );
+        assert_eq!(push_constants.size, 148);
+    }
+
+    #[test]
+    fn reflects_fragment_outputs() {
+        let reflection = reflect_glsl(
+            r#"
+            #version 450
+            layout(location = 0) in vec2 uv;
+            layout(location = 0) out vec4 color;
+            layout(location = 1) out uvec2 ids;
+            layout(location = 2) out vec4 layers[2];
+            layout(set = 0, binding = 0) uniform sampler2D albedo;
+
+            void main() {
+                color = texture(albedo, uv);
+                ids = uvec2(gl_FragCoord.xy);
+                layers[0] = color;
+                layers[1] = color.wzyx;
+            }
+            "#,
+            shaderc::ShaderKind::Fragment,
+        );
+
+        assert_eq!(reflection.stages(), vk::ShaderStageFlags::FRAGMENT);
+        assert_eq!(
+            interface(reflection.fragment_outputs()),
+            vec![
+                ("color", 0, 0, 1, vk::Format::R32G32B32A32_SFLOAT),
+                ("ids", 1, 0, 1, vk::Format::R32G32_UINT),
+                ("layers", 2, 0, 2, vk::Format::R32G32B32A32_SFLOAT),
+            ]
+        );
+        assert_eq!(
+            bindings(&reflection),
+            vec![(
+                0,
+                0,
+                "albedo",
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                1,
+                None
+            )]
+        );
+    }
+
+    #[test]
+    fn reflects_dual_source_outputs_and_used_descriptors() {
+        const SOURCE: &str = r#"
+            #version 450
+            layout(location = 0) in vec2 uv;
+            layout(location = 0, index = 0) out vec4 color;
+            layout(location = 0, index = 1) out vec4 blend;
+            layout(location = 1) in flat dvec3 weights;
+            layout(set = 0, binding = 0) uniform sampler2D used;
+            layout(set = 0, binding = 1) uniform sampler2D unused;
+
+            void main() {
+                color = texture(used, uv) * float(weights.z);
+                blend = color.wzyx;
+            }
+        "#;
+
+        let reflection = reflect(
+            SOURCE,
+            shaderc::ShaderKind::Fragment,
+            shaderc::SourceLanguage::GLSL,
+            "main",
+            Some(shaderc::EnvVersion::Vulkan1_2),
+        );
+        let entry_point = &reflection.entry_points[0];
+        assert_eq!(
+            entry_point
+                .outputs
+                .iter()
+                .map(|v| (v.name.as_str(), v.location, v.index))
+                .collect::<Vec<_>>(),
+            vec![("color", 0, 0), ("blend", 0, 1)]
+        );
+        assert_eq!(
+            interface(&entry_point.inputs),
+            vec![
+                ("uv", 0, 0, 1, vk::Format::R32G32_SFLOAT),
+                ("weights", 1, 0, 2, vk::Format::R64G64B64_SFLOAT),
+            ]
+        );
+
+        // both are declared, but only SPIR-V 1.4 and later says which ones the entry point uses
+        assert_eq!(reflection.descriptor_bindings.len(), 2);
+        assert_eq!(entry_point.descriptors, Some(vec![(0, 0)]));
+        assert_eq!(entry_point.uses_descriptor(0, 1), Some(false));
+
+        let reflection = reflect_glsl(SOURCE, shaderc::ShaderKind::Fragment);
+        assert_eq!(reflection.entry_points[0].descriptors, None);
+        assert_eq!(reflection.entry_points[0].uses_descriptor(0, 0), None);
+    }
+
+    const COMPUTE: &str = r#"
+        #version 450
+        #extension GL_EXT_nonuniform_qualifier : require
+        layout(local_size_x = 8, local_size_y = 4) in;
+
+        layout(set = 0, binding = 0) buffer Particles {
+            vec4 header;
+            vec4 positions[];
+        } particles;
+        layout(set = 0, binding = 1, rgba8) uniform writeonly image2D target;
+        layout(set = 0, binding = 2) uniform sampler2D lights[4];
+        layout(set = 1, binding = 0) uniform texture2D textures[];
+        layout(set = 1, binding = 1) uniform sampler samplers[2];
+        layout(set = 2, binding = 3) uniform Params {
+            uint count;
+        };
+        layout(set = 2, binding = 4) uniform samplerBuffer texels;
+
+        void main() {
+            uint i = gl_GlobalInvocationID.x;
+            vec4 color = texture(lights[i % 4], vec2(0.5))
+                + texture(sampler2D(textures[nonuniformEXT(i)], samplers[i % 2]), vec2(0.5))
+                + texelFetch(texels, int(i));
+            particles.positions[i] += particles.header * float(count) + color;
+            imageStore(target, ivec2(i, 0), color);
+        }
+    "#;
+
+    fn expected_compute_bindings()
+    -> Vec<(u32, u32, &'static str, vk::DescriptorType, u32, Option<u32>)> {
+        use vk::DescriptorType as D;
+        vec![
+            // the runtime-sized array doesn't count towards the block size
+            (0, 0, "particles", D::STORAGE_BUFFER, 1, Some(16)),
+            (0, 1, "target", D::STORAGE_IMAGE, 1, None),
+            (0, 2, "lights", D::COMBINED_IMAGE_SAMPLER, 4, None),
+            (1, 0, "textures", D::SAMPLED_IMAGE, 0, None),
+            (1, 1, "samplers", D::SAMPLER, 2, None),
+            // anonymous blocks are named after their type
+            (2, 3, "Params", D::UNIFORM_BUFFER, 1, Some(4)),
+            (2, 4, "texels", D::UNIFORM_TEXEL_BUFFER, 1, None),
+        ]
+    }
+
+    #[test]
+    fn reflects_compute_descriptors_and_local_size() {
+        // SPIR-V 1.0 declares storage buffers as `BufferBlock` uniforms
+        let reflection = reflect_glsl(COMPUTE, shaderc::ShaderKind::Compute);
+
+        assert_eq!(
+            reflection.entry_points[0].stage,
+            vk::ShaderStageFlags::COMPUTE
+        );
+        assert_eq!(reflection.entry_points[0].local_size, Some([8, 4, 1]));
+        assert_eq!(bindings(&reflection), expected_compute_bindings());
+        assert!(reflection.push_constants.is_none());
+    }
+
+    #[test]
+    fn reflects_storage_buffer_class() {
+        // SPIR-V 1.3 uses the `StorageBuffer` storage class instead
+        let reflection = reflect(
+            COMPUTE,
+            shaderc::ShaderKind::Compute,
+            shaderc::SourceLanguage::GLSL,
+            "main",
+            Some(shaderc::EnvVersion::Vulkan1_1),
+        );
+
+        assert_eq!(reflection.entry_points[0].local_size, Some([8, 4, 1]));
+        assert_eq!(bindings(&reflection), expected_compute_bindings());
+    }
+
+    #[test]
+    fn reflects_hlsl() {
+        let reflection = reflect(
+            r#"
+            cbuffer Material : register(b0, space1) {
+                float4 tint;
+            };
+            Texture2D albedo : register(t1);
+            SamplerState linear_sampler : register(s2);
+            RWStructuredBuffer<uint> counters : register(u3);
+
+            float4 PSMain(float2 uv : TEXCOORD0) : SV_Target0 {
+                InterlockedAdd(counters[0], 1);
+                return albedo.Sample(linear_sampler, uv) * tint;
+            }
+            "#,
+            shaderc::ShaderKind::Fragment,
+            shaderc::SourceLanguage::HLSL,
+            "PSMain",
+            None,
+        );
+
+        assert_eq!(reflection.entry_points.len(), 1);
+        assert_eq!(reflection.entry_points[0].name, "PSMain");
+        assert_eq!(
+            reflection.entry_points[0].stage,
+            vk::ShaderStageFlags::FRAGMENT
+        );
+        assert!(reflection.entry_point("main").is_none());
+        assert_eq!(
+            reflection
+                .fragment_outputs()
+                .iter()
+                .map(|v| (v.location, v.format))
+                .collect::<Vec<_>>(),
+            vec![(0, vk::Format::R32G32B32A32_SFLOAT)]
+        );
+
+        let bindings = reflection
+            .descriptor_bindings
+            .iter()
+            .map(|b| (b.set, b.binding, b.descriptor_type, b.count))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            bindings,
+            vec![
+                (0, 1, vk::DescriptorType::SAMPLED_IMAGE, 1),
+                (0, 2, vk::DescriptorType::SAMPLER, 1),
+                (0, 3, vk::DescriptorType::STORAGE_BUFFER, 1),
+                (1, 0, vk::DescriptorType::UNIFORM_BUFFER, 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_modules() {
+        assert!(ShaderReflection::new(&[]).is_err());
+        assert!(ShaderReflection::new(&[0xdeadbeef, 0, 0, 0, 0]).is_err());
+        // an instruction claiming more words than are left
+        assert!(
+            ShaderReflection::new(&[spirv::MAGIC_NUMBER, 0x10000, 0, 1, 0, 0x0005_0000]).is_err()
+        );
+    }
+
+    fn push_constants(
+        stage: vk::ShaderStageFlags,
+        members: &[(&str, &str, u32, u32)],
+    ) -> ShaderReflection {
+        let members = members
+            .iter()
+            .map(|&(name, type_name, offset, size)| BlockMember {
+                name: name.to_string(),
+                type_name: type_name.to_string(),
+                offset,
+                size,
+            })
+            .collect::<Vec<_>>();
+        ShaderReflection {
+            entry_points: vec![EntryPoint {
+                name: "main".to_string(),
+                stage,
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                local_size: None,
+                descriptors: None,
+            }],
+            descriptor_bindings: Vec::new(),
+            push_constants: Some(BlockLayout {
+                name: "Push".to_string(),
+                size: members.iter().map(|m| m.offset + m.size).max().unwrap_or(0),
+                members,
+            }),
+        }
+    }
+
+    #[test]
+    fn merges_push_constants_shared_between_stages() {
+        let vertex = push_constants(
+            vk::ShaderStageFlags::VERTEX,
+            &[("mvp", "mat4", 0, 64), ("tint", "vec4", 64, 16)],
+        );
+        let fragment = push_constants(
+            vk::ShaderStageFlags::FRAGMENT,
+            &[("tint", "vec4", 64, 16), ("exposure", "float", 80, 4)],
+        );
+
+        let layout = ReflectedLayout::merge(&[&vertex, &fragment]).unwrap();
+        assert_eq!(
+            layout.push_constant_ranges,
+            vec![
+                (vk::ShaderStageFlags::VERTEX, 0..80),
+                (vk::ShaderStageFlags::FRAGMENT, 64..84),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_conflicting_push_constants() {
+        let vertex = push_constants(
+            vk::ShaderStageFlags::VERTEX,
+            &[("mvp", "mat4", 0, 64), ("tint", "vec4", 64, 16)],
+        );
+        let merge_with = |members: &[(&str, &str, u32, u32)]| {
+            let fragment = push_constants(vk::ShaderStageFlags::FRAGMENT, members);
+            ReflectedLayout::merge(&[&vertex, &fragment])
+                .unwrap_err()
+                .to_string()
+        };
+
+        // different names for overlapping bytes
+        let error = merge_with(&[("color", "vec4", 72, 16)]);
+        assert!(
+            error.contains("'tint'") && error.contains("'color'"),
+            "{}",
+            error
+        );
+        assert!(
+            error.contains("VERTEX") && error.contains("FRAGMENT"),
+            "{}",
+            error
+        );
+
+        // the same name with a different type
+        let error = merge_with(&[("tint", "uvec4", 64, 16)]);
+        assert!(
+            error.contains("vec4") && error.contains("uvec4"),
+            "{}",
+            error
+        );
+
+        // the same name at different bytes
+        let error = merge_with(&[("mvp", "mat4", 16, 64)]);
+        assert!(
+            error.contains("bytes 0..64") && error.contains("bytes 16..80"),
+            "{}",
+            error
+        );
+    }
+}
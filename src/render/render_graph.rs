@@ -0,0 +1,1619 @@
+use crate::render::command_buffer::{
+    CommandRecorder, DynamicRenderingRecorder, GenericCommandRecorder,
+};
+use crate::render::render_target::FrameRenderInfo;
+use crate::render::resource::format_aspect;
+use crate::render::resource_state::{ResourceState, ResourceStateTracker, ResourceUsage};
+use crate::render::validation::AttachmentFormats;
+use crate::render::{RenderSystem, find_memory_type};
+use anyhow::anyhow;
+use ash::vk;
+use ash::vk::{
+    AttachmentLoadOp, AttachmentStoreOp, ClearValue, ImageSubresourceRange, PipelineStageFlags2,
+    RenderingAttachmentInfo, RenderingInfo,
+};
+use std::fmt::Write;
+use std::ops::Range;
+
+const PASS_LABEL_COLOR: [f32; 4] = [0.4, 0.6, 1.0, 1.0];
+
+/// A virtual resource declared on a `RenderGraph`. Only meaningful for the graph that created it.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub struct GraphResource(usize);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TransientImageDescription {
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub samples: vk::SampleCountFlags,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct TransientBufferDescription {
+    pub size: u64,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum GraphResourceDescription {
+    Image(TransientImageDescription),
+    Buffer(TransientBufferDescription),
+    /// A color attachment of the render target, by index into `FrameRenderInfo::color_attachments`.
+    Backbuffer(usize),
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum PhysicalResourceDescription {
+    Image(TransientImageDescription, vk::ImageUsageFlags),
+    Buffer(TransientBufferDescription, vk::BufferUsageFlags),
+}
+
+#[derive(Copy, Clone)]
+pub struct GraphAttachment {
+    pub resource: GraphResource,
+    pub load_op: AttachmentLoadOp,
+    pub store_op: AttachmentStoreOp,
+    pub clear_value: ClearValue,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct PassAccess {
+    pub resource: GraphResource,
+    pub usage: ResourceUsage,
+    pub write: bool,
+}
+
+enum PassExecute<'a> {
+    Commands(Box<dyn FnOnce(&CommandRecorder, &GraphResources) + 'a>),
+    Rendering(Box<dyn FnOnce(&DynamicRenderingRecorder, &GraphResources) + 'a>),
+}
+
+pub struct GraphPass<'a> {
+    name: String,
+    accesses: Vec<PassAccess>,
+    color_attachments: Vec<GraphAttachment>,
+    depth_attachment: Option<GraphAttachment>,
+    side_effects: bool,
+    execute: Option<PassExecute<'a>>,
+}
+
+struct GraphResourceNode {
+    name: String,
+    description: GraphResourceDescription,
+    output: bool,
+}
+
+/// A frame graph: passes declare which resources they read and write, and compiling the graph culls
+/// passes that don't contribute to an output, orders the rest and assigns transient resources to
+/// (possibly shared) physical resources. Executing it then records everything, barriers included.
+///
+/// Transient resources with identical descriptions and disjoint lifetimes share a physical resource, and
+/// physical resources with disjoint lifetimes are placed in shared memory by `TransientResourcePool`.
+pub struct RenderGraph<'a> {
+    passes: Vec<GraphPass<'a>>,
+    resources: Vec<GraphResourceNode>,
+}
+
+/// The result of `RenderGraph::compile`. This is pure data and doesn't touch the device.
+#[derive(Clone, Debug)]
+pub struct CompiledRenderGraph {
+    /// Indices of the passes that survived culling, in execution order.
+    pub order: Vec<usize>,
+    pub culled: Vec<usize>,
+    /// `(from, to, resource)` dependencies between surviving passes.
+    pub edges: Vec<(usize, usize, GraphResource)>,
+    pub physical_resources: Vec<PhysicalResourceDescription>,
+    /// The range of positions in `order` during which each physical resource is occupied. Physical
+    /// resources with disjoint lifetimes may share memory.
+    pub slot_lifetimes: Vec<Range<usize>>,
+    /// Maps every virtual resource to its physical slot. `None` for backbuffers and unused resources.
+    pub resource_slots: Vec<Option<usize>>,
+    /// The range of positions in `order` during which each virtual resource is alive.
+    pub lifetimes: Vec<Option<Range<usize>>>,
+
+    pass_names: Vec<String>,
+    pass_accesses: Vec<Vec<PassAccess>>,
+    resource_names: Vec<String>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum ResolvedResource {
+    Image {
+        image: vk::Image,
+        image_view: vk::ImageView,
+        format: vk::Format,
+        extent: vk::Extent2D,
+        subresource_range: ImageSubresourceRange,
+    },
+    Buffer {
+        buffer: vk::Buffer,
+        size: u64,
+    },
+    Unused,
+}
+
+/// Physical handles for a graph's virtual resources, handed to each pass when it executes.
+pub struct GraphResources {
+    resources: Vec<ResolvedResource>,
+}
+
+enum PhysicalResource {
+    Image {
+        image: vk::Image,
+        image_view: vk::ImageView,
+        description: TransientImageDescription,
+    },
+    Buffer {
+        buffer: vk::Buffer,
+        size: u64,
+    },
+}
+
+/// Owns the physical resources backing a graph's transient resources. Keep one per frame in flight,
+/// since resources are reused from one execution to the next.
+///
+/// Resources are bound to a few large memory blocks, and ones whose lifetimes don't overlap may be bound
+/// to the same memory. Such resources are treated as undefined at the start of their lifetime.
+pub struct TransientResourcePool {
+    descriptions: Vec<PhysicalResourceDescription>,
+    lifetimes: Vec<Range<usize>>,
+    resources: Vec<PhysicalResource>,
+    // whether each resource shares memory with another one
+    aliased: Vec<bool>,
+    memory: Vec<vk::DeviceMemory>,
+    device: ash::Device,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct MemoryRequest {
+    size: u64,
+    alignment: u64,
+    memory_type: u32,
+    // images and buffers get separate blocks so `bufferImageGranularity` never applies
+    image: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct MemoryPlacement {
+    /// `(memory_type, size)` of each block to allocate.
+    blocks: Vec<(u32, u64)>,
+    /// `(block, offset)` of each request.
+    offsets: Vec<(usize, u64)>,
+    aliased: Vec<bool>,
+}
+
+impl<'a> GraphPass<'a> {
+    pub fn read(&mut self, resource: GraphResource, usage: impl Into<ResourceUsage>) -> &mut Self {
+        self.accesses.push(PassAccess {
+            resource,
+            usage: usage.into(),
+            write: false,
+        });
+        self
+    }
+
+    pub fn write(&mut self, resource: GraphResource, usage: impl Into<ResourceUsage>) -> &mut Self {
+        self.accesses.push(PassAccess {
+            resource,
+            usage: usage.into(),
+            write: true,
+        });
+        self
+    }
+
+    pub fn color_attachment(
+        &mut self,
+        resource: GraphResource,
+        load_op: AttachmentLoadOp,
+        clear_value: ClearValue,
+    ) -> &mut Self {
+        self.color_attachments.push(GraphAttachment {
+            resource,
+            load_op,
+            store_op: AttachmentStoreOp::STORE,
+            clear_value,
+        });
+
+        if load_op == AttachmentLoadOp::LOAD {
+            self.read(resource, ResourceUsage::ColorAttachmentReadWrite);
+        }
+        self.write(resource, ResourceUsage::ColorAttachmentReadWrite)
+    }
+
+    pub fn depth_attachment(
+        &mut self,
+        resource: GraphResource,
+        load_op: AttachmentLoadOp,
+        clear_value: ClearValue,
+    ) -> &mut Self {
+        self.depth_attachment = Some(GraphAttachment {
+            resource,
+            load_op,
+            store_op: AttachmentStoreOp::STORE,
+            clear_value,
+        });
+
+        if load_op == AttachmentLoadOp::LOAD {
+            self.read(resource, ResourceUsage::DepthStencilAttachmentWrite);
+        }
+        self.write(resource, ResourceUsage::DepthStencilAttachmentWrite)
+    }
+
+    /// Passes with side effects (e.g. host readback) are never culled.
+    pub fn side_effects(&mut self) -> &mut Self {
+        self.side_effects = true;
+        self
+    }
+
+    pub fn execute(&mut self, f: impl FnOnce(&CommandRecorder, &GraphResources) + 'a) {
+        self.execute = Some(PassExecute::Commands(Box::new(f)));
+    }
+
+    /// The pass is recorded inside a dynamic rendering scope covering its declared attachments.
+    pub fn execute_rendering(
+        &mut self,
+        f: impl FnOnce(&DynamicRenderingRecorder, &GraphResources) + 'a,
+    ) {
+        self.execute = Some(PassExecute::Rendering(Box::new(f)));
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            passes: Vec::new(),
+            resources: Vec::new(),
+        }
+    }
+
+    fn add_resource(
+        &mut self,
+        name: impl Into<String>,
+        description: GraphResourceDescription,
+        output: bool,
+    ) -> GraphResource {
+        self.resources.push(GraphResourceNode {
+            name: name.into(),
+            description,
+            output,
+        });
+        GraphResource(self.resources.len() - 1)
+    }
+
+    pub fn create_image(
+        &mut self,
+        name: impl Into<String>,
+        description: TransientImageDescription,
+    ) -> GraphResource {
+        self.add_resource(name, GraphResourceDescription::Image(description), false)
+    }
+
+    pub fn create_buffer(
+        &mut self,
+        name: impl Into<String>,
+        description: TransientBufferDescription,
+    ) -> GraphResource {
+        self.add_resource(name, GraphResourceDescription::Buffer(description), false)
+    }
+
+    /// Imports a color attachment of the render target. Backbuffers are always graph outputs.
+    pub fn backbuffer(&mut self, index: usize) -> GraphResource {
+        if let Some(existing) = self
+            .resources
+            .iter()
+            .position(|r| r.description == GraphResourceDescription::Backbuffer(index))
+        {
+            return GraphResource(existing);
+        }
+
+        self.add_resource(
+            format!("backbuffer {}", index),
+            GraphResourceDescription::Backbuffer(index),
+            true,
+        )
+    }
+
+    /// Marks a resource as needed after the graph has run, so the passes writing it aren't culled.
+    pub fn mark_output(&mut self, resource: GraphResource) {
+        self.resources[resource.0].output = true;
+    }
+
+    pub fn add_pass(&mut self, name: impl Into<String>) -> &mut GraphPass<'a> {
+        self.passes.push(GraphPass {
+            name: name.into(),
+            accesses: Vec::new(),
+            color_attachments: Vec::new(),
+            depth_attachment: None,
+            side_effects: false,
+            execute: None,
+        });
+        self.passes.last_mut().unwrap()
+    }
+
+    pub fn compile(&self) -> anyhow::Result<CompiledRenderGraph> {
+        let pass_count = self.passes.len();
+        let resource_count = self.resources.len();
+
+        for pass in &self.passes {
+            for access in &pass.accesses {
+                if access.resource.0 >= resource_count {
+                    return Err(anyhow!(
+                        "Pass '{}' uses a resource that doesn't belong to this graph",
+                        pass.name
+                    ));
+                }
+            }
+
+            if let Some(PassExecute::Rendering(_)) = &pass.execute {
+                if pass.color_attachments.is_empty() && pass.depth_attachment.is_none() {
+                    return Err(anyhow!(
+                        "Pass '{}' records rendering commands but declares no attachments",
+                        pass.name
+                    ));
+                }
+            }
+        }
+
+        // dependencies follow declaration order: a read sees the latest earlier write
+        let mut edges = Vec::new();
+        let mut last_writer: Vec<Option<usize>> = vec![None; resource_count];
+        let mut readers_since_write: Vec<Vec<usize>> = vec![Vec::new(); resource_count];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for access in pass.accesses.iter().filter(|a| !a.write) {
+                let resource = access.resource.0;
+                match last_writer[resource] {
+                    Some(writer) if writer != index => edges.push((writer, index, access.resource)),
+                    Some(_) => {}
+                    None => {
+                        if !matches!(
+                            self.resources[resource].description,
+                            GraphResourceDescription::Backbuffer(_)
+                        ) {
+                            return Err(anyhow!(
+                                "Pass '{}' reads '{}' before any pass writes it",
+                                pass.name,
+                                self.resources[resource].name
+                            ));
+                        }
+                    }
+                }
+                readers_since_write[resource].push(index);
+            }
+
+            for access in pass.accesses.iter().filter(|a| a.write) {
+                let resource = access.resource.0;
+                if let Some(writer) = last_writer[resource].filter(|&w| w != index) {
+                    edges.push((writer, index, access.resource));
+                }
+                for &reader in readers_since_write[resource]
+                    .iter()
+                    .filter(|&&r| r != index)
+                {
+                    edges.push((reader, index, access.resource));
+                }
+                readers_since_write[resource].clear();
+                last_writer[resource] = Some(index);
+            }
+        }
+        edges.sort();
+        edges.dedup();
+
+        // cull backwards from outputs and side-effecting passes
+        let mut needed: Vec<bool> = self.resources.iter().map(|r| r.output).collect();
+        let mut kept = vec![false; pass_count];
+        for (index, pass) in self.passes.iter().enumerate().rev() {
+            let contributes = pass.side_effects
+                || pass
+                    .accesses
+                    .iter()
+                    .any(|a| a.write && needed[a.resource.0]);
+            if !contributes {
+                continue;
+            }
+
+            kept[index] = true;
+            // anything this pass overwrites without reading is dead before it
+            for access in pass.accesses.iter().filter(|a| a.write) {
+                needed[access.resource.0] = false;
+            }
+            for access in pass.accesses.iter().filter(|a| !a.write) {
+                needed[access.resource.0] = true;
+            }
+        }
+        edges.retain(|&(from, to, _)| kept[from] && kept[to]);
+
+        // topological order, preferring declaration order among ready passes
+        let mut in_degree = vec![0usize; pass_count];
+        for &(_, to, _) in &edges {
+            in_degree[to] += 1;
+        }
+        let mut order = Vec::with_capacity(pass_count);
+        let mut scheduled = vec![false; pass_count];
+        while let Some(next) =
+            (0..pass_count).find(|&p| kept[p] && !scheduled[p] && in_degree[p] == 0)
+        {
+            scheduled[next] = true;
+            order.push(next);
+            for &(_, to, _) in edges.iter().filter(|(from, _, _)| *from == next) {
+                in_degree[to] -= 1;
+            }
+        }
+        if order.len() != kept.iter().filter(|&&k| k).count() {
+            return Err(anyhow!("Render graph contains a dependency cycle"));
+        }
+        let culled = (0..pass_count).filter(|&p| !kept[p]).collect::<Vec<_>>();
+
+        let mut lifetimes: Vec<Option<Range<usize>>> = vec![None; resource_count];
+        let mut image_usages = vec![vk::ImageUsageFlags::empty(); resource_count];
+        let mut buffer_usages = vec![vk::BufferUsageFlags::empty(); resource_count];
+        for (position, &pass) in order.iter().enumerate() {
+            for access in &self.passes[pass].accesses {
+                let resource = access.resource.0;
+                lifetimes[resource] = Some(match lifetimes[resource].take() {
+                    Some(lifetime) => lifetime.start..position + 1,
+                    None => position..position + 1,
+                });
+                image_usages[resource] |= image_usage_for(access.usage);
+                buffer_usages[resource] |= buffer_usage_for(access.usage);
+            }
+        }
+
+        // greedy reuse of a physical resource with an identical description once its previous occupant is
+        // dead; different descriptions can still share memory, see `place_in_memory`
+        let mut by_first_use = (0..resource_count)
+            .filter(|&r| lifetimes[r].is_some())
+            .collect::<Vec<_>>();
+        by_first_use.sort_by_key(|&r| lifetimes[r].as_ref().unwrap().start);
+
+        let mut physical_resources: Vec<PhysicalResourceDescription> = Vec::new();
+        let mut slot_lifetimes: Vec<Range<usize>> = Vec::new();
+        let mut resource_slots: Vec<Option<usize>> = vec![None; resource_count];
+        for resource in by_first_use {
+            let lifetime = lifetimes[resource].clone().unwrap();
+            let description = match self.resources[resource].description {
+                GraphResourceDescription::Image(description) => {
+                    PhysicalResourceDescription::Image(description, image_usages[resource])
+                }
+                GraphResourceDescription::Buffer(description) => {
+                    PhysicalResourceDescription::Buffer(description, buffer_usages[resource])
+                }
+                GraphResourceDescription::Backbuffer(_) => continue,
+            };
+
+            let reusable = (0..physical_resources.len()).find(|&slot| {
+                slot_lifetimes[slot].end <= lifetime.start
+                    && same_shape(&physical_resources[slot], &description)
+            });
+
+            let slot = match reusable {
+                Some(slot) => {
+                    physical_resources[slot] = merge_usage(&physical_resources[slot], &description);
+                    slot_lifetimes[slot].end = lifetime.end;
+                    slot
+                }
+                None => {
+                    physical_resources.push(description);
+                    slot_lifetimes.push(lifetime);
+                    physical_resources.len() - 1
+                }
+            };
+            resource_slots[resource] = Some(slot);
+        }
+
+        Ok(CompiledRenderGraph {
+            order,
+            culled,
+            edges,
+            physical_resources,
+            slot_lifetimes,
+            resource_slots,
+            lifetimes,
+            pass_names: self.passes.iter().map(|p| p.name.clone()).collect(),
+            pass_accesses: self.passes.iter().map(|p| p.accesses.clone()).collect(),
+            resource_names: self.resources.iter().map(|r| r.name.clone()).collect(),
+        })
+    }
+
+    /// Records the compiled graph into `cmd`. Barriers are derived from `tracker`, which should be the
+    /// same tracker for every execution so transient resource states carry over between frames.
+    pub fn execute(
+        self,
+        compiled: &CompiledRenderGraph,
+        cmd: &CommandRecorder,
+        frame: &FrameRenderInfo,
+        pool: &mut TransientResourcePool,
+        tracker: &mut ResourceStateTracker,
+    ) -> anyhow::Result<()> {
+        pool.prepare(
+            &compiled.physical_resources,
+            &compiled.slot_lifetimes,
+            tracker,
+        )?;
+
+        let mut resources = Vec::with_capacity(self.resources.len());
+        for (index, node) in self.resources.iter().enumerate() {
+            let resolved = match (node.description, compiled.resource_slots[index]) {
+                (GraphResourceDescription::Backbuffer(attachment), _) => {
+                    let Some(attachment) = frame.color_attachments.get(attachment) else {
+                        return Err(anyhow!(
+                            "Render target has no color attachment {}",
+                            attachment
+                        ));
+                    };
+                    let subresource_range = ImageSubresourceRange {
+                        aspect_mask: vk::ImageAspectFlags::COLOR,
+                        base_mip_level: 0,
+                        level_count: 1,
+                        base_array_layer: 0,
+                        layer_count: 1,
+                    };
+                    tracker.register_image(
+                        attachment.image,
+                        vk::ImageAspectFlags::COLOR,
+                        1,
+                        1,
+                        ResourceState::from_external(
+                            attachment.initial_state,
+                            PipelineStageFlags2::TOP_OF_PIPE,
+                        ),
+                    );
+
+                    ResolvedResource::Image {
+                        image: attachment.image,
+                        image_view: attachment.image_view,
+                        format: attachment.format,
+                        extent: frame.extent,
+                        subresource_range,
+                    }
+                }
+                (_, Some(slot)) => pool.resolve(slot),
+                (_, None) => ResolvedResource::Unused,
+            };
+            resources.push(resolved);
+        }
+
+        let resources = GraphResources { resources };
+        let mut passes = self.passes.into_iter().map(Some).collect::<Vec<_>>();
+        for (position, &pass_index) in compiled.order.iter().enumerate() {
+            let pass = passes[pass_index].take().unwrap();
+            pool.begin_lifetimes(&compiled.slot_lifetimes, position, tracker);
+
+            resources.use_accesses(&pass.accesses, tracker);
+            let cmd = cmd.begin_label(&pass.name, PASS_LABEL_COLOR);
+            let cmd = cmd.profile_scope(&pass.name);
+            cmd.resource_barriers(tracker);
+
+            match pass.execute {
+                Some(PassExecute::Commands(f)) => f(&cmd, &resources),
+                Some(PassExecute::Rendering(f)) => {
+                    let attachment_info = |attachment: &GraphAttachment, layout| {
+                        RenderingAttachmentInfo::default()
+                            .image_view(
+                                resources
+                                    .image_view(attachment.resource)
+                                    .unwrap_or_default(),
+                            )
+                            .image_layout(layout)
+                            .load_op(attachment.load_op)
+                            .store_op(attachment.store_op)
+                            .clear_value(attachment.clear_value)
+                    };
+
+                    let color_attachments = pass
+                        .color_attachments
+                        .iter()
+                        .map(|a| attachment_info(a, vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                        .collect::<Vec<_>>();
+                    let depth_attachment = pass.depth_attachment.as_ref().map(|a| {
+                        attachment_info(a, vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    });
+
+                    let extent = pass
+                        .color_attachments
+                        .iter()
+                        .chain(pass.depth_attachment.iter())
+                        .find_map(|a| resources.extent(a.resource))
+                        .unwrap_or(frame.extent);
+
+                    let mut formats = AttachmentFormats {
+                        color: pass
+                            .color_attachments
+                            .iter()
+                            .map(|a| resources.format(a.resource).unwrap_or_default())
+                            .collect(),
+                        ..AttachmentFormats::default()
+                    };
+
+                    let mut rendering_info = RenderingInfo::default()
+                        .render_area(vk::Rect2D::from(extent))
+                        .color_attachments(color_attachments.as_slice())
+                        .layer_count(1)
+                        .view_mask(0);
+                    if let Some(depth_attachment) = depth_attachment.as_ref() {
+                        let depth_format = resources
+                            .format(pass.depth_attachment.unwrap().resource)
+                            .unwrap_or_default();
+                        let aspect = format_aspect(depth_format);
+                        if aspect.contains(vk::ImageAspectFlags::DEPTH) {
+                            rendering_info = rendering_info.depth_attachment(depth_attachment);
+                            formats.depth = depth_format;
+                        }
+                        if aspect.contains(vk::ImageAspectFlags::STENCIL) {
+                            rendering_info = rendering_info.stencil_attachment(depth_attachment);
+                            formats.stencil = depth_format;
+                        }
+                    }
+
+                    let cmd = cmd.begin_rendering_with_formats(rendering_info, &formats);
+                    f(&cmd, &resources);
+                }
+                None => {}
+            }
+        }
+
+        for (index, node) in self.resources.iter().enumerate() {
+            let GraphResourceDescription::Backbuffer(attachment) = node.description else {
+                continue;
+            };
+            let (
+                Some(attachment),
+                ResolvedResource::Image {
+                    image,
+                    subresource_range,
+                    ..
+                },
+            ) = (
+                frame.color_attachments.get(attachment),
+                resources.resources[index],
+            )
+            else {
+                continue;
+            };
+
+            tracker.use_image(
+                image,
+                subresource_range,
+                ResourceState::from_external(
+                    attachment.final_state,
+                    PipelineStageFlags2::BOTTOM_OF_PIPE,
+                ),
+            );
+        }
+        cmd.resource_barriers(tracker);
+
+        for resource in &resources.resources {
+            if let ResolvedResource::Image { image, .. } = resource {
+                if frame.color_attachments.iter().any(|a| a.image == *image) {
+                    tracker.forget_image(*image);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for RenderGraph<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompiledRenderGraph {
+    pub fn pass_name(&self, pass: usize) -> &str {
+        &self.pass_names[pass]
+    }
+
+    /// Dumps the compiled graph in GraphViz `dot` format. Culled passes are drawn dashed and each
+    /// resource is labelled with the physical slot it was assigned.
+    pub fn to_graphviz(&self) -> String {
+        let mut dot = String::new();
+        let _ = writeln!(dot, "digraph render_graph {{");
+        let _ = writeln!(dot, "    rankdir=LR;");
+
+        for (pass, name) in self.pass_names.iter().enumerate() {
+            let position = self.order.iter().position(|&p| p == pass);
+            match position {
+                Some(position) => {
+                    let _ = writeln!(
+                        dot,
+                        "    pass_{} [label=\"{}. {}\", shape=box, style=filled, fillcolor=lightblue];",
+                        pass,
+                        position,
+                        escape(name)
+                    );
+                }
+                None => {
+                    let _ = writeln!(
+                        dot,
+                        "    pass_{} [label=\"{} (culled)\", shape=box, style=dashed, color=gray];",
+                        pass,
+                        escape(name)
+                    );
+                }
+            }
+        }
+
+        for (resource, name) in self.resource_names.iter().enumerate() {
+            let slot = match self.resource_slots[resource] {
+                Some(slot) => format!("slot {}", slot),
+                None if self.lifetimes[resource].is_some() => "imported".to_string(),
+                None => "unused".to_string(),
+            };
+            let _ = writeln!(
+                dot,
+                "    res_{} [label=\"{}\\n({})\", shape=ellipse];",
+                resource,
+                escape(name),
+                slot
+            );
+        }
+
+        for (pass, accesses) in self.pass_accesses.iter().enumerate() {
+            for access in accesses {
+                if access.write {
+                    let _ = writeln!(dot, "    pass_{} -> res_{};", pass, access.resource.0);
+                } else {
+                    let _ = writeln!(dot, "    res_{} -> pass_{};", access.resource.0, pass);
+                }
+            }
+        }
+
+        let _ = writeln!(dot, "}}");
+        dot
+    }
+}
+
+impl GraphResources {
+    // declares a pass's accesses on the tracker, which batches up the barriers they need
+    fn use_accesses(&self, accesses: &[PassAccess], tracker: &mut ResourceStateTracker) {
+        for (index, access) in accesses.iter().enumerate() {
+            if accesses[..index]
+                .iter()
+                .any(|a| a.resource == access.resource && a.usage == access.usage)
+            {
+                continue;
+            }
+
+            match self.resources[access.resource.0] {
+                ResolvedResource::Image {
+                    image,
+                    subresource_range,
+                    ..
+                } => tracker.use_image(image, subresource_range, access.usage),
+                ResolvedResource::Buffer { buffer, size } => {
+                    tracker.use_buffer(buffer, 0..size, access.usage)
+                }
+                ResolvedResource::Unused => {}
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, resource: GraphResource) -> ResolvedResource {
+        self.resources
+            .get(resource.0)
+            .copied()
+            .unwrap_or(ResolvedResource::Unused)
+    }
+
+    pub fn image(&self, resource: GraphResource) -> Option<vk::Image> {
+        match self.get(resource) {
+            ResolvedResource::Image { image, .. } => Some(image),
+            _ => None,
+        }
+    }
+
+    pub fn image_view(&self, resource: GraphResource) -> Option<vk::ImageView> {
+        match self.get(resource) {
+            ResolvedResource::Image { image_view, .. } => Some(image_view),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self, resource: GraphResource) -> Option<vk::Format> {
+        match self.get(resource) {
+            ResolvedResource::Image { format, .. } => Some(format),
+            _ => None,
+        }
+    }
+
+    pub fn extent(&self, resource: GraphResource) -> Option<vk::Extent2D> {
+        match self.get(resource) {
+            ResolvedResource::Image { extent, .. } => Some(extent),
+            _ => None,
+        }
+    }
+
+    pub fn buffer(&self, resource: GraphResource) -> Option<vk::Buffer> {
+        match self.get(resource) {
+            ResolvedResource::Buffer { buffer, .. } => Some(buffer),
+            _ => None,
+        }
+    }
+}
+
+impl TransientResourcePool {
+    pub fn new(render_system: &RenderSystem) -> Self {
+        Self {
+            descriptions: Vec::new(),
+            lifetimes: Vec::new(),
+            resources: Vec::new(),
+            aliased: Vec::new(),
+            memory: Vec::new(),
+            device: render_system.device().clone(),
+            memory_properties: *render_system.memory_properties(),
+        }
+    }
+
+    /// Drops the tracked states of every resource in the pool. Call before dropping a pool whose
+    /// resources were used with `tracker`, since their handles may be reused for new resources.
+    pub fn forget_states(&self, tracker: &mut ResourceStateTracker) {
+        for resource in self.resources.iter() {
+            match resource {
+                PhysicalResource::Image { image, .. } => tracker.forget_image(*image),
+                PhysicalResource::Buffer { buffer, .. } => tracker.forget_buffer(*buffer),
+            }
+        }
+    }
+
+    // recreates every physical resource if the graph's resources or their lifetimes changed since the
+    // last execution, forgetting the states of the ones it destroys
+    fn prepare(
+        &mut self,
+        descriptions: &[PhysicalResourceDescription],
+        lifetimes: &[Range<usize>],
+        tracker: &mut ResourceStateTracker,
+    ) -> anyhow::Result<()> {
+        if self.descriptions == descriptions && self.lifetimes == lifetimes {
+            return Ok(());
+        }
+
+        self.forget_states(tracker);
+        self.destroy();
+        if let Err(e) = self.create(descriptions, lifetimes) {
+            self.destroy();
+            return Err(e);
+        }
+        self.descriptions = descriptions.to_vec();
+        self.lifetimes = lifetimes.to_vec();
+        Ok(())
+    }
+
+    fn create(
+        &mut self,
+        descriptions: &[PhysicalResourceDescription],
+        lifetimes: &[Range<usize>],
+    ) -> anyhow::Result<()> {
+        unsafe {
+            let mut requests = Vec::with_capacity(descriptions.len());
+            for description in descriptions {
+                let (resource, requirements) = match *description {
+                    PhysicalResourceDescription::Image(image, usage) => {
+                        let create_info = vk::ImageCreateInfo::default()
+                            .image_type(vk::ImageType::TYPE_2D)
+                            .format(image.format)
+                            .extent(image.extent.into())
+                            .mip_levels(image.mip_levels)
+                            .array_layers(image.array_layers)
+                            .samples(image.samples)
+                            .tiling(vk::ImageTiling::OPTIMAL)
+                            .usage(usage)
+                            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                            .initial_layout(vk::ImageLayout::UNDEFINED);
+                        let handle = self.device.create_image(&create_info, None)?;
+                        (
+                            PhysicalResource::Image {
+                                image: handle,
+                                image_view: vk::ImageView::null(),
+                                description: image,
+                            },
+                            self.device.get_image_memory_requirements(handle),
+                        )
+                    }
+                    PhysicalResourceDescription::Buffer(buffer, usage) => {
+                        let create_info = vk::BufferCreateInfo::default()
+                            .size(buffer.size)
+                            .usage(usage)
+                            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+                        let handle = self.device.create_buffer(&create_info, None)?;
+                        (
+                            PhysicalResource::Buffer {
+                                buffer: handle,
+                                size: buffer.size,
+                            },
+                            self.device.get_buffer_memory_requirements(handle),
+                        )
+                    }
+                };
+                let image = matches!(resource, PhysicalResource::Image { .. });
+                self.resources.push(resource);
+
+                let memory_type = find_memory_type(
+                    &self.memory_properties,
+                    requirements.memory_type_bits,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                )
+                .ok_or(anyhow!(
+                    "No device local memory type for a transient resource"
+                ))?;
+                requests.push(MemoryRequest {
+                    size: requirements.size,
+                    alignment: requirements.alignment,
+                    memory_type,
+                    image,
+                });
+            }
+
+            let placement = place_in_memory(&requests, lifetimes);
+            for &(memory_type, size) in placement.blocks.iter() {
+                self.memory.push(
+                    self.device.allocate_memory(
+                        &vk::MemoryAllocateInfo::default()
+                            .allocation_size(size)
+                            .memory_type_index(memory_type),
+                        None,
+                    )?,
+                );
+            }
+
+            for (resource, &(block, offset)) in
+                self.resources.iter_mut().zip(placement.offsets.iter())
+            {
+                let memory = self.memory[block];
+                match resource {
+                    PhysicalResource::Image {
+                        image,
+                        image_view,
+                        description,
+                    } => {
+                        self.device.bind_image_memory(*image, memory, offset)?;
+                        *image_view = self.device.create_image_view(
+                            &vk::ImageViewCreateInfo::default()
+                                .image(*image)
+                                .view_type(if description.array_layers > 1 {
+                                    vk::ImageViewType::TYPE_2D_ARRAY
+                                } else {
+                                    vk::ImageViewType::TYPE_2D
+                                })
+                                .format(description.format)
+                                .subresource_range(image_subresource_range(description)),
+                            None,
+                        )?;
+                    }
+                    PhysicalResource::Buffer { buffer, .. } => {
+                        self.device.bind_buffer_memory(*buffer, memory, offset)?;
+                    }
+                }
+            }
+            self.aliased = placement.aliased;
+        }
+
+        Ok(())
+    }
+
+    fn destroy(&mut self) {
+        unsafe {
+            for resource in self.resources.drain(..) {
+                match resource {
+                    PhysicalResource::Image {
+                        image, image_view, ..
+                    } => {
+                        self.device.destroy_image_view(image_view, None);
+                        self.device.destroy_image(image, None);
+                    }
+                    PhysicalResource::Buffer { buffer, .. } => {
+                        self.device.destroy_buffer(buffer, None);
+                    }
+                }
+            }
+            for memory in self.memory.drain(..) {
+                self.device.free_memory(memory, None);
+            }
+        }
+        self.aliased.clear();
+        self.descriptions.clear();
+        self.lifetimes.clear();
+    }
+
+    // memory shared with another resource holds whatever that one left there, so aliased resources start
+    // out undefined and wait for all earlier work on the memory
+    fn begin_lifetimes(
+        &self,
+        lifetimes: &[Range<usize>],
+        position: usize,
+        tracker: &mut ResourceStateTracker,
+    ) {
+        const ALIASED: ResourceState = ResourceState::new(
+            PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::MEMORY_WRITE,
+            vk::ImageLayout::UNDEFINED,
+        );
+
+        for (slot, resource) in self.resources.iter().enumerate() {
+            if !self.aliased[slot] || lifetimes[slot].start != position {
+                continue;
+            }
+            match resource {
+                PhysicalResource::Image {
+                    image, description, ..
+                } => tracker.register_image(
+                    *image,
+                    format_aspect(description.format),
+                    description.mip_levels,
+                    description.array_layers,
+                    ALIASED,
+                ),
+                PhysicalResource::Buffer { buffer, size } => {
+                    tracker.register_buffer(*buffer, *size, ALIASED)
+                }
+            }
+        }
+    }
+
+    fn resolve(&self, slot: usize) -> ResolvedResource {
+        match self.resources.get(slot) {
+            Some(PhysicalResource::Image {
+                image,
+                image_view,
+                description,
+            }) => ResolvedResource::Image {
+                image: *image,
+                image_view: *image_view,
+                format: description.format,
+                extent: description.extent,
+                subresource_range: image_subresource_range(description),
+            },
+            Some(PhysicalResource::Buffer { buffer, size }) => ResolvedResource::Buffer {
+                buffer: *buffer,
+                size: *size,
+            },
+            None => ResolvedResource::Unused,
+        }
+    }
+}
+
+impl Drop for TransientResourcePool {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
+/// Assigns each request a block and offset. Requests share memory only if their lifetimes are disjoint;
+/// larger ones are placed first, each at the lowest offset that's free for its whole lifetime.
+fn place_in_memory(requests: &[MemoryRequest], lifetimes: &[Range<usize>]) -> MemoryPlacement {
+    let mut placement = MemoryPlacement {
+        blocks: Vec::new(),
+        offsets: vec![(0, 0); requests.len()],
+        aliased: vec![false; requests.len()],
+    };
+    let mut block_keys: Vec<(u32, bool)> = Vec::new();
+    let mut placed: Vec<usize> = Vec::new();
+
+    let mut by_size = (0..requests.len()).collect::<Vec<_>>();
+    by_size.sort_by_key(|&r| std::cmp::Reverse(requests[r].size));
+    for request in by_size {
+        let MemoryRequest {
+            size,
+            alignment,
+            memory_type,
+            image,
+        } = requests[request];
+        let block = match block_keys
+            .iter()
+            .position(|&key| key == (memory_type, image))
+        {
+            Some(block) => block,
+            None => {
+                block_keys.push((memory_type, image));
+                placement.blocks.push((memory_type, 0));
+                block_keys.len() - 1
+            }
+        };
+
+        let lifetime = &lifetimes[request];
+        let mut taken = placed
+            .iter()
+            .filter(|&&other| {
+                placement.offsets[other].0 == block && overlaps(&lifetimes[other], lifetime)
+            })
+            .map(|&other| {
+                let offset = placement.offsets[other].1;
+                offset..offset + requests[other].size
+            })
+            .collect::<Vec<_>>();
+        taken.sort_by_key(|range| range.start);
+
+        let mut offset = 0;
+        for range in taken {
+            if align_up(offset, alignment) + size <= range.start {
+                break;
+            }
+            offset = offset.max(range.end);
+        }
+        let offset = align_up(offset, alignment);
+
+        placement.offsets[request] = (block, offset);
+        placement.blocks[block].1 = placement.blocks[block].1.max(offset + size);
+        placed.push(request);
+    }
+
+    for a in 0..requests.len() {
+        for b in a + 1..requests.len() {
+            let (block_a, offset_a) = placement.offsets[a];
+            let (block_b, offset_b) = placement.offsets[b];
+            if block_a == block_b
+                && overlaps(
+                    &(offset_a..offset_a + requests[a].size),
+                    &(offset_b..offset_b + requests[b].size),
+                )
+            {
+                placement.aliased[a] = true;
+                placement.aliased[b] = true;
+            }
+        }
+    }
+
+    placement
+}
+
+#[inline]
+fn overlaps<T: Ord>(a: &Range<T>, b: &Range<T>) -> bool {
+    a.start < b.end && b.start < a.end
+}
+
+#[inline]
+fn align_up(value: u64, alignment: u64) -> u64 {
+    value.next_multiple_of(alignment.max(1))
+}
+
+fn image_subresource_range(description: &TransientImageDescription) -> ImageSubresourceRange {
+    ImageSubresourceRange {
+        aspect_mask: format_aspect(description.format),
+        base_mip_level: 0,
+        level_count: description.mip_levels,
+        base_array_layer: 0,
+        layer_count: description.array_layers,
+    }
+}
+
+fn same_shape(a: &PhysicalResourceDescription, b: &PhysicalResourceDescription) -> bool {
+    match (a, b) {
+        (PhysicalResourceDescription::Image(a, _), PhysicalResourceDescription::Image(b, _)) => {
+            a == b
+        }
+        (PhysicalResourceDescription::Buffer(a, _), PhysicalResourceDescription::Buffer(b, _)) => {
+            a == b
+        }
+        _ => false,
+    }
+}
+
+fn merge_usage(
+    a: &PhysicalResourceDescription,
+    b: &PhysicalResourceDescription,
+) -> PhysicalResourceDescription {
+    match (*a, *b) {
+        (
+            PhysicalResourceDescription::Image(description, a),
+            PhysicalResourceDescription::Image(_, b),
+        ) => PhysicalResourceDescription::Image(description, a | b),
+        (
+            PhysicalResourceDescription::Buffer(description, a),
+            PhysicalResourceDescription::Buffer(_, b),
+        ) => PhysicalResourceDescription::Buffer(description, a | b),
+        (a, _) => a,
+    }
+}
+
+fn image_usage_for(usage: ResourceUsage) -> vk::ImageUsageFlags {
+    use vk::ImageUsageFlags as U;
+    match usage {
+        ResourceUsage::ColorAttachmentWrite | ResourceUsage::ColorAttachmentReadWrite => {
+            U::COLOR_ATTACHMENT
+        }
+        ResourceUsage::DepthStencilAttachmentWrite | ResourceUsage::DepthStencilAttachmentRead => {
+            U::DEPTH_STENCIL_ATTACHMENT
+        }
+        ResourceUsage::SampledInVertex
+        | ResourceUsage::SampledInFragment
+        | ResourceUsage::SampledInCompute => U::SAMPLED,
+        ResourceUsage::StorageReadInCompute
+        | ResourceUsage::StorageWriteInCompute
+        | ResourceUsage::StorageReadWriteInCompute
+        | ResourceUsage::General => U::STORAGE,
+        ResourceUsage::TransferSrc => U::TRANSFER_SRC,
+        ResourceUsage::TransferDst => U::TRANSFER_DST,
+        ResourceUsage::Custom(state) => match state.layout {
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => U::COLOR_ATTACHMENT,
+            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL
+            | vk::ImageLayout::DEPTH_STENCIL_READ_ONLY_OPTIMAL => U::DEPTH_STENCIL_ATTACHMENT,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => U::SAMPLED,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL => U::TRANSFER_SRC,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL => U::TRANSFER_DST,
+            vk::ImageLayout::GENERAL => U::STORAGE,
+            _ => U::empty(),
+        },
+        _ => U::empty(),
+    }
+}
+
+fn buffer_usage_for(usage: ResourceUsage) -> vk::BufferUsageFlags {
+    use vk::BufferUsageFlags as U;
+    match usage {
+        ResourceUsage::UniformInVertex
+        | ResourceUsage::UniformInFragment
+        | ResourceUsage::UniformInCompute => U::UNIFORM_BUFFER,
+        ResourceUsage::StorageReadInCompute
+        | ResourceUsage::StorageWriteInCompute
+        | ResourceUsage::StorageReadWriteInCompute
+        | ResourceUsage::General => U::STORAGE_BUFFER,
+        ResourceUsage::VertexBuffer => U::VERTEX_BUFFER,
+        ResourceUsage::IndexBuffer => U::INDEX_BUFFER,
+        ResourceUsage::IndirectBuffer => U::INDIRECT_BUFFER,
+        ResourceUsage::TransferSrc => U::TRANSFER_SRC,
+        ResourceUsage::TransferDst => U::TRANSFER_DST,
+        _ => U::empty(),
+    }
+}
+
+fn escape(name: &str) -> String {
+    name.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    fn request(size: u64, alignment: u64) -> MemoryRequest {
+        MemoryRequest {
+            size,
+            alignment,
+            memory_type: 0,
+            image: true,
+        }
+    }
+
+    fn color_image(format: vk::Format) -> TransientImageDescription {
+        TransientImageDescription {
+            format,
+            extent: vk::Extent2D {
+                width: 64,
+                height: 64,
+            },
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+        }
+    }
+
+    #[test]
+    fn culls_passes_that_dont_reach_an_output() {
+        let mut graph = RenderGraph::new();
+        let gbuffer = graph.create_image("gbuffer", color_image(vk::Format::R8G8B8A8_UNORM));
+        let debug = graph.create_image("debug", color_image(vk::Format::R8G8B8A8_UNORM));
+        let readback = graph.create_buffer("readback", TransientBufferDescription { size: 16 });
+        let backbuffer = graph.backbuffer(0);
+
+        graph
+            .add_pass("gbuffer")
+            .write(gbuffer, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("debug")
+            .write(debug, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("readback")
+            .write(readback, ResourceUsage::TransferDst)
+            .side_effects();
+        graph
+            .add_pass("lighting")
+            .read(gbuffer, ResourceUsage::SampledInFragment)
+            .write(backbuffer, ResourceUsage::ColorAttachmentWrite);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.order, vec![0, 2, 3]);
+        assert_eq!(compiled.culled, vec![1]);
+        assert_eq!(compiled.resource_slots[debug.0], None);
+        assert_eq!(compiled.lifetimes[debug.0], None);
+    }
+
+    #[test]
+    fn culls_writes_overwritten_before_being_read() {
+        let mut graph = RenderGraph::new();
+        let image = graph.create_image("image", color_image(vk::Format::R8G8B8A8_UNORM));
+        let backbuffer = graph.backbuffer(0);
+
+        graph
+            .add_pass("overwritten")
+            .write(image, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("clear")
+            .write(image, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("blit")
+            .read(image, ResourceUsage::TransferSrc)
+            .write(backbuffer, ResourceUsage::TransferDst);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.order, vec![1, 2]);
+        assert_eq!(compiled.culled, vec![0]);
+    }
+
+    #[test]
+    fn orders_passes_by_their_dependencies() {
+        let mut graph = RenderGraph::new();
+        let shadow = graph.create_image("shadow", color_image(vk::Format::D32_SFLOAT));
+        let color = graph.create_image("color", color_image(vk::Format::R16G16B16A16_SFLOAT));
+        let backbuffer = graph.backbuffer(0);
+
+        graph
+            .add_pass("shadow")
+            .write(shadow, ResourceUsage::DepthStencilAttachmentWrite);
+        graph
+            .add_pass("scene")
+            .read(shadow, ResourceUsage::SampledInFragment)
+            .write(color, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("shadow again")
+            .write(shadow, ResourceUsage::DepthStencilAttachmentWrite);
+        graph
+            .add_pass("composite")
+            .read(color, ResourceUsage::SampledInFragment)
+            .read(shadow, ResourceUsage::SampledInFragment)
+            .write(backbuffer, ResourceUsage::ColorAttachmentWrite);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.order, vec![0, 1, 2, 3]);
+        assert_eq!(
+            compiled.edges,
+            vec![
+                (0, 1, shadow),
+                (0, 2, shadow),
+                (1, 2, shadow),
+                (1, 3, color),
+                (2, 3, shadow),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_reads_before_writes_and_foreign_resources() {
+        let mut graph = RenderGraph::new();
+        let image = graph.create_image("image", color_image(vk::Format::R8G8B8A8_UNORM));
+        graph
+            .add_pass("read")
+            .read(image, ResourceUsage::SampledInFragment)
+            .side_effects();
+        assert!(graph.compile().is_err());
+
+        let mut graph = RenderGraph::new();
+        graph
+            .add_pass("foreign")
+            .write(GraphResource(7), ResourceUsage::TransferDst)
+            .side_effects();
+        assert!(graph.compile().is_err());
+    }
+
+    #[test]
+    fn reuses_slots_for_identical_descriptions_with_disjoint_lifetimes() {
+        let mut graph = RenderGraph::new();
+        let first = graph.create_image("first", color_image(vk::Format::R8G8B8A8_UNORM));
+        let second = graph.create_image("second", color_image(vk::Format::R8G8B8A8_UNORM));
+        let other = graph.create_image("other", color_image(vk::Format::R16G16B16A16_SFLOAT));
+        let backbuffer = graph.backbuffer(0);
+
+        graph
+            .add_pass("a")
+            .write(first, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("b")
+            .read(first, ResourceUsage::SampledInFragment)
+            .write(second, ResourceUsage::TransferDst);
+        graph
+            .add_pass("c")
+            .read(second, ResourceUsage::TransferSrc)
+            .write(other, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("d")
+            .read(other, ResourceUsage::SampledInFragment)
+            .write(backbuffer, ResourceUsage::ColorAttachmentWrite);
+
+        let compiled = graph.compile().unwrap();
+        // `second` starts in the pass `first` ends in, so they can't share
+        assert_eq!(compiled.resource_slots[first.0], Some(0));
+        assert_eq!(compiled.resource_slots[second.0], Some(1));
+        assert_eq!(compiled.resource_slots[other.0], Some(2));
+        assert_eq!(compiled.resource_slots[backbuffer.0], None);
+        assert_eq!(compiled.slot_lifetimes, vec![0..2, 1..3, 2..4]);
+
+        let mut graph = RenderGraph::new();
+        let first = graph.create_image("first", color_image(vk::Format::R8G8B8A8_UNORM));
+        let middle = graph.create_image("middle", color_image(vk::Format::R16G16B16A16_SFLOAT));
+        let second = graph.create_image("second", color_image(vk::Format::R8G8B8A8_UNORM));
+        let backbuffer = graph.backbuffer(0);
+
+        graph
+            .add_pass("a")
+            .write(first, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("b")
+            .read(first, ResourceUsage::SampledInFragment)
+            .write(middle, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("c")
+            .read(middle, ResourceUsage::SampledInFragment)
+            .write(second, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("d")
+            .read(second, ResourceUsage::TransferSrc)
+            .write(backbuffer, ResourceUsage::TransferDst);
+
+        let compiled = graph.compile().unwrap();
+        assert_eq!(compiled.resource_slots[first.0], Some(0));
+        assert_eq!(compiled.resource_slots[second.0], Some(0));
+        assert_eq!(compiled.slot_lifetimes, vec![0..4, 1..3]);
+        assert_eq!(
+            compiled.physical_resources[0],
+            PhysicalResourceDescription::Image(
+                color_image(vk::Format::R8G8B8A8_UNORM),
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+            )
+        );
+    }
+
+    #[test]
+    fn emits_barriers_between_passes() {
+        let mut graph = RenderGraph::new();
+        let vertices = graph.create_buffer("vertices", TransientBufferDescription { size: 256 });
+        let color = graph.create_image("color", color_image(vk::Format::R8G8B8A8_UNORM));
+        let backbuffer = graph.backbuffer(0);
+
+        graph
+            .add_pass("upload")
+            .write(vertices, ResourceUsage::TransferDst);
+        graph
+            .add_pass("draw")
+            .read(vertices, ResourceUsage::VertexBuffer)
+            .write(color, ResourceUsage::ColorAttachmentWrite);
+        graph
+            .add_pass("post")
+            .read(color, ResourceUsage::SampledInFragment)
+            .read(vertices, ResourceUsage::VertexBuffer)
+            .write(backbuffer, ResourceUsage::ColorAttachmentWrite);
+        let compiled = graph.compile().unwrap();
+
+        let subresource_range = ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        let image = |raw| ResolvedResource::Image {
+            image: vk::Image::from_raw(raw),
+            image_view: vk::ImageView::null(),
+            format: vk::Format::R8G8B8A8_UNORM,
+            extent: vk::Extent2D::default(),
+            subresource_range,
+        };
+        let resources = GraphResources {
+            resources: vec![
+                ResolvedResource::Buffer {
+                    buffer: vk::Buffer::from_raw(1),
+                    size: 256,
+                },
+                image(2),
+                image(3),
+            ],
+        };
+
+        let mut tracker = ResourceStateTracker::new();
+        let barriers = compiled
+            .order
+            .iter()
+            .map(|&pass| {
+                resources.use_accesses(&compiled.pass_accesses[pass], &mut tracker);
+                tracker.take_barriers()
+            })
+            .collect::<Vec<_>>();
+
+        let (images, buffers) = &barriers[0];
+        assert!(images.is_empty());
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers[0].dst_access_mask, vk::AccessFlags2::TRANSFER_WRITE);
+
+        let (images, buffers) = &barriers[1];
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers[0].src_access_mask, vk::AccessFlags2::TRANSFER_WRITE);
+        assert_eq!(
+            buffers[0].dst_access_mask,
+            vk::AccessFlags2::VERTEX_ATTRIBUTE_READ
+        );
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].image, vk::Image::from_raw(2));
+        assert_eq!(images[0].old_layout, vk::ImageLayout::UNDEFINED);
+        assert_eq!(
+            images[0].new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+
+        // the vertex buffer is only read again, so it needs no barrier
+        let (images, buffers) = &barriers[2];
+        assert!(buffers.is_empty());
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].image, vk::Image::from_raw(2));
+        assert_eq!(
+            images[0].old_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+        assert_eq!(
+            images[0].new_layout,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+        assert_eq!(
+            images[0].src_access_mask,
+            vk::AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(images[1].image, vk::Image::from_raw(3));
+        assert_eq!(
+            images[1].new_layout,
+            vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+        );
+    }
+
+    #[test]
+    fn disjoint_lifetimes_share_memory() {
+        let placement = place_in_memory(&[request(256, 64), request(128, 64)], &[0..1, 1..2]);
+
+        assert_eq!(placement.blocks, vec![(0, 256)]);
+        assert_eq!(placement.offsets, vec![(0, 0), (0, 0)]);
+        assert_eq!(placement.aliased, vec![true, true]);
+    }
+
+    #[test]
+    fn overlapping_lifetimes_get_separate_ranges() {
+        let placement = place_in_memory(&[request(256, 64), request(100, 64)], &[0..2, 1..3]);
+
+        assert_eq!(placement.blocks, vec![(0, 356)]);
+        assert_eq!(placement.offsets, vec![(0, 0), (0, 256)]);
+        assert_eq!(placement.aliased, vec![false, false]);
+    }
+
+    #[test]
+    fn placement_respects_alignment_and_fills_gaps() {
+        // 0 and 1 live together, 2 only overlaps 1 and fits where 0 was
+        let requests = [request(100, 1), request(64, 256), request(50, 16)];
+        let placement = place_in_memory(&requests, &[0..1, 0..2, 1..2]);
+
+        assert_eq!(placement.offsets, vec![(0, 0), (0, 256), (0, 0)]);
+        assert_eq!(placement.blocks, vec![(0, 320)]);
+        assert_eq!(placement.aliased, vec![true, false, true]);
+    }
+
+    #[test]
+    fn images_and_buffers_use_separate_blocks() {
+        let buffer = MemoryRequest {
+            image: false,
+            ..request(64, 16)
+        };
+        let other_type = MemoryRequest {
+            memory_type: 1,
+            ..request(64, 16)
+        };
+        let placement =
+            place_in_memory(&[request(64, 16), buffer, other_type], &[0..1, 1..2, 2..3]);
+
+        assert_eq!(placement.blocks, vec![(0, 64), (0, 64), (1, 64)]);
+        assert_eq!(placement.aliased, vec![false, false, false]);
+    }
+}
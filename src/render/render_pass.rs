@@ -0,0 +1,199 @@
+use crate::render::RenderSystem;
+use anyhow::anyhow;
+use ash::vk;
+use ash::vk::{
+    AttachmentReference, FramebufferCreateInfo, PipelineBindPoint, RenderPassCreateInfo,
+};
+use std::rc::Rc;
+
+#[derive(Clone, Debug, Default)]
+pub struct SubpassDescription {
+    /// Attachments read with `subpassLoad` in the fragment shader.
+    pub input_attachments: Vec<AttachmentReference>,
+    pub color_attachments: Vec<AttachmentReference>,
+    /// Either empty or one per color attachment; unused entries are `vk::ATTACHMENT_UNUSED`.
+    pub resolve_attachments: Vec<AttachmentReference>,
+    pub depth_stencil_attachment: Option<AttachmentReference>,
+    pub preserve_attachments: Vec<u32>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderPassDescription {
+    pub attachments: Vec<vk::AttachmentDescription>,
+    pub subpasses: Vec<SubpassDescription>,
+    pub dependencies: Vec<vk::SubpassDependency>,
+    pub debug_name: Option<String>,
+}
+
+pub struct RenderPass {
+    render_pass: vk::RenderPass,
+    attachments: Vec<vk::AttachmentDescription>,
+    subpasses: Vec<SubpassDescription>,
+    device: ash::Device,
+}
+
+pub struct FramebufferDescription {
+    pub render_pass: Rc<RenderPass>,
+    /// One view per attachment of the render pass, in the same order.
+    pub attachments: Vec<vk::ImageView>,
+    pub extent: vk::Extent2D,
+    pub layers: u32,
+    pub debug_name: Option<String>,
+}
+
+pub struct Framebuffer {
+    framebuffer: vk::Framebuffer,
+    render_pass: Rc<RenderPass>,
+    extent: vk::Extent2D,
+    device: ash::Device,
+}
+
+impl RenderPass {
+    pub fn new(
+        render_system: &RenderSystem,
+        description: &RenderPassDescription,
+    ) -> anyhow::Result<Self> {
+        if description.subpasses.is_empty() {
+            return Err(anyhow!("A render pass needs at least one subpass"));
+        }
+
+        let subpasses = description
+            .subpasses
+            .iter()
+            .map(|subpass| {
+                let mut subpass_description = vk::SubpassDescription::default()
+                    .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+                    .input_attachments(subpass.input_attachments.as_slice())
+                    .color_attachments(subpass.color_attachments.as_slice())
+                    .preserve_attachments(subpass.preserve_attachments.as_slice());
+                if !subpass.resolve_attachments.is_empty() {
+                    subpass_description = subpass_description
+                        .resolve_attachments(subpass.resolve_attachments.as_slice());
+                }
+                if let Some(depth_stencil_attachment) = subpass.depth_stencil_attachment.as_ref() {
+                    subpass_description =
+                        subpass_description.depth_stencil_attachment(depth_stencil_attachment);
+                }
+                subpass_description
+            })
+            .collect::<Vec<_>>();
+
+        let create_info = RenderPassCreateInfo::default()
+            .attachments(description.attachments.as_slice())
+            .subpasses(subpasses.as_slice())
+            .dependencies(description.dependencies.as_slice());
+
+        let device = render_system.device().clone();
+        let render_pass = Self {
+            render_pass: unsafe { device.create_render_pass(&create_info, None) }?,
+            attachments: description.attachments.clone(),
+            subpasses: description.subpasses.clone(),
+            device,
+        };
+
+        if let Some(name) = description.debug_name.as_deref() {
+            render_pass.set_debug_name(render_system, name);
+        }
+
+        Ok(render_pass)
+    }
+
+    #[inline]
+    pub fn handle(&self) -> vk::RenderPass {
+        self.render_pass
+    }
+
+    #[inline]
+    pub fn attachments(&self) -> &[vk::AttachmentDescription] {
+        &self.attachments
+    }
+
+    #[inline]
+    pub fn subpasses(&self) -> &[SubpassDescription] {
+        &self.subpasses
+    }
+
+    /// Whether pipelines and framebuffers created for `other` can be used with this render pass. This only
+    /// compares attachment formats and sample counts, which covers the common cases of the spec's rules.
+    pub fn is_compatible_with(&self, other: &RenderPass) -> bool {
+        self.render_pass == other.render_pass
+            || (self.subpasses.len() == other.subpasses.len()
+                && self.attachments.len() == other.attachments.len()
+                && self
+                    .attachments
+                    .iter()
+                    .zip(other.attachments.iter())
+                    .all(|(a, b)| a.format == b.format && a.samples == b.samples))
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.render_pass, name);
+    }
+}
+
+impl Drop for RenderPass {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_render_pass(self.render_pass, None) };
+    }
+}
+
+impl Framebuffer {
+    pub fn new(
+        render_system: &RenderSystem,
+        description: &FramebufferDescription,
+    ) -> anyhow::Result<Self> {
+        if description.attachments.len() != description.render_pass.attachments().len() {
+            return Err(anyhow!(
+                "Framebuffer has {} attachments but its render pass has {}",
+                description.attachments.len(),
+                description.render_pass.attachments().len()
+            ));
+        }
+
+        let create_info = FramebufferCreateInfo::default()
+            .render_pass(description.render_pass.handle())
+            .attachments(description.attachments.as_slice())
+            .width(description.extent.width)
+            .height(description.extent.height)
+            .layers(description.layers);
+
+        let device = render_system.device().clone();
+        let framebuffer = Self {
+            framebuffer: unsafe { device.create_framebuffer(&create_info, None) }?,
+            render_pass: description.render_pass.clone(),
+            extent: description.extent,
+            device,
+        };
+
+        if let Some(name) = description.debug_name.as_deref() {
+            framebuffer.set_debug_name(render_system, name);
+        }
+
+        Ok(framebuffer)
+    }
+
+    #[inline]
+    pub fn handle(&self) -> vk::Framebuffer {
+        self.framebuffer
+    }
+
+    #[inline]
+    pub fn render_pass(&self) -> &Rc<RenderPass> {
+        &self.render_pass
+    }
+
+    #[inline]
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.framebuffer, name);
+    }
+}
+
+impl Drop for Framebuffer {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_framebuffer(self.framebuffer, None) };
+    }
+}
@@ -30,6 +30,8 @@ pub struct FrameRenderAttachment {
 
 pub struct FrameRenderInfo {
     pub color_attachments: Vec<FrameRenderAttachment>,
+    /// Its format decides whether it's used as the depth attachment, the stencil attachment or both.
+    pub depth_stencil_attachment: Option<FrameRenderAttachment>,
     pub extent: vk::Extent2D,
     pub image_index: u32,
 }
@@ -195,6 +197,9 @@ impl SwapchainRenderTarget {
         let swapchain = unsafe { swapchain_fn.create_swapchain(&swapchain_create_info, None) }?;
 
         let images = unsafe { swapchain_fn.get_swapchain_images(swapchain) }?;
+        for (index, image) in images.iter().enumerate() {
+            render_system.set_debug_name(*image, &format!("swapchain image {}", index));
+        }
 
         let image_views = images
             .iter()
@@ -222,6 +227,9 @@ impl SwapchainRenderTarget {
                 unsafe { render_system.device().create_image_view(&create_info, None) }
             })
             .try_collect::<Vec<_>>()?;
+        for (index, image_view) in image_views.iter().enumerate() {
+            render_system.set_debug_name(*image_view, &format!("swapchain image view {}", index));
+        }
 
         Ok(Self {
             window,
@@ -277,6 +285,7 @@ impl RenderTarget for SwapchainRenderTarget {
                     queue_family: 0,
                 },
             }],
+            depth_stencil_attachment: None,
             extent: self.extent,
             image_index,
         })
@@ -0,0 +1,336 @@
+use crate::render::{RenderSystem, allocate_memory};
+use ash::vk;
+use ash::vk::{
+    BufferCreateInfo, ImageAspectFlags, ImageCreateInfo, ImageSubresourceRange,
+    ImageViewCreateInfo, MemoryPropertyFlags, SharingMode,
+};
+
+pub struct ImageDescription {
+    pub image_type: vk::ImageType,
+    pub view_type: vk::ImageViewType,
+    pub format: vk::Format,
+    pub extent: vk::Extent3D,
+    pub mip_levels: u32,
+    pub array_layers: u32,
+    pub samples: vk::SampleCountFlags,
+    pub usage: vk::ImageUsageFlags,
+    pub memory_properties: vk::MemoryPropertyFlags,
+    pub debug_name: Option<String>,
+}
+
+pub struct Image {
+    image: vk::Image,
+    image_view: vk::ImageView,
+    memory: vk::DeviceMemory,
+    format: vk::Format,
+    extent: vk::Extent3D,
+    mip_levels: u32,
+    array_layers: u32,
+    device: ash::Device,
+}
+
+pub struct Buffer {
+    buffer: vk::Buffer,
+    memory: vk::DeviceMemory,
+    size: u64,
+    mapped: *mut u8,
+    device: ash::Device,
+}
+
+impl ImageDescription {
+    #[inline]
+    pub fn simple_2d(format: vk::Format, extent: vk::Extent2D, usage: vk::ImageUsageFlags) -> Self {
+        Self {
+            image_type: vk::ImageType::TYPE_2D,
+            view_type: vk::ImageViewType::TYPE_2D,
+            format,
+            extent: extent.into(),
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            usage,
+            memory_properties: MemoryPropertyFlags::DEVICE_LOCAL,
+            debug_name: None,
+        }
+    }
+}
+
+impl Image {
+    #[inline]
+    pub fn new(render_system: &RenderSystem, description: &ImageDescription) -> anyhow::Result<Self> {
+        let image = Self::create(
+            render_system.device(),
+            render_system.memory_properties(),
+            description,
+        )?;
+
+        if let Some(name) = description.debug_name.as_deref() {
+            image.set_debug_name(render_system, name);
+        }
+
+        Ok(image)
+    }
+
+    pub(crate) fn create(
+        device: &ash::Device,
+        memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        description: &ImageDescription,
+    ) -> anyhow::Result<Self> {
+        let create_info = ImageCreateInfo::default()
+            .image_type(description.image_type)
+            .format(description.format)
+            .extent(description.extent)
+            .mip_levels(description.mip_levels)
+            .array_layers(description.array_layers)
+            .samples(description.samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(description.usage)
+            .sharing_mode(SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+
+        unsafe {
+            let image = device.create_image(&create_info, None)?;
+
+            let memory = match allocate_memory(
+                device,
+                memory_properties,
+                device.get_image_memory_requirements(image),
+                description.memory_properties,
+            ) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    device.destroy_image(image, None);
+                    return Err(e);
+                }
+            };
+
+            if let Err(e) = device.bind_image_memory(image, memory, 0) {
+                device.destroy_image(image, None);
+                device.free_memory(memory, None);
+                return Err(e.into());
+            }
+
+            let view_create_info = ImageViewCreateInfo::default()
+                .image(image)
+                .view_type(description.view_type)
+                .format(description.format)
+                .subresource_range(ImageSubresourceRange {
+                    aspect_mask: format_aspect(description.format),
+                    base_mip_level: 0,
+                    level_count: description.mip_levels,
+                    base_array_layer: 0,
+                    layer_count: description.array_layers,
+                });
+
+            let image_view = match device.create_image_view(&view_create_info, None) {
+                Ok(image_view) => image_view,
+                Err(e) => {
+                    device.destroy_image(image, None);
+                    device.free_memory(memory, None);
+                    return Err(e.into());
+                }
+            };
+
+            Ok(Self {
+                image,
+                image_view,
+                memory,
+                format: description.format,
+                extent: description.extent,
+                mip_levels: description.mip_levels,
+                array_layers: description.array_layers,
+                device: device.clone(),
+            })
+        }
+    }
+
+    #[inline]
+    pub fn handle(&self) -> vk::Image {
+        self.image
+    }
+
+    #[inline]
+    pub fn view(&self) -> vk::ImageView {
+        self.image_view
+    }
+
+    /// Names both the image and its view.
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.image, name);
+        render_system.set_debug_name(self.image_view, &format!("{} view", name));
+    }
+
+    #[inline]
+    pub fn format(&self) -> vk::Format {
+        self.format
+    }
+
+    #[inline]
+    pub fn extent(&self) -> vk::Extent3D {
+        self.extent
+    }
+
+    pub fn full_subresource_range(&self) -> ImageSubresourceRange {
+        ImageSubresourceRange {
+            aspect_mask: format_aspect(self.format),
+            base_mip_level: 0,
+            level_count: self.mip_levels,
+            base_array_layer: 0,
+            layer_count: self.array_layers,
+        }
+    }
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.image_view, None);
+            self.device.destroy_image(self.image, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+impl Buffer {
+    #[inline]
+    pub fn new(
+        render_system: &RenderSystem,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+    ) -> anyhow::Result<Self> {
+        Self::create(
+            render_system.device(),
+            render_system.memory_properties(),
+            size,
+            usage,
+            memory_properties,
+        )
+    }
+
+    /// Host visible buffers are persistently mapped for their whole lifetime.
+    pub(crate) fn create(
+        device: &ash::Device,
+        device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        memory_properties: vk::MemoryPropertyFlags,
+    ) -> anyhow::Result<Self> {
+        let create_info = BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(SharingMode::EXCLUSIVE);
+
+        unsafe {
+            let buffer = device.create_buffer(&create_info, None)?;
+
+            let memory = match allocate_memory(
+                device,
+                device_memory_properties,
+                device.get_buffer_memory_requirements(buffer),
+                memory_properties,
+            ) {
+                Ok(memory) => memory,
+                Err(e) => {
+                    device.destroy_buffer(buffer, None);
+                    return Err(e);
+                }
+            };
+
+            let mapped = if memory_properties.contains(MemoryPropertyFlags::HOST_VISIBLE) {
+                device
+                    .bind_buffer_memory(buffer, memory, 0)
+                    .and_then(|_| {
+                        device.map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                    })
+                    .map(|ptr| ptr as *mut u8)
+            } else {
+                device
+                    .bind_buffer_memory(buffer, memory, 0)
+                    .map(|_| std::ptr::null_mut())
+            };
+
+            let mapped = match mapped {
+                Ok(mapped) => mapped,
+                Err(e) => {
+                    device.destroy_buffer(buffer, None);
+                    device.free_memory(memory, None);
+                    return Err(e.into());
+                }
+            };
+
+            Ok(Self {
+                buffer,
+                memory,
+                size,
+                mapped,
+                device: device.clone(),
+            })
+        }
+    }
+
+    #[inline]
+    pub fn handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+
+    #[inline]
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.buffer, name);
+    }
+
+    /// Returns the persistent mapping of a host visible buffer, or `None` for device local buffers.
+    #[inline]
+    pub fn mapped_ptr(&self) -> Option<*mut u8> {
+        (!self.mapped.is_null()).then_some(self.mapped)
+    }
+
+    pub fn write(&self, offset: u64, data: &[u8]) -> anyhow::Result<()> {
+        let mapped = self
+            .mapped_ptr()
+            .ok_or(anyhow::anyhow!("Buffer is not host visible"))?;
+        let end = offset.checked_add(data.len() as u64);
+        if end.is_none_or(|end| end > self.size) {
+            return Err(anyhow::anyhow!(
+                "Write of {} bytes at offset {} overflows buffer of size {}",
+                data.len(),
+                offset,
+                self.size
+            ));
+        }
+
+        unsafe {
+            std::ptr::copy_nonoverlapping(data.as_ptr(), mapped.add(offset as usize), data.len())
+        };
+        Ok(())
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.mapped.is_null() {
+                self.device.unmap_memory(self.memory);
+            }
+            self.device.destroy_buffer(self.buffer, None);
+            self.device.free_memory(self.memory, None);
+        }
+    }
+}
+
+pub fn format_aspect(format: vk::Format) -> ImageAspectFlags {
+    match format {
+        vk::Format::D16_UNORM | vk::Format::X8_D24_UNORM_PACK32 | vk::Format::D32_SFLOAT => {
+            ImageAspectFlags::DEPTH
+        }
+        vk::Format::S8_UINT => ImageAspectFlags::STENCIL,
+        vk::Format::D16_UNORM_S8_UINT
+        | vk::Format::D24_UNORM_S8_UINT
+        | vk::Format::D32_SFLOAT_S8_UINT => ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL,
+        _ => ImageAspectFlags::COLOR,
+    }
+}
@@ -0,0 +1,959 @@
+use crate::render::render_target::FrameRenderAttachmentImageStateExternal;
+use ash::vk;
+use ash::vk::{
+    AccessFlags2, BufferMemoryBarrier2, ImageLayout, ImageMemoryBarrier2, ImageSubresourceRange,
+    PipelineStageFlags2,
+};
+use std::collections::HashMap;
+use std::ops::Range;
+
+const WRITE_ACCESS: AccessFlags2 = AccessFlags2::from_raw(
+    AccessFlags2::SHADER_WRITE.as_raw()
+        | AccessFlags2::SHADER_STORAGE_WRITE.as_raw()
+        | AccessFlags2::COLOR_ATTACHMENT_WRITE.as_raw()
+        | AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw()
+        | AccessFlags2::TRANSFER_WRITE.as_raw()
+        | AccessFlags2::HOST_WRITE.as_raw()
+        | AccessFlags2::MEMORY_WRITE.as_raw(),
+);
+
+/// The last known (or intended) synchronization state of a resource.
+///
+/// `layout` is ignored for buffers. A `queue_family` of `vk::QUEUE_FAMILY_IGNORED` means the
+/// resource is not owned by any particular queue family and never takes part in an ownership transfer.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ResourceState {
+    pub stage: PipelineStageFlags2,
+    pub access: AccessFlags2,
+    pub layout: ImageLayout,
+    pub queue_family: u32,
+}
+
+impl ResourceState {
+    pub const UNDEFINED: Self = Self {
+        stage: PipelineStageFlags2::NONE,
+        access: AccessFlags2::NONE,
+        layout: ImageLayout::UNDEFINED,
+        queue_family: vk::QUEUE_FAMILY_IGNORED,
+    };
+
+    #[inline]
+    pub const fn new(
+        stage: PipelineStageFlags2,
+        access: AccessFlags2,
+        layout: ImageLayout,
+    ) -> Self {
+        Self {
+            stage,
+            access,
+            layout,
+            queue_family: vk::QUEUE_FAMILY_IGNORED,
+        }
+    }
+
+    #[inline]
+    pub const fn on_queue(mut self, queue_family: u32) -> Self {
+        self.queue_family = queue_family;
+        self
+    }
+
+    #[inline]
+    pub fn from_external(
+        state: FrameRenderAttachmentImageStateExternal,
+        stage: PipelineStageFlags2,
+    ) -> Self {
+        Self {
+            stage,
+            access: state.access,
+            layout: state.layout,
+            queue_family: state.queue_family,
+        }
+    }
+
+    #[inline]
+    pub fn writes(&self) -> bool {
+        self.access.intersects(WRITE_ACCESS)
+    }
+
+    fn needs_barrier_to(&self, next: &ResourceState) -> bool {
+        self.layout != next.layout || self.needs_buffer_barrier_to(next)
+    }
+
+    // buffers have no layout, so only hazards and ownership transfers matter
+    fn needs_buffer_barrier_to(&self, next: &ResourceState) -> bool {
+        self.transfers_ownership_to(next) || self.writes() || next.writes()
+    }
+
+    #[inline]
+    fn transfers_ownership_to(&self, next: &ResourceState) -> bool {
+        self.queue_family != next.queue_family
+            && self.queue_family != vk::QUEUE_FAMILY_IGNORED
+            && next.queue_family != vk::QUEUE_FAMILY_IGNORED
+    }
+
+    fn queue_families_to(&self, next: &ResourceState) -> (u32, u32) {
+        if self.transfers_ownership_to(next) {
+            (self.queue_family, next.queue_family)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        }
+    }
+
+    // read-after-read in the same layout needs no barrier, but a later write has to wait on every reader
+    fn merged_read(&self, next: &ResourceState) -> ResourceState {
+        ResourceState {
+            stage: self.stage | next.stage,
+            access: self.access | next.access,
+            layout: self.layout,
+            queue_family: next.queue_family,
+        }
+    }
+}
+
+// what the tracker remembers per image subresource or buffer range
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+struct TrackedState {
+    // the stages and accesses of every use since the last barrier, which a later write has to wait on
+    state: ResourceState,
+    // the last write (or layout transition) a barrier waited on, and the stages and accesses it was made
+    // visible to. Reads outside of that scope still need a barrier of their own
+    write_stage: PipelineStageFlags2,
+    write_access: AccessFlags2,
+    visible_stage: PipelineStageFlags2,
+    visible_access: AccessFlags2,
+}
+
+impl TrackedState {
+    fn new(state: ResourceState) -> Self {
+        let (write_stage, write_access) = if state.writes() {
+            (state.stage, state.access & WRITE_ACCESS)
+        } else {
+            (PipelineStageFlags2::NONE, AccessFlags2::NONE)
+        };
+        Self {
+            state,
+            write_stage,
+            write_access,
+            visible_stage: PipelineStageFlags2::NONE,
+            visible_access: AccessFlags2::NONE,
+        }
+    }
+
+    // whether nothing is pending, or the pending write was already made visible to `next`
+    fn covers(&self, next: &ResourceState) -> bool {
+        (self.write_stage.is_empty() && self.write_access.is_empty())
+            || (self.visible_stage.contains(next.stage)
+                && self.visible_access.contains(next.access))
+    }
+
+    // moves to `next`, returning the source state of the barrier that takes, if any. `needs_barrier` is whether
+    // the use needs one regardless of the pending write, see `ResourceState::needs_barrier_to`
+    fn transition_to(
+        &mut self,
+        next: &ResourceState,
+        needs_barrier: bool,
+    ) -> Option<ResourceState> {
+        if needs_barrier {
+            let source = self.state;
+            *self = Self::new(*next);
+            if !next.writes() {
+                // the barrier waits on the previous uses, and makes their writes and any layout
+                // transition visible to `next` only
+                self.write_stage = source.stage | next.stage;
+                self.write_access = source.access & WRITE_ACCESS;
+                self.visible_stage = next.stage;
+                self.visible_access = next.access;
+            }
+            return Some(source);
+        }
+
+        let covered = self.covers(next);
+        let source = ResourceState {
+            stage: self.write_stage,
+            access: self.write_access,
+            ..self.state
+        };
+        self.state = self.state.merged_read(next);
+        if covered {
+            return None;
+        }
+        self.visible_stage |= next.stage;
+        self.visible_access |= next.access;
+        Some(source)
+    }
+}
+
+/// A declared intended use of a resource. The tracker translates this into a `ResourceState` and emits whatever barriers are needed to get there.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ResourceUsage {
+    ColorAttachmentWrite,
+    ColorAttachmentReadWrite,
+    DepthStencilAttachmentWrite,
+    DepthStencilAttachmentRead,
+    SampledInVertex,
+    SampledInFragment,
+    SampledInCompute,
+    StorageReadInCompute,
+    StorageWriteInCompute,
+    StorageReadWriteInCompute,
+    UniformInVertex,
+    UniformInFragment,
+    UniformInCompute,
+    VertexBuffer,
+    IndexBuffer,
+    IndirectBuffer,
+    TransferSrc,
+    TransferDst,
+    HostRead,
+    HostWrite,
+    Present,
+    General,
+    Custom(ResourceState),
+}
+
+impl ResourceUsage {
+    pub const fn state(&self) -> ResourceState {
+        use AccessFlags2 as A;
+        use ImageLayout as L;
+        use PipelineStageFlags2 as S;
+
+        match self {
+            Self::ColorAttachmentWrite => ResourceState::new(
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::COLOR_ATTACHMENT_WRITE,
+                L::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::ColorAttachmentReadWrite => ResourceState::new(
+                S::COLOR_ATTACHMENT_OUTPUT,
+                A::from_raw(A::COLOR_ATTACHMENT_READ.as_raw() | A::COLOR_ATTACHMENT_WRITE.as_raw()),
+                L::COLOR_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthStencilAttachmentWrite => ResourceState::new(
+                S::from_raw(S::EARLY_FRAGMENT_TESTS.as_raw() | S::LATE_FRAGMENT_TESTS.as_raw()),
+                A::from_raw(
+                    A::DEPTH_STENCIL_ATTACHMENT_READ.as_raw()
+                        | A::DEPTH_STENCIL_ATTACHMENT_WRITE.as_raw(),
+                ),
+                L::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ),
+            Self::DepthStencilAttachmentRead => ResourceState::new(
+                S::from_raw(S::EARLY_FRAGMENT_TESTS.as_raw() | S::LATE_FRAGMENT_TESTS.as_raw()),
+                A::DEPTH_STENCIL_ATTACHMENT_READ,
+                L::DEPTH_STENCIL_READ_ONLY_OPTIMAL,
+            ),
+            Self::SampledInVertex => ResourceState::new(
+                S::VERTEX_SHADER,
+                A::SHADER_SAMPLED_READ,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::SampledInFragment => ResourceState::new(
+                S::FRAGMENT_SHADER,
+                A::SHADER_SAMPLED_READ,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::SampledInCompute => ResourceState::new(
+                S::COMPUTE_SHADER,
+                A::SHADER_SAMPLED_READ,
+                L::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            Self::StorageReadInCompute => {
+                ResourceState::new(S::COMPUTE_SHADER, A::SHADER_STORAGE_READ, L::GENERAL)
+            }
+            Self::StorageWriteInCompute => {
+                ResourceState::new(S::COMPUTE_SHADER, A::SHADER_STORAGE_WRITE, L::GENERAL)
+            }
+            Self::StorageReadWriteInCompute => ResourceState::new(
+                S::COMPUTE_SHADER,
+                A::from_raw(A::SHADER_STORAGE_READ.as_raw() | A::SHADER_STORAGE_WRITE.as_raw()),
+                L::GENERAL,
+            ),
+            Self::UniformInVertex => {
+                ResourceState::new(S::VERTEX_SHADER, A::UNIFORM_READ, L::UNDEFINED)
+            }
+            Self::UniformInFragment => {
+                ResourceState::new(S::FRAGMENT_SHADER, A::UNIFORM_READ, L::UNDEFINED)
+            }
+            Self::UniformInCompute => {
+                ResourceState::new(S::COMPUTE_SHADER, A::UNIFORM_READ, L::UNDEFINED)
+            }
+            Self::VertexBuffer => ResourceState::new(
+                S::VERTEX_ATTRIBUTE_INPUT,
+                A::VERTEX_ATTRIBUTE_READ,
+                L::UNDEFINED,
+            ),
+            Self::IndexBuffer => {
+                ResourceState::new(S::INDEX_INPUT, A::INDEX_READ, L::UNDEFINED)
+            }
+            Self::IndirectBuffer => ResourceState::new(
+                S::DRAW_INDIRECT,
+                A::INDIRECT_COMMAND_READ,
+                L::UNDEFINED,
+            ),
+            Self::TransferSrc => {
+                ResourceState::new(S::TRANSFER, A::TRANSFER_READ, L::TRANSFER_SRC_OPTIMAL)
+            }
+            Self::TransferDst => {
+                ResourceState::new(S::TRANSFER, A::TRANSFER_WRITE, L::TRANSFER_DST_OPTIMAL)
+            }
+            Self::HostRead => ResourceState::new(S::HOST, A::HOST_READ, L::GENERAL),
+            Self::HostWrite => ResourceState::new(S::HOST, A::HOST_WRITE, L::GENERAL),
+            Self::Present => {
+                ResourceState::new(S::BOTTOM_OF_PIPE, A::NONE, L::PRESENT_SRC_KHR)
+            }
+            Self::General => ResourceState::new(
+                S::ALL_COMMANDS,
+                A::from_raw(A::MEMORY_READ.as_raw() | A::MEMORY_WRITE.as_raw()),
+                L::GENERAL,
+            ),
+            Self::Custom(state) => *state,
+        }
+    }
+}
+
+impl From<ResourceState> for ResourceUsage {
+    fn from(value: ResourceState) -> Self {
+        Self::Custom(value)
+    }
+}
+
+struct TrackedImage {
+    aspect_mask: vk::ImageAspectFlags,
+    mip_levels: u32,
+    array_layers: u32,
+    // images used without being registered grow to cover every range they're used with
+    registered: bool,
+    // indexed by `layer * mip_levels + mip`
+    states: Vec<TrackedState>,
+}
+
+impl TrackedImage {
+    #[inline]
+    fn index(&self, mip: u32, layer: u32) -> usize {
+        (layer * self.mip_levels + mip) as usize
+    }
+
+    // makes room for `range` if the image's size isn't known, new subresources start out undefined
+    fn grow_to(&mut self, range: &ImageSubresourceRange) {
+        if self.registered {
+            return;
+        }
+
+        let mip_levels = match range.level_count {
+            vk::REMAINING_MIP_LEVELS => range.base_mip_level + 1,
+            count => range.base_mip_level + count,
+        }
+        .max(self.mip_levels);
+        let array_layers = match range.layer_count {
+            vk::REMAINING_ARRAY_LAYERS => range.base_array_layer + 1,
+            count => range.base_array_layer + count,
+        }
+        .max(self.array_layers);
+        if mip_levels == self.mip_levels && array_layers == self.array_layers {
+            return;
+        }
+
+        let mut states =
+            vec![TrackedState::new(ResourceState::UNDEFINED); (mip_levels * array_layers) as usize];
+        for layer in 0..self.array_layers {
+            for mip in 0..self.mip_levels {
+                states[(layer * mip_levels + mip) as usize] = self.states[self.index(mip, layer)];
+            }
+        }
+        self.mip_levels = mip_levels;
+        self.array_layers = array_layers;
+        self.states = states;
+    }
+
+    fn resolve_range(&self, range: &ImageSubresourceRange) -> (Range<u32>, Range<u32>) {
+        let mip_end = if range.level_count == vk::REMAINING_MIP_LEVELS {
+            self.mip_levels
+        } else {
+            (range.base_mip_level + range.level_count).min(self.mip_levels)
+        };
+        let layer_end = if range.layer_count == vk::REMAINING_ARRAY_LAYERS {
+            self.array_layers
+        } else {
+            (range.base_array_layer + range.layer_count).min(self.array_layers)
+        };
+
+        (
+            range.base_mip_level.min(mip_end)..mip_end,
+            range.base_array_layer.min(layer_end)..layer_end,
+        )
+    }
+}
+
+struct TrackedBuffer {
+    size: u64,
+    // sorted, non-overlapping and covering `0..size`
+    segments: Vec<(Range<u64>, TrackedState)>,
+}
+
+/// Remembers the last known state of every image subresource and buffer range it has seen, and turns
+/// declared uses into the minimal set of barriers needed to get there.
+///
+/// Barriers are batched until `take_barriers` (or `CommandRecorder::resource_barriers`) is called, so
+/// several uses can be declared and then recorded as a single `vkCmdPipelineBarrier2`.
+pub struct ResourceStateTracker {
+    images: HashMap<vk::Image, TrackedImage>,
+    buffers: HashMap<vk::Buffer, TrackedBuffer>,
+    pending_image_barriers: Vec<ImageMemoryBarrier2<'static>>,
+    pending_buffer_barriers: Vec<BufferMemoryBarrier2<'static>>,
+}
+
+impl ResourceStateTracker {
+    pub fn new() -> Self {
+        Self {
+            images: HashMap::new(),
+            buffers: HashMap::new(),
+            pending_image_barriers: Vec::new(),
+            pending_buffer_barriers: Vec::new(),
+        }
+    }
+
+    pub fn register_image(
+        &mut self,
+        image: vk::Image,
+        aspect_mask: vk::ImageAspectFlags,
+        mip_levels: u32,
+        array_layers: u32,
+        state: ResourceState,
+    ) {
+        let mip_levels = mip_levels.max(1);
+        let array_layers = array_layers.max(1);
+        self.images.insert(
+            image,
+            TrackedImage {
+                aspect_mask,
+                mip_levels,
+                array_layers,
+                registered: true,
+                states: vec![TrackedState::new(state); (mip_levels * array_layers) as usize],
+            },
+        );
+    }
+
+    pub fn register_buffer(&mut self, buffer: vk::Buffer, size: u64, state: ResourceState) {
+        self.buffers.insert(
+            buffer,
+            TrackedBuffer {
+                size,
+                segments: vec![(0..size, TrackedState::new(state))],
+            },
+        );
+    }
+
+    pub fn forget_image(&mut self, image: vk::Image) {
+        self.images.remove(&image);
+    }
+
+    pub fn forget_buffer(&mut self, buffer: vk::Buffer) {
+        self.buffers.remove(&buffer);
+    }
+
+    pub fn image_state(&self, image: vk::Image, mip: u32, layer: u32) -> Option<ResourceState> {
+        let tracked = self.images.get(&image)?;
+        if mip >= tracked.mip_levels || layer >= tracked.array_layers {
+            return None;
+        }
+        Some(tracked.states[tracked.index(mip, layer)].state)
+    }
+
+    pub fn buffer_state(&self, buffer: vk::Buffer, offset: u64) -> Option<ResourceState> {
+        self.buffers
+            .get(&buffer)?
+            .segments
+            .iter()
+            .find(|(range, _)| range.contains(&offset))
+            .map(|(_, tracked)| tracked.state)
+    }
+
+    #[inline]
+    pub fn use_image(
+        &mut self,
+        image: vk::Image,
+        subresource_range: ImageSubresourceRange,
+        usage: impl Into<ResourceUsage>,
+    ) {
+        self.transition_image(image, subresource_range, usage.into().state());
+    }
+
+    #[inline]
+    pub fn use_image_on_queue(
+        &mut self,
+        image: vk::Image,
+        subresource_range: ImageSubresourceRange,
+        usage: impl Into<ResourceUsage>,
+        queue_family: u32,
+    ) {
+        self.transition_image(
+            image,
+            subresource_range,
+            usage.into().state().on_queue(queue_family),
+        );
+    }
+
+    #[inline]
+    pub fn use_buffer(&mut self, buffer: vk::Buffer, range: Range<u64>, usage: impl Into<ResourceUsage>) {
+        self.transition_buffer(buffer, range, usage.into().state());
+    }
+
+    #[inline]
+    pub fn use_buffer_on_queue(
+        &mut self,
+        buffer: vk::Buffer,
+        range: Range<u64>,
+        usage: impl Into<ResourceUsage>,
+        queue_family: u32,
+    ) {
+        self.transition_buffer(buffer, range, usage.into().state().on_queue(queue_family));
+    }
+
+    pub fn has_pending_barriers(&self) -> bool {
+        !self.pending_image_barriers.is_empty() || !self.pending_buffer_barriers.is_empty()
+    }
+
+    /// Drains every barrier generated since the last call.
+    pub fn take_barriers(
+        &mut self,
+    ) -> (
+        Vec<ImageMemoryBarrier2<'static>>,
+        Vec<BufferMemoryBarrier2<'static>>,
+    ) {
+        (
+            std::mem::take(&mut self.pending_image_barriers),
+            std::mem::take(&mut self.pending_buffer_barriers),
+        )
+    }
+
+    fn transition_image(
+        &mut self,
+        image: vk::Image,
+        subresource_range: ImageSubresourceRange,
+        next: ResourceState,
+    ) {
+        let tracked = self.images.entry(image).or_insert_with(|| TrackedImage {
+            aspect_mask: subresource_range.aspect_mask,
+            mip_levels: 0,
+            array_layers: 0,
+            registered: false,
+            states: Vec::new(),
+        });
+        tracked.grow_to(&subresource_range);
+
+        let aspect_mask = if subresource_range.aspect_mask.is_empty() {
+            tracked.aspect_mask
+        } else {
+            subresource_range.aspect_mask
+        };
+        let (mips, layers) = tracked.resolve_range(&subresource_range);
+
+        // runs of consecutive mips on one layer that share a source state, then merged across layers
+        let mut runs: Vec<(Range<u32>, Range<u32>, ResourceState)> = Vec::new();
+        for layer in layers {
+            let mut layer_runs: Vec<(Range<u32>, ResourceState)> = Vec::new();
+            for mip in mips.clone() {
+                let index = tracked.index(mip, layer);
+                let state = &mut tracked.states[index];
+                let needs_barrier = state.state.needs_barrier_to(&next);
+                let Some(previous) = state.transition_to(&next, needs_barrier) else {
+                    continue;
+                };
+
+                match layer_runs.last_mut() {
+                    Some((run, state)) if run.end == mip && *state == previous => run.end = mip + 1,
+                    _ => layer_runs.push((mip..mip + 1, previous)),
+                }
+            }
+
+            for (mip_run, previous) in layer_runs {
+                match runs.iter_mut().find(|(run_mips, run_layers, state)| {
+                    *run_mips == mip_run && run_layers.end == layer && *state == previous
+                }) {
+                    Some((_, run_layers, _)) => run_layers.end = layer + 1,
+                    None => runs.push((mip_run, layer..layer + 1, previous)),
+                }
+            }
+        }
+
+        for (mips, layers, previous) in runs {
+            let (src_queue_family_index, dst_queue_family_index) = previous.queue_families_to(&next);
+            self.pending_image_barriers.push(
+                ImageMemoryBarrier2::default()
+                    .image(image)
+                    .subresource_range(ImageSubresourceRange {
+                        aspect_mask,
+                        base_mip_level: mips.start,
+                        level_count: mips.len() as u32,
+                        base_array_layer: layers.start,
+                        layer_count: layers.len() as u32,
+                    })
+                    .src_stage_mask(previous.stage)
+                    .src_access_mask(previous.access & WRITE_ACCESS)
+                    .old_layout(previous.layout)
+                    .dst_stage_mask(next.stage)
+                    .dst_access_mask(next.access)
+                    .new_layout(next.layout)
+                    .src_queue_family_index(src_queue_family_index)
+                    .dst_queue_family_index(dst_queue_family_index),
+            );
+        }
+    }
+
+    fn transition_buffer(&mut self, buffer: vk::Buffer, range: Range<u64>, next: ResourceState) {
+        let tracked = self.buffers.entry(buffer).or_insert_with(|| TrackedBuffer {
+            size: vk::WHOLE_SIZE,
+            segments: vec![(
+                0..vk::WHOLE_SIZE,
+                TrackedState::new(ResourceState::UNDEFINED),
+            )],
+        });
+
+        let range = range.start..range.end.min(tracked.size);
+        if range.is_empty() {
+            return;
+        }
+
+        let mut segments = Vec::with_capacity(tracked.segments.len() + 2);
+        let mut barrier_ranges: Vec<(Range<u64>, ResourceState)> = Vec::new();
+        for (segment, previous) in tracked.segments.drain(..) {
+            if segment.end <= range.start || segment.start >= range.end {
+                segments.push((segment, previous));
+                continue;
+            }
+
+            let overlap = segment.start.max(range.start)..segment.end.min(range.end);
+            if segment.start < overlap.start {
+                segments.push((segment.start..overlap.start, previous));
+            }
+
+            let mut tracked_state = previous;
+            let needs_barrier = previous.state.needs_buffer_barrier_to(&next);
+            if let Some(source) = tracked_state.transition_to(&next, needs_barrier) {
+                match barrier_ranges.last_mut() {
+                    Some((barrier_range, state))
+                        if barrier_range.end == overlap.start && *state == source =>
+                    {
+                        barrier_range.end = overlap.end
+                    }
+                    _ => barrier_ranges.push((overlap.clone(), source)),
+                }
+            }
+            segments.push((overlap.clone(), tracked_state));
+
+            if overlap.end < segment.end {
+                segments.push((overlap.end..segment.end, previous));
+            }
+        }
+
+        // coalesce neighbours that ended up in the same state
+        tracked.segments = segments.into_iter().fold(Vec::new(), |mut merged, (segment, state)| {
+            match merged.last_mut() {
+                Some((last, last_state)) if last.end == segment.start && *last_state == state => {
+                    last.end = segment.end
+                }
+                _ => merged.push((segment, state)),
+            }
+            merged
+        });
+
+        for (barrier_range, previous) in barrier_ranges {
+            let (src_queue_family_index, dst_queue_family_index) = previous.queue_families_to(&next);
+            self.pending_buffer_barriers.push(
+                BufferMemoryBarrier2::default()
+                    .buffer(buffer)
+                    .offset(barrier_range.start)
+                    .size(if barrier_range.end == vk::WHOLE_SIZE {
+                        vk::WHOLE_SIZE
+                    } else {
+                        barrier_range.end - barrier_range.start
+                    })
+                    .src_stage_mask(previous.stage)
+                    .src_access_mask(previous.access & WRITE_ACCESS)
+                    .dst_stage_mask(next.stage)
+                    .dst_access_mask(next.access)
+                    .src_queue_family_index(src_queue_family_index)
+                    .dst_queue_family_index(dst_queue_family_index),
+            );
+        }
+    }
+}
+
+impl Default for ResourceStateTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ash::vk::Handle;
+
+    const COLOR: ImageSubresourceRange = ImageSubresourceRange {
+        aspect_mask: vk::ImageAspectFlags::COLOR,
+        base_mip_level: 0,
+        level_count: vk::REMAINING_MIP_LEVELS,
+        base_array_layer: 0,
+        layer_count: vk::REMAINING_ARRAY_LAYERS,
+    };
+
+    fn image() -> vk::Image {
+        vk::Image::from_raw(1)
+    }
+
+    fn buffer() -> vk::Buffer {
+        vk::Buffer::from_raw(2)
+    }
+
+    #[test]
+    fn image_reads_in_the_same_layout_need_no_barrier() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_image(
+            image(),
+            vk::ImageAspectFlags::COLOR,
+            1,
+            1,
+            ResourceUsage::SampledInVertex.state(),
+        );
+
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInFragment);
+        assert!(!tracker.has_pending_barriers());
+
+        // a later write waits on both readers
+        tracker.use_image(image(), COLOR, ResourceUsage::ColorAttachmentWrite);
+        let (images, buffers) = tracker.take_barriers();
+        assert!(buffers.is_empty());
+        assert_eq!(images.len(), 1);
+        assert_eq!(
+            images[0].src_stage_mask,
+            PipelineStageFlags2::VERTEX_SHADER | PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(images[0].src_access_mask, AccessFlags2::NONE);
+        assert_eq!(images[0].old_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(images[0].new_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+    }
+
+    #[test]
+    fn image_reads_in_different_layouts_need_a_transition() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_image(
+            image(),
+            vk::ImageAspectFlags::COLOR,
+            1,
+            1,
+            ResourceUsage::TransferSrc.state(),
+        );
+
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInFragment);
+        let (images, _) = tracker.take_barriers();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].old_layout, ImageLayout::TRANSFER_SRC_OPTIMAL);
+        assert_eq!(images[0].new_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+    }
+
+    #[test]
+    fn image_write_then_read_makes_the_write_available() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_image(
+            image(),
+            vk::ImageAspectFlags::COLOR,
+            2,
+            1,
+            ResourceUsage::ColorAttachmentWrite.state(),
+        );
+
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInFragment);
+        let (images, _) = tracker.take_barriers();
+        assert_eq!(images.len(), 1);
+        assert_eq!(
+            images[0].src_stage_mask,
+            PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT
+        );
+        assert_eq!(
+            images[0].src_access_mask,
+            AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(
+            images[0].dst_stage_mask,
+            PipelineStageFlags2::FRAGMENT_SHADER
+        );
+        assert_eq!(images[0].dst_access_mask, AccessFlags2::SHADER_SAMPLED_READ);
+        assert_eq!(images[0].subresource_range.level_count, 2);
+        assert_eq!(images[0].src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+        assert_eq!(images[0].dst_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    }
+
+    #[test]
+    fn image_reads_on_another_queue_transfer_ownership() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_image(
+            image(),
+            vk::ImageAspectFlags::COLOR,
+            1,
+            1,
+            ResourceUsage::SampledInCompute.state().on_queue(1),
+        );
+
+        tracker.use_image_on_queue(image(), COLOR, ResourceUsage::SampledInFragment, 0);
+        let (images, _) = tracker.take_barriers();
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].old_layout, images[0].new_layout);
+        assert_eq!(images[0].src_queue_family_index, 1);
+        assert_eq!(images[0].dst_queue_family_index, 0);
+        assert_eq!(tracker.image_state(image(), 0, 0).unwrap().queue_family, 0);
+    }
+
+    #[test]
+    fn buffer_reads_need_no_barrier_whatever_their_layout() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_buffer(buffer(), 256, ResourceUsage::TransferSrc.state());
+
+        // `TransferSrc` carries an image layout, which buffers don't have
+        tracker.use_buffer(buffer(), 0..256, ResourceUsage::VertexBuffer);
+        tracker.use_buffer(buffer(), 0..128, ResourceUsage::UniformInFragment);
+        assert!(!tracker.has_pending_barriers());
+
+        let state = tracker.buffer_state(buffer(), 0).unwrap();
+        assert_eq!(
+            state.stage,
+            PipelineStageFlags2::TRANSFER
+                | PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT
+                | PipelineStageFlags2::FRAGMENT_SHADER
+        );
+    }
+
+    #[test]
+    fn buffer_write_then_read_covers_only_the_written_range() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_buffer(buffer(), 256, ResourceUsage::VertexBuffer.state());
+
+        tracker.use_buffer(buffer(), 64..128, ResourceUsage::TransferDst);
+        let (_, buffers) = tracker.take_barriers();
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers[0].src_access_mask, AccessFlags2::NONE);
+        assert_eq!(buffers[0].dst_access_mask, AccessFlags2::TRANSFER_WRITE);
+
+        tracker.use_buffer(buffer(), 0..256, ResourceUsage::VertexBuffer);
+        let (images, buffers) = tracker.take_barriers();
+        assert!(images.is_empty());
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers[0].offset, 64);
+        assert_eq!(buffers[0].size, 64);
+        assert_eq!(buffers[0].src_stage_mask, PipelineStageFlags2::TRANSFER);
+        assert_eq!(buffers[0].src_access_mask, AccessFlags2::TRANSFER_WRITE);
+        assert_eq!(
+            buffers[0].dst_stage_mask,
+            PipelineStageFlags2::VERTEX_ATTRIBUTE_INPUT
+        );
+        assert_eq!(
+            buffers[0].dst_access_mask,
+            AccessFlags2::VERTEX_ATTRIBUTE_READ
+        );
+    }
+
+    #[test]
+    fn buffer_reads_on_another_queue_transfer_ownership() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_buffer(
+            buffer(),
+            256,
+            ResourceUsage::UniformInCompute.state().on_queue(1),
+        );
+
+        tracker.use_buffer_on_queue(buffer(), 0..256, ResourceUsage::UniformInVertex, 0);
+        let (_, buffers) = tracker.take_barriers();
+        assert_eq!(buffers.len(), 1);
+        assert_eq!(buffers[0].size, 256);
+        assert_eq!(buffers[0].src_queue_family_index, 1);
+        assert_eq!(buffers[0].dst_queue_family_index, 0);
+
+        // without a queue family on either side there's nothing to transfer
+        tracker.use_buffer(buffer(), 0..256, ResourceUsage::UniformInVertex);
+        assert!(!tracker.has_pending_barriers());
+    }
+
+    #[test]
+    fn image_reads_in_other_stages_wait_on_the_write_again() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_image(
+            image(),
+            vk::ImageAspectFlags::COLOR,
+            1,
+            1,
+            ResourceUsage::ColorAttachmentWrite.state(),
+        );
+
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInFragment);
+        assert_eq!(tracker.take_barriers().0.len(), 1);
+
+        // the write was only made visible to the fragment shader
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInVertex);
+        let (images, _) = tracker.take_barriers();
+        assert_eq!(images.len(), 1);
+        assert!(
+            images[0]
+                .src_stage_mask
+                .contains(PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+        );
+        assert_eq!(
+            images[0].src_access_mask,
+            AccessFlags2::COLOR_ATTACHMENT_WRITE
+        );
+        assert_eq!(images[0].dst_stage_mask, PipelineStageFlags2::VERTEX_SHADER);
+        assert_eq!(images[0].dst_access_mask, AccessFlags2::SHADER_SAMPLED_READ);
+        assert_eq!(images[0].old_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(images[0].new_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+
+        // both stages are covered now, and a later write waits on both readers
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInFragment);
+        tracker.use_image(image(), COLOR, ResourceUsage::SampledInVertex);
+        assert!(!tracker.has_pending_barriers());
+        tracker.use_image(image(), COLOR, ResourceUsage::ColorAttachmentWrite);
+        let (images, _) = tracker.take_barriers();
+        assert_eq!(
+            images[0].src_stage_mask,
+            PipelineStageFlags2::VERTEX_SHADER | PipelineStageFlags2::FRAGMENT_SHADER
+        );
+    }
+
+    #[test]
+    fn buffer_reads_in_other_stages_wait_on_the_write_again() {
+        let mut tracker = ResourceStateTracker::new();
+        tracker.register_buffer(buffer(), 256, ResourceUsage::TransferDst.state());
+
+        tracker.use_buffer(buffer(), 0..256, ResourceUsage::UniformInFragment);
+        tracker.use_buffer(buffer(), 0..256, ResourceUsage::UniformInVertex);
+        let (_, buffers) = tracker.take_barriers();
+        assert_eq!(buffers.len(), 2);
+        assert_eq!(buffers[1].src_access_mask, AccessFlags2::TRANSFER_WRITE);
+        assert_eq!(buffers[1].dst_stage_mask, PipelineStageFlags2::VERTEX_SHADER);
+    }
+
+    #[test]
+    fn unregistered_images_grow_with_wider_ranges() {
+        let mut tracker = ResourceStateTracker::new();
+        let mip = |mip: u32, count: u32| ImageSubresourceRange {
+            base_mip_level: mip,
+            level_count: count,
+            ..COLOR
+        };
+
+        tracker.use_image(image(), mip(0, vk::REMAINING_MIP_LEVELS), ResourceUsage::TransferDst);
+        assert_eq!(tracker.take_barriers().0.len(), 1);
+        assert!(tracker.image_state(image(), 1, 0).is_none());
+
+        tracker.use_image(image(), mip(0, 4), ResourceUsage::SampledInFragment);
+        let (images, _) = tracker.take_barriers();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].old_layout, ImageLayout::TRANSFER_DST_OPTIMAL);
+        assert_eq!(images[0].subresource_range.level_count, 1);
+        assert_eq!(images[1].old_layout, ImageLayout::UNDEFINED);
+        assert_eq!(images[1].subresource_range.base_mip_level, 1);
+        assert_eq!(images[1].subresource_range.level_count, 3);
+        assert_eq!(
+            tracker.image_state(image(), 3, 0).unwrap().layout,
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        );
+    }
+}
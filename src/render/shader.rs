@@ -1,38 +1,154 @@
-use std::{ops::{Div, Rem}, path::Path};
+use std::{ops::{Div, Rem}, path::{Component, Path, PathBuf}};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
+use anyhow::anyhow;
 use ash::vk;
 use log::warn;
 
 use super::RenderSystem;
+use super::reflection::ShaderReflection;
 
 pub struct ShaderModule {
     shader_module: vk::ShaderModule,
+    reflection: ShaderReflection,
+    dependencies: Vec<PathBuf>,
     device: ash::Device,
 }
 
+/// Resolves `#include` directives in GLSL. `#include "file"` is looked up relative to the including file
+/// first and then like `#include <file>`, which tries the virtual files and then each search path in
+/// order.
+#[derive(Clone, Debug, Default)]
+pub struct ShaderIncludes {
+    search_paths: Vec<PathBuf>,
+    // keyed by normalized path, see `normalize_path`
+    virtual_files: HashMap<PathBuf, String>,
+}
+
+/// The source language of a shader file, see `ShaderLanguage::from_path`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ShaderLanguage {
+    Glsl,
+    Hlsl,
+    SpirV,
+}
+
+/// Preprocessor macros by name, with an optional value. Ordered, so equal sets compare and hash equal.
+pub type ShaderDefines = BTreeMap<String, Option<String>>;
+
+#[derive(Clone, Debug, Default)]
+pub struct ShaderCompileOptions {
+    pub includes: ShaderIncludes,
+    /// Defined as if by `#define` before the first line of the source.
+    pub defines: ShaderDefines,
+    /// The name of the entry point, `main` if unset.
+    pub entry_point: Option<String>,
+    /// Names the module in debugging tools, taking precedence over the file name. Shaders compiled from
+    /// memory without a file name are unnamed otherwise.
+    pub debug_name: Option<String>,
+    pub hlsl: HlslOptions,
+}
+
+/// Options only used when compiling HLSL.
+#[derive(Clone, Debug)]
+pub struct HlslOptions {
+    /// Exposed to the source as `__SHADER_TARGET_MAJOR` and `__SHADER_TARGET_MINOR`, like DXC does.
+    /// shaderc has no notion of shader models, so it doesn't restrict which features can be used.
+    pub shader_model: (u32, u32),
+    /// Maps `register(x<n>, space<m>)` to binding `n` in set `m`.
+    pub register_mapping: bool,
+    /// Assigns bindings to resources that don't have a register.
+    pub auto_bind: bool,
+    /// Added to the bindings of each register class, e.g. to keep `t0` and `s0` apart within a set.
+    pub binding_bases: Vec<(shaderc::ResourceKind, u32)>,
+    /// Explicit `(register, set, binding)` mappings, e.g. `("t4", 1, 0)`, taking precedence over the
+    /// register mapping.
+    pub register_bindings: Vec<(String, u32, u32)>,
+    /// Uses HLSL packing rules for the member offsets of constant buffers.
+    pub hlsl_offsets: bool,
+}
+
 impl ShaderModule {
     pub fn new(render_system: &RenderSystem, code: &[u32]) -> anyhow::Result<Self> {
         unsafe {
             let create_info = vk::ShaderModuleCreateInfo::default().code(code);
+            let reflection = ShaderReflection::new(code)?;
             let device = render_system.device().clone();
             Ok(Self {
                 shader_module: device.create_shader_module(&create_info, None)?,
+                reflection,
+                dependencies: Vec::new(),
                 device,
             })
         }
     }
 
+    /// Loads the shader at `path`, picking the language with `ShaderLanguage::from_path` and inferring the
+    /// stage. Returns the stage along with the module.
+    pub fn load(render_system: &RenderSystem, path: impl AsRef<Path>) -> anyhow::Result<(vk::ShaderStageFlags, Self)> {
+        Self::load_with_options(render_system, path, &ShaderCompileOptions::default())
+    }
+
+    /// The stage is taken from the first extension naming one, e.g. `lit.frag` or `lit.frag.hlsl`, and
+    /// otherwise from a `#pragma shader_stage(fragment)` in the source. SPIR-V binaries without a stage
+    /// extension use the stage of their entry points if they're all for the same one.
+    pub fn load_with_options(
+        render_system: &RenderSystem,
+        path: impl AsRef<Path>,
+        options: &ShaderCompileOptions,
+    ) -> anyhow::Result<(vk::ShaderStageFlags, Self)> {
+        let path = path.as_ref();
+        let language = ShaderLanguage::from_path(path);
+        if language == ShaderLanguage::SpirV {
+            let module = Self::load_spv(render_system, path)?;
+            if let Some(debug_name) = options.debug_name.as_deref() {
+                module.set_debug_name(render_system, debug_name);
+            }
+            let stages = module.reflection.stages();
+            let stage = match stage_from_path(path) {
+                Some(stage) => stage,
+                None if stages.as_raw().count_ones() == 1 => stages,
+                None => return Err(anyhow!("Can't infer the shader stage of '{}', its entry points are for {:?}", path.display(), stages)),
+            };
+            return Ok((stage, module));
+        }
+
+        let code = std::fs::read_to_string(path)?;
+        let stage = stage_from_path(path)
+            .or_else(|| stage_from_pragma(&code))
+            .ok_or_else(|| anyhow!("Can't infer the shader stage of '{}', use a stage extension or #pragma shader_stage", path.display()))?;
+        let language = match language {
+            ShaderLanguage::Hlsl => shaderc::SourceLanguage::HLSL,
+            _ => shaderc::SourceLanguage::GLSL,
+        };
+        let module = Self::compile(render_system, &code, shader_kind(stage)?, path, options, language)?;
+        Ok((stage, module))
+    }
+
     pub fn load_spv(render_system: &RenderSystem, path: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let code_u8 = std::fs::read(path)?;
+        let code_u8 = std::fs::read(path.as_ref())?;
         let usable = code_u8.len().div(std::mem::size_of::<u32>());
         let code = unsafe { std::mem::transmute::<&[u8], &[u32]>(&code_u8[0..usable]) };
 
-        Self::new(render_system, code)
+        let mut shader_module = Self::new(render_system, code)?;
+        shader_module.set_debug_name(render_system, &path.as_ref().to_string_lossy());
+        shader_module.dependencies = vec![path.as_ref().to_path_buf()];
+        Ok(shader_module)
     }
 
     pub fn load_glsl(render_system: &RenderSystem, path: impl AsRef<Path>, kind: shaderc::ShaderKind) -> anyhow::Result<Self> {
+        Self::load_glsl_with_options(render_system, path, kind, &ShaderCompileOptions::default())
+    }
+
+    pub fn load_glsl_with_options(
+        render_system: &RenderSystem,
+        path: impl AsRef<Path>,
+        kind: shaderc::ShaderKind,
+        options: &ShaderCompileOptions,
+    ) -> anyhow::Result<Self> {
         let code = std::fs::read_to_string(path.as_ref())?;
-        Self::load_glsl_from_memory_with_filename(render_system, code.as_str(), kind, path)
+        Self::load_glsl_from_memory_with_options(render_system, code.as_str(), kind, path, options)
     }
 
     pub fn load_glsl_from_memory(render_system: &RenderSystem, code: &str, kind: shaderc::ShaderKind) -> anyhow::Result<Self> {
@@ -40,9 +156,86 @@ impl ShaderModule {
     }
 
     pub fn load_glsl_from_memory_with_filename(render_system: &RenderSystem, code: &str, kind: shaderc::ShaderKind, filename: impl AsRef<Path>) -> anyhow::Result<Self> {
-        let filename = filename.as_ref().to_str().unwrap_or("");
+        Self::load_glsl_from_memory_with_options(render_system, code, kind, filename, &ShaderCompileOptions::default())
+    }
+
+    /// `filename` is used for diagnostics and to resolve relative includes, and recorded as the first
+    /// dependency unless it's empty.
+    pub fn load_glsl_from_memory_with_options(
+        render_system: &RenderSystem,
+        code: &str,
+        kind: shaderc::ShaderKind,
+        filename: impl AsRef<Path>,
+        options: &ShaderCompileOptions,
+    ) -> anyhow::Result<Self> {
+        Self::compile(render_system, code, kind, filename.as_ref(), options, shaderc::SourceLanguage::GLSL)
+    }
+
+    pub fn load_hlsl(render_system: &RenderSystem, path: impl AsRef<Path>, kind: shaderc::ShaderKind, entry_point: &str) -> anyhow::Result<Self> {
+        let options = ShaderCompileOptions::default().with_entry_point(entry_point);
+        Self::load_hlsl_with_options(render_system, path, kind, &options)
+    }
+
+    pub fn load_hlsl_with_options(
+        render_system: &RenderSystem,
+        path: impl AsRef<Path>,
+        kind: shaderc::ShaderKind,
+        options: &ShaderCompileOptions,
+    ) -> anyhow::Result<Self> {
+        let code = std::fs::read_to_string(path.as_ref())?;
+        Self::load_hlsl_from_memory_with_options(render_system, code.as_str(), kind, path, options)
+    }
+
+    pub fn load_hlsl_from_memory(render_system: &RenderSystem, code: &str, kind: shaderc::ShaderKind, entry_point: &str) -> anyhow::Result<Self> {
+        let options = ShaderCompileOptions::default().with_entry_point(entry_point);
+        Self::load_hlsl_from_memory_with_options(render_system, code, kind, "", &options)
+    }
+
+    /// Like `load_glsl_from_memory_with_options`. The module's entry point keeps the HLSL function's name,
+    /// so that's the name to use in the pipeline's shader stages.
+    pub fn load_hlsl_from_memory_with_options(
+        render_system: &RenderSystem,
+        code: &str,
+        kind: shaderc::ShaderKind,
+        filename: impl AsRef<Path>,
+        options: &ShaderCompileOptions,
+    ) -> anyhow::Result<Self> {
+        Self::compile(render_system, code, kind, filename.as_ref(), options, shaderc::SourceLanguage::HLSL)
+    }
+
+    fn compile(
+        render_system: &RenderSystem,
+        code: &str,
+        kind: shaderc::ShaderKind,
+        filename: &Path,
+        options: &ShaderCompileOptions,
+        language: shaderc::SourceLanguage,
+    ) -> anyhow::Result<Self> {
+        let filename = filename.to_str().unwrap_or("");
+        let included = RefCell::new(BTreeSet::new());
+        let mut compile_options = shaderc::CompileOptions::new()?;
+        compile_options.set_source_language(language);
+        if language == shaderc::SourceLanguage::HLSL {
+            options.hlsl.apply(&mut compile_options);
+        }
+        for (name, value) in options.defines.iter() {
+            compile_options.add_macro_definition(name, value.as_deref());
+        }
+        compile_options.set_include_callback(|requested, include_type, requesting, _depth| {
+            let (path, content) = options.includes.resolve(requested, include_type, requesting)?;
+            let resolved_name = path.to_string_lossy().into_owned();
+            included.borrow_mut().insert(path);
+            Ok(shaderc::ResolvedInclude { resolved_name, content })
+        });
+
         let compiler = shaderc::Compiler::new()?;
-        let spirv = compiler.compile_into_spirv(code, kind, filename, "main", None)?;
+        let spirv = compiler.compile_into_spirv(
+            code,
+            kind,
+            filename,
+            options.entry_point.as_deref().unwrap_or("main"),
+            Some(&compile_options),
+        )?;
         if spirv.get_num_warnings() > 0 {
             if filename.is_empty() {
                 warn!("Warning while building shader: {}", spirv.get_warning_messages());
@@ -50,13 +243,280 @@ impl ShaderModule {
                 warn!("Warning while building shader '{}': {}", filename, spirv.get_warning_messages());
             }
         }
-        
-        Self::new(render_system, spirv.as_binary())
+
+        let mut shader_module = Self::new(render_system, spirv.as_binary())?;
+        if let Some(debug_name) = options.debug_name.as_deref().or(Some(filename).filter(|f| !f.is_empty())) {
+            shader_module.set_debug_name(render_system, debug_name);
+        }
+        if !filename.is_empty() {
+            shader_module.dependencies.push(PathBuf::from(filename));
+        }
+        shader_module.dependencies.extend(included.into_inner());
+        Ok(shader_module)
     }
 
     pub fn handle(&self) -> vk::ShaderModule {
         self.shader_module
     }
+
+    /// The module's entry points, descriptor bindings, push constants and stage interfaces.
+    pub fn reflection(&self) -> &ShaderReflection {
+        &self.reflection
+    }
+
+    /// The files the module was compiled from: the source file, if it has one, followed by every file it
+    /// included. Virtual files are listed by their virtual path.
+    pub fn dependencies(&self) -> &[PathBuf] {
+        &self.dependencies
+    }
+
+    pub fn set_debug_name(&self, render_system: &RenderSystem, name: &str) {
+        render_system.set_debug_name(self.shader_module, name);
+    }
+}
+
+impl ShaderIncludes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_search_path(&mut self, path: impl Into<PathBuf>) {
+        self.search_paths.push(path.into());
+    }
+
+    pub fn with_search_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.add_search_path(path);
+        self
+    }
+
+    /// Makes `source` includable as `path`, e.g. for shaders embedded with `include_str!`. Virtual files
+    /// take precedence over files on disk.
+    pub fn add_virtual_file(&mut self, path: impl AsRef<Path>, source: impl Into<String>) {
+        self.virtual_files.insert(normalize_path(path.as_ref()), source.into());
+    }
+
+    pub fn with_virtual_file(mut self, path: impl AsRef<Path>, source: impl Into<String>) -> Self {
+        self.add_virtual_file(path, source);
+        self
+    }
+
+    #[inline]
+    pub fn search_paths(&self) -> &[PathBuf] {
+        &self.search_paths
+    }
+
+    /// Finds the file `requested` refers to when included from `requesting`, returning its path and
+    /// contents. The error is meant for the compiler's diagnostics.
+    pub fn resolve(
+        &self,
+        requested: &str,
+        include_type: shaderc::IncludeType,
+        requesting: &str,
+    ) -> Result<(PathBuf, String), String> {
+        if include_type == shaderc::IncludeType::Relative {
+            let base = Path::new(requesting).parent().unwrap_or(Path::new(""));
+            if let Some(found) = self.find(&base.join(requested)) {
+                return Ok(found);
+            }
+        }
+
+        // only virtual files, disk lookups go through the search paths rather than the working directory
+        if let Some(found) = self.find_virtual(Path::new(requested)) {
+            return Ok(found);
+        }
+        for search_path in self.search_paths.iter() {
+            if let Some(found) = self.find(&search_path.join(requested)) {
+                return Ok(found);
+            }
+        }
+
+        Err(format!("Cannot find '{}' included from '{}'", requested, requesting))
+    }
+
+    fn find(&self, path: &Path) -> Option<(PathBuf, String)> {
+        if let Some(found) = self.find_virtual(path) {
+            return Some(found);
+        }
+
+        let path = normalize_path(path);
+        let source = std::fs::read_to_string(&path).ok()?;
+        Some((path, source))
+    }
+
+    fn find_virtual(&self, path: &Path) -> Option<(PathBuf, String)> {
+        let path = normalize_path(path);
+        let source = self.virtual_files.get(&path)?;
+        Some((path, source.clone()))
+    }
+}
+
+impl ShaderCompileOptions {
+    pub fn with_includes(mut self, includes: ShaderIncludes) -> Self {
+        self.includes = includes;
+        self
+    }
+
+    /// Defines `name`, replacing any earlier value.
+    pub fn define(&mut self, name: impl Into<String>, value: Option<&str>) {
+        self.defines.insert(name.into(), value.map(str::to_string));
+    }
+
+    pub fn with_define(mut self, name: impl Into<String>, value: Option<&str>) -> Self {
+        self.define(name, value);
+        self
+    }
+
+    pub fn with_defines(mut self, defines: ShaderDefines) -> Self {
+        self.defines.extend(defines);
+        self
+    }
+
+    pub fn with_entry_point(mut self, entry_point: impl Into<String>) -> Self {
+        self.entry_point = Some(entry_point.into());
+        self
+    }
+
+    pub fn with_debug_name(mut self, debug_name: impl Into<String>) -> Self {
+        self.debug_name = Some(debug_name.into());
+        self
+    }
+
+    pub fn with_hlsl(mut self, hlsl: HlslOptions) -> Self {
+        self.hlsl = hlsl;
+        self
+    }
+}
+
+impl Default for HlslOptions {
+    fn default() -> Self {
+        Self {
+            shader_model: (6, 0),
+            register_mapping: true,
+            auto_bind: true,
+            binding_bases: Vec::new(),
+            register_bindings: Vec::new(),
+            hlsl_offsets: true,
+        }
+    }
+}
+
+impl HlslOptions {
+    pub fn with_shader_model(mut self, major: u32, minor: u32) -> Self {
+        self.shader_model = (major, minor);
+        self
+    }
+
+    pub fn with_binding_base(mut self, kind: shaderc::ResourceKind, base: u32) -> Self {
+        self.binding_bases.push((kind, base));
+        self
+    }
+
+    pub fn with_register_binding(mut self, register: impl Into<String>, set: u32, binding: u32) -> Self {
+        self.register_bindings.push((register.into(), set, binding));
+        self
+    }
+
+    fn apply(&self, compile_options: &mut shaderc::CompileOptions) {
+        let (major, minor) = self.shader_model;
+        compile_options.add_macro_definition("__SHADER_TARGET_MAJOR", Some(&major.to_string()));
+        compile_options.add_macro_definition("__SHADER_TARGET_MINOR", Some(&minor.to_string()));
+        compile_options.set_hlsl_io_mapping(self.register_mapping);
+        compile_options.set_auto_bind_uniforms(self.auto_bind);
+        compile_options.set_hlsl_offsets(self.hlsl_offsets);
+        for (kind, base) in self.binding_bases.iter() {
+            compile_options.set_binding_base(*kind, *base);
+        }
+        for (register, set, binding) in self.register_bindings.iter() {
+            compile_options.set_hlsl_register_set_and_binding(register, &set.to_string(), &binding.to_string());
+        }
+    }
+}
+
+impl ShaderLanguage {
+    /// `.spv` is SPIR-V, `.hlsl` is HLSL, and everything else is GLSL.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("spv") => Self::SpirV,
+            Some("hlsl") => Self::Hlsl,
+            _ => Self::Glsl,
+        }
+    }
+}
+
+/// The stage of a conventional shader file extension like `vert` or `frag`.
+pub fn shader_stage_from_extension(extension: &str) -> Option<vk::ShaderStageFlags> {
+    Some(match extension {
+        "vert" => vk::ShaderStageFlags::VERTEX,
+        "frag" => vk::ShaderStageFlags::FRAGMENT,
+        "comp" => vk::ShaderStageFlags::COMPUTE,
+        "geom" => vk::ShaderStageFlags::GEOMETRY,
+        "tesc" => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        "tese" => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        "task" => vk::ShaderStageFlags::TASK_EXT,
+        "mesh" => vk::ShaderStageFlags::MESH_EXT,
+        _ => return None,
+    })
+}
+
+// the first stage extension after the file stem, so `lit.frag.spv` is a fragment shader
+fn stage_from_path(path: &Path) -> Option<vk::ShaderStageFlags> {
+    let name = path.file_name()?.to_str()?;
+    name.split('.').skip(1).find_map(shader_stage_from_extension)
+}
+
+// `#pragma shader_stage(<stage>)` with the stage names glslang understands
+fn stage_from_pragma(code: &str) -> Option<vk::ShaderStageFlags> {
+    code.lines().find_map(|line| {
+        let pragma = line.trim().strip_prefix('#')?.trim_start().strip_prefix("pragma")?;
+        let argument = pragma.trim_start().strip_prefix("shader_stage")?.trim_start();
+        let stage = argument.strip_prefix('(')?.split(')').next()?.trim();
+        Some(match stage {
+            "vertex" => vk::ShaderStageFlags::VERTEX,
+            "fragment" => vk::ShaderStageFlags::FRAGMENT,
+            "compute" => vk::ShaderStageFlags::COMPUTE,
+            "geometry" => vk::ShaderStageFlags::GEOMETRY,
+            "tesscontrol" => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+            "tesseval" => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+            "task" => vk::ShaderStageFlags::TASK_EXT,
+            "mesh" => vk::ShaderStageFlags::MESH_EXT,
+            _ => return None,
+        })
+    })
+}
+
+/// The shaderc kind to compile a shader for `stage` as.
+pub fn shader_kind(stage: vk::ShaderStageFlags) -> anyhow::Result<shaderc::ShaderKind> {
+    Ok(match stage {
+        vk::ShaderStageFlags::VERTEX => shaderc::ShaderKind::Vertex,
+        vk::ShaderStageFlags::FRAGMENT => shaderc::ShaderKind::Fragment,
+        vk::ShaderStageFlags::COMPUTE => shaderc::ShaderKind::Compute,
+        vk::ShaderStageFlags::GEOMETRY => shaderc::ShaderKind::Geometry,
+        vk::ShaderStageFlags::TESSELLATION_CONTROL => shaderc::ShaderKind::TessControl,
+        vk::ShaderStageFlags::TESSELLATION_EVALUATION => shaderc::ShaderKind::TessEvaluation,
+        vk::ShaderStageFlags::TASK_EXT => shaderc::ShaderKind::Task,
+        vk::ShaderStageFlags::MESH_EXT => shaderc::ShaderKind::Mesh,
+        _ => return Err(anyhow!("Can't compile a shader for stage {:?}", stage)),
+    })
+}
+
+/// Resolves `.` and `..` without touching the filesystem, so virtual paths can use them too.
+pub(crate) fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                // there's nothing above the root
+                Some(Component::RootDir) => (),
+                _ => normalized.push(component),
+            },
+            component => normalized.push(component),
+        }
+    }
+    normalized
 }
 
 impl Drop for ShaderModule {
@@ -65,4 +525,105 @@ impl Drop for ShaderModule {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shaderc::IncludeType;
 
+    #[test]
+    fn stages_come_from_the_first_stage_extension() {
+        assert_eq!(stage_from_path(Path::new("shaders/lit.frag")), Some(vk::ShaderStageFlags::FRAGMENT));
+        assert_eq!(stage_from_path(Path::new("shaders/lit.frag.hlsl")), Some(vk::ShaderStageFlags::FRAGMENT));
+        assert_eq!(stage_from_path(Path::new("shaders/lit.frag.spv")), Some(vk::ShaderStageFlags::FRAGMENT));
+        assert_eq!(stage_from_path(Path::new("shaders/skin.vert.glsl")), Some(vk::ShaderStageFlags::VERTEX));
+        // only the file name counts, not the directories
+        assert_eq!(stage_from_path(Path::new("shaders.frag/lit.glsl")), None);
+        assert_eq!(stage_from_path(Path::new("shaders/lit.hlsl")), None);
+    }
+
+    #[test]
+    fn stages_come_from_the_pragma_without_a_stage_extension() {
+        let code = "#version 450\n#pragma shader_stage(fragment)\nvoid main() {}\n";
+        assert_eq!(stage_from_path(Path::new("shaders/lit.glsl")), None);
+        assert_eq!(stage_from_pragma(code), Some(vk::ShaderStageFlags::FRAGMENT));
+
+        assert_eq!(stage_from_pragma("  #  pragma   shader_stage( compute )"), Some(vk::ShaderStageFlags::COMPUTE));
+        assert_eq!(stage_from_pragma("#pragma shader_stage(tesseval)"), Some(vk::ShaderStageFlags::TESSELLATION_EVALUATION));
+    }
+
+    #[test]
+    fn malformed_pragmas_have_no_stage() {
+        for code in [
+            "#pragma shader_stage(pixel)",
+            "#pragma shader_stage fragment",
+            "#pragma shader_stage()",
+            "#pragma once",
+            "// #pragma shader_stage(fragment)",
+            "void main() {}",
+        ] {
+            assert_eq!(stage_from_pragma(code), None, "{}", code);
+        }
+    }
+
+    #[test]
+    fn normalizes_dots_without_touching_the_filesystem() {
+        assert_eq!(normalize_path(Path::new("shaders/./lighting/../common.glsl")), Path::new("shaders/common.glsl"));
+        assert_eq!(normalize_path(Path::new("../shaders/lit.frag")), Path::new("../shaders/lit.frag"));
+        assert_eq!(normalize_path(Path::new("a/../../b")), Path::new("../b"));
+        assert_eq!(normalize_path(Path::new("/../shaders")), Path::new("/shaders"));
+    }
+
+    #[test]
+    fn relative_includes_resolve_next_to_the_including_file() {
+        let includes = ShaderIncludes::new()
+            .with_virtual_file("shaders/lighting/brdf.glsl", "brdf")
+            .with_virtual_file("shaders/common.glsl", "common");
+
+        let (path, source) = includes.resolve("brdf.glsl", IncludeType::Relative, "shaders/lighting/lit.frag").unwrap();
+        assert_eq!((path.as_path(), source.as_str()), (Path::new("shaders/lighting/brdf.glsl"), "brdf"));
+
+        let (path, source) = includes.resolve("../common.glsl", IncludeType::Relative, "shaders/lighting/lit.frag").unwrap();
+        assert_eq!((path.as_path(), source.as_str()), (Path::new("shaders/common.glsl"), "common"));
+
+        // standard includes don't look next to the including file
+        assert!(includes.resolve("brdf.glsl", IncludeType::Standard, "shaders/lighting/lit.frag").is_err());
+    }
+
+    #[test]
+    fn relative_includes_fall_back_to_the_search_paths() {
+        let includes = ShaderIncludes::new()
+            .with_search_path("engine/include")
+            .with_virtual_file("engine/include/common.glsl", "engine common")
+            .with_virtual_file("noise.glsl", "noise");
+
+        let (path, source) = includes.resolve("common.glsl", IncludeType::Relative, "shaders/lit.frag").unwrap();
+        assert_eq!((path.as_path(), source.as_str()), (Path::new("engine/include/common.glsl"), "engine common"));
+
+        let (path, _) = includes.resolve("./noise.glsl", IncludeType::Standard, "shaders/lit.frag").unwrap();
+        assert_eq!(path, Path::new("noise.glsl"));
+
+        let error = includes.resolve("missing.glsl", IncludeType::Relative, "shaders/lit.frag").unwrap_err();
+        assert_eq!(error, "Cannot find 'missing.glsl' included from 'shaders/lit.frag'");
+    }
+
+    #[test]
+    fn virtual_files_take_precedence_over_disk() {
+        let dir = std::env::temp_dir().join(format!("vkism-includes-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("common.glsl"), "on disk").unwrap();
+        std::fs::write(dir.join("noise.glsl"), "noise on disk").unwrap();
+
+        let includes = ShaderIncludes::new().with_search_path(&dir).with_virtual_file(dir.join("common.glsl"), "virtual");
+        let requesting = dir.join("lit.frag");
+        let requesting = requesting.to_str().unwrap();
+
+        for include_type in [IncludeType::Relative, IncludeType::Standard] {
+            let (path, source) = includes.resolve("common.glsl", include_type, requesting).unwrap();
+            assert_eq!((path, source.as_str()), (dir.join("common.glsl"), "virtual"));
+        }
+        let (_, source) = includes.resolve("noise.glsl", IncludeType::Standard, requesting).unwrap();
+        assert_eq!(source, "noise on disk");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
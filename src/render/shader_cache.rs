@@ -0,0 +1,193 @@
+use crate::render::RenderSystem;
+use crate::render::shader::{
+    ShaderCompileOptions, ShaderDefines, ShaderLanguage, ShaderModule, shader_kind,
+};
+use anyhow::anyhow;
+use ash::vk;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// Identifies one compiled variant of a shader source file.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderPermutation {
+    pub path: PathBuf,
+    pub defines: ShaderDefines,
+    pub stage: vk::ShaderStageFlags,
+    pub entry_point: String,
+}
+
+/// Compiles variants of shaders on first use and shares them between everything asking for the same
+/// permutation. The language is picked with `ShaderLanguage::from_path`. Every GLSL or HLSL variant is
+/// compiled with the cache's options, plus the permutation's defines (overriding defines of the same name)
+/// and entry point. SPIR-V binaries are loaded as is, so their permutations can't have defines.
+pub struct ShaderPermutationCache {
+    options: ShaderCompileOptions,
+    modules: HashMap<ShaderPermutation, Rc<ShaderModule>>,
+}
+
+impl ShaderPermutation {
+    pub fn new(path: impl Into<PathBuf>, stage: vk::ShaderStageFlags) -> Self {
+        Self {
+            path: path.into(),
+            defines: ShaderDefines::new(),
+            stage,
+            entry_point: "main".to_string(),
+        }
+    }
+
+    pub fn with_define(mut self, name: impl Into<String>, value: Option<&str>) -> Self {
+        self.defines.insert(name.into(), value.map(str::to_string));
+        self
+    }
+
+    pub fn with_entry_point(mut self, entry_point: impl Into<String>) -> Self {
+        self.entry_point = entry_point.into();
+        self
+    }
+}
+
+impl ShaderPermutationCache {
+    pub fn new(options: ShaderCompileOptions) -> Self {
+        Self {
+            options,
+            modules: HashMap::new(),
+        }
+    }
+
+    #[inline]
+    pub fn options(&self) -> &ShaderCompileOptions {
+        &self.options
+    }
+
+    /// Drops every compiled variant, since they were built with the old options.
+    pub fn set_options(&mut self, options: ShaderCompileOptions) {
+        self.options = options;
+        self.modules.clear();
+    }
+
+    /// Returns the variant described by `permutation`, compiling it if it isn't cached yet.
+    pub fn get(
+        &mut self,
+        render_system: &RenderSystem,
+        permutation: &ShaderPermutation,
+    ) -> anyhow::Result<Rc<ShaderModule>> {
+        if let Some(module) = self.modules.get(permutation) {
+            return Ok(module.clone());
+        }
+
+        let module = Rc::new(self.compile(render_system, permutation)?);
+        self.modules.insert(permutation.clone(), module.clone());
+        Ok(module)
+    }
+
+    /// Compiles the variant again, replacing the cached module only if that succeeds.
+    pub fn reload(
+        &mut self,
+        render_system: &RenderSystem,
+        permutation: &ShaderPermutation,
+    ) -> anyhow::Result<Rc<ShaderModule>> {
+        let module = Rc::new(self.compile(render_system, permutation)?);
+        self.modules.insert(permutation.clone(), module.clone());
+        Ok(module)
+    }
+
+    /// Makes `module` the variant for `permutation` instead of compiling it.
+    pub fn insert(&mut self, permutation: ShaderPermutation, module: Rc<ShaderModule>) {
+        self.modules.insert(permutation, module);
+    }
+
+    /// Whether the variant has been compiled already.
+    pub fn contains(&self, permutation: &ShaderPermutation) -> bool {
+        self.modules.contains_key(permutation)
+    }
+
+    /// Drops every variant compiled from or including `path`, so they're recompiled on next use. Returns
+    /// the dropped permutations.
+    pub fn invalidate(&mut self, path: impl AsRef<Path>) -> Vec<ShaderPermutation> {
+        let path = path.as_ref();
+        let stale = self
+            .modules
+            .iter()
+            .filter(|(permutation, module)| {
+                permutation.path == path || module.dependencies().iter().any(|d| d == path)
+            })
+            .map(|(permutation, _)| permutation.clone())
+            .collect::<Vec<_>>();
+
+        for permutation in stale.iter() {
+            self.modules.remove(permutation);
+        }
+        stale
+    }
+
+    pub fn clear(&mut self) {
+        self.modules.clear();
+    }
+
+    /// The number of compiled variants.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    fn compile(
+        &self,
+        render_system: &RenderSystem,
+        permutation: &ShaderPermutation,
+    ) -> anyhow::Result<ShaderModule> {
+        let path = permutation.path.as_path();
+        let options = self
+            .options
+            .clone()
+            .with_defines(permutation.defines.clone())
+            .with_entry_point(permutation.entry_point.clone());
+
+        let module = match ShaderLanguage::from_path(path) {
+            ShaderLanguage::SpirV if !permutation.defines.is_empty() => {
+                return Err(anyhow!(
+                    "Can't apply defines to the SPIR-V binary '{}'",
+                    path.display()
+                ));
+            }
+            ShaderLanguage::SpirV => ShaderModule::load_spv(render_system, path)?,
+            ShaderLanguage::Glsl => ShaderModule::load_glsl_with_options(
+                render_system,
+                path,
+                shader_kind(permutation.stage)?,
+                &options,
+            )?,
+            ShaderLanguage::Hlsl => ShaderModule::load_hlsl_with_options(
+                render_system,
+                path,
+                shader_kind(permutation.stage)?,
+                &options,
+            )?,
+        };
+        module.set_debug_name(render_system, &debug_name(permutation));
+        Ok(module)
+    }
+}
+
+// e.g. "lit.frag [SHADOWS, SAMPLES=4]"
+fn debug_name(permutation: &ShaderPermutation) -> String {
+    let name = permutation.path.to_string_lossy();
+    if permutation.defines.is_empty() {
+        return name.into_owned();
+    }
+
+    let defines = permutation
+        .defines
+        .iter()
+        .map(|(name, value)| match value {
+            Some(value) => format!("{}={}", name, value),
+            None => name.clone(),
+        })
+        .collect::<Vec<_>>();
+    format!("{} [{}]", name, defines.join(", "))
+}
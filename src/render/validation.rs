@@ -0,0 +1,807 @@
+use crate::render::pipeline::{
+    GraphicsPipeline, GraphicsPipelineDescription, PipelineRenderCompatibility,
+};
+use crate::render::reflection::{EntryPoint, InterfaceVariable, ShaderReflection};
+use crate::render::render_pass::RenderPass;
+use crate::render::vertex::format_locations;
+use ash::vk;
+use log::{error, warn};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static COMMAND_VALIDATION: AtomicBool = AtomicBool::new(false);
+
+/// Enables checking recorded commands against the state they depend on (bound pipeline, attachment formats),
+/// for every command buffer begun afterwards. Off by default; meant for debug builds.
+pub fn set_command_validation(enabled: bool) {
+    COMMAND_VALIDATION.store(enabled, Ordering::Relaxed);
+}
+
+#[inline]
+pub fn command_validation_enabled() -> bool {
+    COMMAND_VALIDATION.load(Ordering::Relaxed)
+}
+
+/// The formats of a rendering scope's attachments, which `RenderingInfo` itself doesn't carry.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct AttachmentFormats {
+    pub color: Vec<vk::Format>,
+    pub depth: vk::Format,
+    pub stencil: vk::Format,
+}
+
+/// A way a `GraphicsPipelineDescription` disagrees with its shaders.
+#[derive(Clone, Debug)]
+pub struct PipelineDescriptionError {
+    /// The offending field of the description, e.g. `"vertex_layout.attributes"`.
+    pub field: &'static str,
+    pub message: String,
+}
+
+/// Every problem `validate_graphics_pipeline` found, as a single error.
+#[derive(Clone, Debug)]
+pub struct PipelineValidationErrors {
+    pub pipeline: Option<String>,
+    pub errors: Vec<PipelineDescriptionError>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum NumericType {
+    Float,
+    SignedInt,
+    UnsignedInt,
+}
+
+enum ActiveScope {
+    Rendering {
+        view_mask: u32,
+        /// `None` when the scope was begun without declaring its formats.
+        formats: Option<AttachmentFormats>,
+    },
+    RenderPass {
+        render_pass: Rc<RenderPass>,
+        subpass: u32,
+    },
+}
+
+struct BoundPipeline {
+    name: String,
+    compatibility: PipelineRenderCompatibility,
+}
+
+/// Tracks what a command buffer has bound while it's being recorded and reports misuse. Every problem is
+/// logged as an error and kept until the command buffer is begun again.
+#[derive(Default)]
+pub(crate) struct CommandValidator {
+    scope: Option<ActiveScope>,
+    pipeline: Option<BoundPipeline>,
+    // the bound pipeline has already been checked against the active rendering scope
+    checked: bool,
+    errors: Vec<String>,
+}
+
+impl CommandValidator {
+    pub(crate) fn begin_rendering(&mut self, view_mask: u32, formats: Option<AttachmentFormats>) {
+        self.begin_scope(ActiveScope::Rendering { view_mask, formats });
+    }
+
+    pub(crate) fn end_rendering(&mut self) {
+        if !matches!(self.scope.take(), Some(ActiveScope::Rendering { .. })) {
+            self.report("end_rendering called outside of a dynamic rendering scope".to_string());
+        }
+    }
+
+    pub(crate) fn begin_render_pass(&mut self, render_pass: &Rc<RenderPass>) {
+        self.begin_scope(ActiveScope::RenderPass {
+            render_pass: render_pass.clone(),
+            subpass: 0,
+        });
+    }
+
+    pub(crate) fn next_subpass(&mut self) {
+        let message = match self.scope.as_mut() {
+            Some(ActiveScope::RenderPass {
+                render_pass,
+                subpass,
+            }) => {
+                *subpass += 1;
+                self.checked = false;
+                (*subpass as usize >= render_pass.subpasses().len()).then(|| {
+                    format!(
+                        "next_subpass moved past the last of the render pass's {} subpasses",
+                        render_pass.subpasses().len()
+                    )
+                })
+            }
+            _ => Some("next_subpass called outside of a render pass".to_string()),
+        };
+
+        if let Some(message) = message {
+            self.report(message);
+        }
+    }
+
+    pub(crate) fn end_render_pass(&mut self) {
+        if !matches!(self.scope.take(), Some(ActiveScope::RenderPass { .. })) {
+            self.report("end_render_pass called outside of a render pass".to_string());
+        }
+    }
+
+    pub(crate) fn bind_pipeline(&mut self, pipeline: &GraphicsPipeline) {
+        self.pipeline = Some(BoundPipeline {
+            name: pipeline.debug_name().map_or_else(
+                || format!("{:?}", pipeline.handle()),
+                |name| format!("'{}'", name),
+            ),
+            compatibility: pipeline.rendering_compatibility().clone(),
+        });
+        self.checked = false;
+    }
+
+    pub(crate) fn draw(&mut self) {
+        let Some(pipeline) = self.pipeline.as_ref() else {
+            self.report("draw called without a bound graphics pipeline".to_string());
+            return;
+        };
+        if self.checked {
+            return;
+        }
+        self.checked = true;
+
+        let Some(scope) = self.scope.as_ref() else {
+            return;
+        };
+
+        let mut mismatches = Vec::new();
+        match (&pipeline.compatibility, scope) {
+            (PipelineRenderCompatibility::UseRenderPass(..), ActiveScope::Rendering { .. }) => {
+                mismatches.push(
+                    "it was created for a render pass but is used in a dynamic rendering scope"
+                        .to_string(),
+                )
+            }
+            (PipelineRenderCompatibility::RenderingInfo { .. }, ActiveScope::RenderPass { .. }) => {
+                mismatches.push(
+                    "it was created for dynamic rendering but is used in a render pass".to_string(),
+                )
+            }
+            (
+                PipelineRenderCompatibility::UseRenderPass(expected_render_pass, expected_subpass),
+                ActiveScope::RenderPass {
+                    render_pass,
+                    subpass,
+                },
+            ) => {
+                if !render_pass.is_compatible_with(expected_render_pass) {
+                    mismatches.push(format!(
+                        "its render pass {:?} isn't compatible with the active render pass {:?}",
+                        expected_render_pass.handle(),
+                        render_pass.handle()
+                    ));
+                }
+                if expected_subpass != subpass {
+                    mismatches.push(format!(
+                        "it was created for subpass {} but subpass {} is active",
+                        expected_subpass, subpass
+                    ));
+                }
+            }
+            (
+                PipelineRenderCompatibility::RenderingInfo {
+                    view_mask,
+                    color_attachment_formats,
+                    depth_attachment_format,
+                    stencil_attachment_format,
+                },
+                ActiveScope::Rendering {
+                    view_mask: active_view_mask,
+                    formats,
+                },
+            ) => {
+                if view_mask != active_view_mask {
+                    mismatches.push(format!(
+                        "its view mask is {:#x} but the rendering scope's is {:#x}",
+                        view_mask, active_view_mask
+                    ));
+                }
+
+                if let Some(formats) = formats.as_ref() {
+                    if color_attachment_formats.len() != formats.color.len() {
+                        mismatches.push(format!(
+                            "it has {} color attachments but the rendering scope has {}",
+                            color_attachment_formats.len(),
+                            formats.color.len()
+                        ));
+                    }
+                    for (index, (expected, actual)) in color_attachment_formats
+                        .iter()
+                        .zip(formats.color.iter())
+                        .enumerate()
+                    {
+                        if expected != actual {
+                            mismatches.push(format!(
+                                "color attachment {} is {:?} but the pipeline expects {:?}",
+                                index, actual, expected
+                            ));
+                        }
+                    }
+                    if *depth_attachment_format != formats.depth {
+                        mismatches.push(format!(
+                            "the depth attachment is {:?} but the pipeline expects {:?}",
+                            formats.depth, depth_attachment_format
+                        ));
+                    }
+                    if *stencil_attachment_format != formats.stencil {
+                        mismatches.push(format!(
+                            "the stencil attachment is {:?} but the pipeline expects {:?}",
+                            formats.stencil, stencil_attachment_format
+                        ));
+                    }
+                }
+            }
+        }
+
+        if !mismatches.is_empty() {
+            let message = format!(
+                "Pipeline {} is incompatible with the active rendering scope: {}",
+                pipeline.name,
+                mismatches.join(", ")
+            );
+            self.report(message);
+        }
+    }
+
+    fn begin_scope(&mut self, scope: ActiveScope) {
+        if self.scope.is_some() {
+            self.report("A rendering scope was begun inside of another one".to_string());
+        }
+
+        self.scope = Some(scope);
+        self.checked = false;
+    }
+
+    pub(crate) fn errors(&self) -> &[String] {
+        &self.errors
+    }
+
+    fn report(&mut self, message: String) {
+        error!("Command validation: {}", message);
+        self.errors.push(message);
+    }
+}
+
+impl Display for PipelineDescriptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+impl std::error::Error for PipelineDescriptionError {}
+
+impl Display for PipelineValidationErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.pipeline.as_deref() {
+            Some(name) => write!(f, "Pipeline description '{}' is invalid:", name)?,
+            None => write!(f, "Pipeline description is invalid:")?,
+        }
+        for error in self.errors.iter() {
+            write!(f, "\n  {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for PipelineValidationErrors {}
+
+/// Checks a description against the reflected interfaces of its shaders: vertex attributes against the
+/// vertex shader's inputs, fragment outputs against the color attachments and blend states, the stages
+/// against the topology, and the layout against the shaders' descriptors and push constants.
+/// `GraphicsPipeline::new` runs this before creating the pipeline.
+pub fn validate_graphics_pipeline(
+    description: &GraphicsPipelineDescription,
+) -> Result<(), PipelineValidationErrors> {
+    let mut errors = Vec::new();
+    let mut report = |field: &'static str, message: String| {
+        errors.push(PipelineDescriptionError { field, message })
+    };
+
+    let mut stages = vk::ShaderStageFlags::empty();
+    let mut entry_points = Vec::<(&EntryPoint, &ShaderReflection)>::new();
+    for (stage, shader, name) in description.shader_stages.iter() {
+        if stages.intersects(*stage) {
+            report(
+                "shader_stages",
+                format!("{:?} is given more than once", stage),
+            );
+        }
+        stages |= *stage;
+
+        let reflection = shader.reflection();
+        match reflection.entry_point(name) {
+            Some(entry_point) if entry_point.stage == *stage => {
+                entry_points.push((entry_point, reflection))
+            }
+            Some(entry_point) => report(
+                "shader_stages",
+                format!(
+                    "entry point '{}' is a {:?} shader but is used for {:?}",
+                    name, entry_point.stage, stage
+                ),
+            ),
+            None => report(
+                "shader_stages",
+                format!("the {:?} shader has no entry point named '{}'", stage, name),
+            ),
+        }
+    }
+    let stage_entry_point = |stage: vk::ShaderStageFlags| {
+        entry_points
+            .iter()
+            .find(|(entry_point, _)| entry_point.stage == stage)
+            .map(|(entry_point, _)| *entry_point)
+    };
+
+    // topology
+    let tessellation =
+        vk::ShaderStageFlags::TESSELLATION_CONTROL | vk::ShaderStageFlags::TESSELLATION_EVALUATION;
+    let patch_list = description.primitive_topology == vk::PrimitiveTopology::PATCH_LIST;
+    if patch_list && !stages.contains(tessellation) {
+        report(
+            "primitive_topology",
+            "PATCH_LIST requires tessellation control and evaluation shaders".to_string(),
+        );
+    } else if !patch_list && stages.intersects(tessellation) {
+        report(
+            "primitive_topology",
+            format!(
+                "tessellation shaders require PATCH_LIST, not {:?}",
+                description.primitive_topology
+            ),
+        );
+    }
+    if patch_list && description.tessellator_patch_control_points == 0 {
+        report(
+            "tessellator_patch_control_points",
+            "has to be at least 1 for PATCH_LIST".to_string(),
+        );
+    }
+
+    // vertex input
+    let dynamic_vertex_input = description
+        .dynamic_states
+        .contains(&vk::DynamicState::VERTEX_INPUT_EXT);
+    if let Some(vertex) = stage_entry_point(vk::ShaderStageFlags::VERTEX)
+        && !dynamic_vertex_input
+    {
+        let layout = &description.vertex_layout;
+        for attribute in layout.attributes.iter() {
+            if !layout
+                .bindings
+                .iter()
+                .any(|b| b.binding == attribute.binding)
+            {
+                report(
+                    "vertex_layout.attributes",
+                    format!(
+                        "the attribute at location {} uses binding {}, which isn't in \
+                         vertex_layout.bindings",
+                        attribute.location, attribute.binding
+                    ),
+                );
+            }
+        }
+
+        for input in vertex.inputs.iter() {
+            for location in input.location..input.location + input.locations {
+                let Some(attribute) = layout.attributes.iter().find(|attribute| {
+                    (attribute.location..attribute.location + format_locations(attribute.format))
+                        .contains(&location)
+                }) else {
+                    report(
+                        "vertex_layout.attributes",
+                        format!(
+                            "vertex shader input {} has no attribute at location {}",
+                            describe_variable(input),
+                            location
+                        ),
+                    );
+                    continue;
+                };
+
+                if let (Some(expected), Some(actual)) =
+                    (numeric_type(input.format), numeric_type(attribute.format))
+                    && expected != actual
+                {
+                    report(
+                        "vertex_layout.attributes",
+                        format!(
+                            "the attribute at location {} is {:?}, which is incompatible with \
+                             vertex shader input {} of type {:?}",
+                            attribute.location,
+                            attribute.format,
+                            describe_variable(input),
+                            input.format
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    // fragment output
+    let color_formats = match &description.rendering_compatibility {
+        PipelineRenderCompatibility::RenderingInfo {
+            color_attachment_formats,
+            ..
+        } => Some(color_attachment_formats.clone()),
+        PipelineRenderCompatibility::UseRenderPass(render_pass, subpass) => render_pass
+            .subpasses()
+            .get(*subpass as usize)
+            .map(|subpass| {
+                subpass
+                    .color_attachments
+                    .iter()
+                    .map(|reference| {
+                        render_pass
+                            .attachments()
+                            .get(reference.attachment as usize)
+                            .map_or(vk::Format::UNDEFINED, |a| a.format)
+                    })
+                    .collect::<Vec<_>>()
+            }),
+    };
+    if let Some(color_formats) = color_formats.as_ref() {
+        if description.color_blending.attachments.len() != color_formats.len() {
+            report(
+                "color_blending.attachments",
+                format!(
+                    "has {} entries but there are {} color attachments",
+                    description.color_blending.attachments.len(),
+                    color_formats.len()
+                ),
+            );
+        }
+
+        if let Some(fragment) = stage_entry_point(vk::ShaderStageFlags::FRAGMENT) {
+            let write_masks = description
+                .color_blending
+                .attachments
+                .iter()
+                .map(|attachment| attachment.color_write_mask)
+                .collect::<Vec<_>>();
+            let unwritten = validate_fragment_outputs(
+                &fragment.outputs,
+                color_formats,
+                &write_masks,
+                &mut report,
+            );
+            // valid, but whatever ends up in the attachment is undefined
+            for location in unwritten {
+                let message = format!(
+                    "color attachment {} ({:?}) isn't written by the fragment shader, its contents \
+                     will be undefined",
+                    location, color_formats[location]
+                );
+                match description.debug_name.as_deref() {
+                    Some(name) => warn!("Pipeline description '{}': {}", name, message),
+                    None => warn!("Pipeline description: {}", message),
+                }
+            }
+        }
+    }
+
+    // layout
+    let set_layouts = description.layout.descriptor_set_layouts();
+    let push_constant_ranges = description.layout.push_constant_ranges();
+    for (entry_point, reflection) in entry_points.iter() {
+        let stage = entry_point.stage;
+        for binding in reflection.descriptor_bindings.iter() {
+            // modules that don't say which descriptors an entry point uses may declare them for others
+            let used = entry_point.uses_descriptor(binding.set, binding.binding);
+            if used == Some(false) {
+                continue;
+            }
+
+            let declared = set_layouts
+                .get(binding.set as usize)
+                .and_then(|set_layout| set_layout.binding(binding.binding));
+            let problem = match declared {
+                None => Some("is missing from the layout".to_string()),
+                Some(declared) if declared.descriptor_type != binding.descriptor_type => {
+                    Some(format!("is {:?} in the layout", declared.descriptor_type))
+                }
+                Some(declared) if declared.descriptor_count < binding.count => Some(format!(
+                    "has only {} descriptors in the layout",
+                    declared.descriptor_count
+                )),
+                Some(declared) if !declared.stage_flags.contains(stage) => Some(format!(
+                    "isn't visible to {:?} in the layout, only to {:?}",
+                    stage, declared.stage_flags
+                )),
+                Some(_) => None,
+            };
+
+            if let Some(problem) = problem {
+                let message = format!(
+                    "set {} binding {} ('{}', {:?}[{}]) {} the {:?} shader {}",
+                    binding.set,
+                    binding.binding,
+                    binding.name,
+                    binding.descriptor_type,
+                    binding.count,
+                    if used.is_some() {
+                        "used by"
+                    } else {
+                        "declared in"
+                    },
+                    stage,
+                    problem
+                );
+                if used.is_some() {
+                    report("layout", message);
+                } else {
+                    match description.debug_name.as_deref() {
+                        Some(name) => warn!("Pipeline description '{}': {}", name, message),
+                        None => warn!("Pipeline description: {}", message),
+                    }
+                }
+            }
+        }
+
+        if let Some(block) = reflection.push_constants.as_ref() {
+            for member in block.members.iter() {
+                let end = member.offset + member.size;
+                let covered = push_constant_ranges.iter().any(|(stages, range)| {
+                    stages.contains(stage) && range.start <= member.offset && end <= range.end
+                });
+                if !covered {
+                    report(
+                        "layout",
+                        format!(
+                            "push constant '{}' (bytes {}..{}) used by the {:?} shader isn't \
+                             covered by a push constant range for that stage",
+                            member.name, member.offset, end, stage
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(PipelineValidationErrors {
+            pipeline: description.debug_name.clone(),
+            errors,
+        })
+    }
+}
+
+/// Checks that every location the fragment shader writes has a color attachment of a compatible type. The
+/// second outputs of dual-source blending don't take up attachments. Returns the color attachments the
+/// shader doesn't write even though their `color_write_mask` lets it.
+fn validate_fragment_outputs(
+    outputs: &[InterfaceVariable],
+    color_formats: &[vk::Format],
+    write_masks: &[vk::ColorComponentFlags],
+    report: &mut impl FnMut(&'static str, String),
+) -> Vec<usize> {
+    let mut written = vec![false; color_formats.len()];
+    for output in outputs.iter().filter(|output| output.index == 0) {
+        for location in output.location..output.location + output.locations {
+            let Some(format) = color_formats.get(location as usize) else {
+                report(
+                    "rendering_compatibility",
+                    format!(
+                        "fragment shader output {} has no color attachment at location {}",
+                        describe_variable(output),
+                        location
+                    ),
+                );
+                continue;
+            };
+            written[location as usize] = true;
+
+            if let (Some(expected), Some(actual)) =
+                (numeric_type(output.format), numeric_type(*format))
+                && expected != actual
+            {
+                report(
+                    "rendering_compatibility",
+                    format!(
+                        "color attachment {} is {:?}, which is incompatible with fragment shader \
+                         output {} of type {:?}",
+                        location,
+                        format,
+                        describe_variable(output),
+                        output.format
+                    ),
+                );
+            }
+        }
+    }
+
+    // unused attachments are left UNDEFINED or masked out
+    color_formats
+        .iter()
+        .enumerate()
+        .filter(|&(location, format)| {
+            !written[location]
+                && *format != vk::Format::UNDEFINED
+                && write_masks
+                    .get(location)
+                    .is_none_or(|mask| !mask.is_empty())
+        })
+        .map(|(location, _)| location)
+        .collect()
+}
+
+fn describe_variable(variable: &InterfaceVariable) -> String {
+    if variable.name.is_empty() {
+        format!("at location {}", variable.location)
+    } else {
+        format!("'{}' at location {}", variable.name, variable.location)
+    }
+}
+
+/// How a format's components are read or written by shaders. `None` for `UNDEFINED`; every format that
+/// isn't an integer one, normalized, scaled, sRGB or floating point, reads as float.
+fn numeric_type(format: vk::Format) -> Option<NumericType> {
+    use vk::Format as F;
+
+    Some(match format {
+        F::UNDEFINED => return None,
+        F::R8_UINT
+        | F::R8G8_UINT
+        | F::R8G8B8_UINT
+        | F::B8G8R8_UINT
+        | F::R8G8B8A8_UINT
+        | F::B8G8R8A8_UINT
+        | F::A8B8G8R8_UINT_PACK32
+        | F::A2R10G10B10_UINT_PACK32
+        | F::A2B10G10R10_UINT_PACK32
+        | F::R16_UINT
+        | F::R16G16_UINT
+        | F::R16G16B16_UINT
+        | F::R16G16B16A16_UINT
+        | F::R32_UINT
+        | F::R32G32_UINT
+        | F::R32G32B32_UINT
+        | F::R32G32B32A32_UINT
+        | F::R64_UINT
+        | F::R64G64_UINT
+        | F::R64G64B64_UINT
+        | F::R64G64B64A64_UINT
+        | F::S8_UINT => NumericType::UnsignedInt,
+        F::R8_SINT
+        | F::R8G8_SINT
+        | F::R8G8B8_SINT
+        | F::B8G8R8_SINT
+        | F::R8G8B8A8_SINT
+        | F::B8G8R8A8_SINT
+        | F::A8B8G8R8_SINT_PACK32
+        | F::A2R10G10B10_SINT_PACK32
+        | F::A2B10G10R10_SINT_PACK32
+        | F::R16_SINT
+        | F::R16G16_SINT
+        | F::R16G16B16_SINT
+        | F::R16G16B16A16_SINT
+        | F::R32_SINT
+        | F::R32G32_SINT
+        | F::R32G32B32_SINT
+        | F::R32G32B32A32_SINT
+        | F::R64_SINT
+        | F::R64G64_SINT
+        | F::R64G64B64_SINT
+        | F::R64G64B64A64_SINT => NumericType::SignedInt,
+        _ => NumericType::Float,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(location: u32, locations: u32, index: u32, format: vk::Format) -> InterfaceVariable {
+        InterfaceVariable {
+            name: format!("out{}", location),
+            location,
+            component: 0,
+            locations,
+            index,
+            format,
+        }
+    }
+
+    fn validate(outputs: &[InterfaceVariable], color_formats: &[vk::Format]) -> Vec<String> {
+        let mut errors = Vec::new();
+        validate_fragment_outputs(outputs, color_formats, &[], &mut |field, message| {
+            errors.push(format!("{}: {}", field, message))
+        });
+        errors
+    }
+
+    fn unwritten(
+        outputs: &[InterfaceVariable],
+        color_formats: &[vk::Format],
+        write_masks: &[vk::ColorComponentFlags],
+    ) -> Vec<usize> {
+        validate_fragment_outputs(outputs, color_formats, write_masks, &mut |_, message| {
+            panic!("{}", message)
+        })
+    }
+
+    const RGBA: vk::Format = vk::Format::R8G8B8A8_UNORM;
+    const VEC4: vk::Format = vk::Format::R32G32B32A32_SFLOAT;
+
+    #[test]
+    fn arrays_cover_one_attachment_per_element() {
+        let outputs = [output(0, 1, 0, VEC4), output(1, 2, 0, VEC4)];
+        assert!(validate(&outputs, &[RGBA, RGBA, RGBA]).is_empty());
+
+        let errors = validate(&outputs, &[RGBA, RGBA]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("has no color attachment at location 2"));
+    }
+
+    #[test]
+    fn dual_source_outputs_share_an_attachment() {
+        let outputs = [output(0, 1, 0, VEC4), output(0, 1, 1, VEC4)];
+        assert!(validate(&outputs, &[RGBA]).is_empty());
+    }
+
+    #[test]
+    fn unwritten_attachments_are_reported_unless_undefined_or_masked() {
+        let outputs = [output(1, 1, 0, VEC4)];
+        assert!(validate(&outputs, &[RGBA, RGBA]).is_empty());
+        assert_eq!(unwritten(&outputs, &[RGBA, RGBA], &[]), vec![0]);
+        assert_eq!(
+            unwritten(&outputs, &[RGBA, RGBA], &[vk::ColorComponentFlags::RGBA; 2]),
+            vec![0]
+        );
+
+        let masked = [
+            vk::ColorComponentFlags::empty(),
+            vk::ColorComponentFlags::RGBA,
+        ];
+        assert!(unwritten(&outputs, &[RGBA, RGBA], &masked).is_empty());
+        assert!(unwritten(&outputs, &[vk::Format::UNDEFINED, RGBA], &[]).is_empty());
+    }
+
+    #[test]
+    fn classifies_formats_by_how_shaders_access_them() {
+        assert_eq!(numeric_type(vk::Format::UNDEFINED), None);
+        assert_eq!(
+            numeric_type(vk::Format::A2B10G10R10_UINT_PACK32),
+            Some(NumericType::UnsignedInt)
+        );
+        assert_eq!(
+            numeric_type(vk::Format::R16G16_SINT),
+            Some(NumericType::SignedInt)
+        );
+        for format in [
+            vk::Format::R8G8B8A8_SRGB,
+            vk::Format::R16G16_SSCALED,
+            vk::Format::B10G11R11_UFLOAT_PACK32,
+            vk::Format::BC7_UNORM_BLOCK,
+            vk::Format::D32_SFLOAT,
+        ] {
+            assert_eq!(
+                numeric_type(format),
+                Some(NumericType::Float),
+                "{:?}",
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn attachment_types_have_to_match_every_location() {
+        let outputs = [output(0, 2, 0, vk::Format::R32G32B32A32_UINT)];
+        let errors = validate(&outputs, &[vk::Format::R32G32B32A32_UINT, RGBA]);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("rendering_compatibility: color attachment 1"));
+    }
+}
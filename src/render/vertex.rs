@@ -0,0 +1,300 @@
+use crate::render::pipeline::VertexLayout;
+use ash::vk;
+
+pub use vkism_derive::Vertex;
+
+/// A struct usable as vertex or instance data, usually through `#[derive(Vertex)]`.
+pub trait Vertex: bytemuck::Pod {
+    /// The input rate `VertexLayout::with_vertex` uses for it.
+    const INPUT_RATE: vk::VertexInputRate = vk::VertexInputRate::VERTEX;
+
+    /// The attributes of every field, the first at `first_location`.
+    fn attributes(binding: u32, first_location: u32) -> Vec<vk::VertexInputAttributeDescription>;
+}
+
+/// A field type of a `Vertex` struct.
+pub trait VertexFormat {
+    /// The format of each location the type takes up.
+    const FORMAT: vk::Format;
+    /// The format for `#[vertex(normalized)]`; `UNDEFINED` for types that can't be normalized.
+    const NORMALIZED_FORMAT: vk::Format = vk::Format::UNDEFINED;
+    /// More than 1 for matrices, which take up a location per column, and 64-bit vectors with more than
+    /// 2 components.
+    const LOCATIONS: u32 = 1;
+    /// The bytes between the starts of consecutive locations when there's more than 1.
+    const LOCATION_STRIDE: u32 = 0;
+    /// The format of the last location, e.g. `R64_SFLOAT` for the third component of `[f64; 3]`.
+    const LAST_FORMAT: vk::Format = Self::FORMAT;
+}
+
+macro_rules! vertex_formats {
+    ($ty:ty => [$f1:ident, $f2:ident, $f3:ident, $f4:ident]) => {
+        vertex_formats!(@impl $ty, $f1, UNDEFINED);
+        vertex_formats!(@impl [$ty; 1], $f1, UNDEFINED);
+        vertex_formats!(@impl [$ty; 2], $f2, UNDEFINED);
+        vertex_formats!(@impl [$ty; 3], $f3, UNDEFINED);
+        vertex_formats!(@impl [$ty; 4], $f4, UNDEFINED);
+    };
+    ($ty:ty => [$f1:ident, $f2:ident, $f3:ident, $f4:ident], [$n1:ident, $n2:ident, $n3:ident, $n4:ident]) => {
+        vertex_formats!(@impl $ty, $f1, $n1);
+        vertex_formats!(@impl [$ty; 1], $f1, $n1);
+        vertex_formats!(@impl [$ty; 2], $f2, $n2);
+        vertex_formats!(@impl [$ty; 3], $f3, $n3);
+        vertex_formats!(@impl [$ty; 4], $f4, $n4);
+    };
+    (@impl $ty:ty, $format:ident, $normalized:ident) => {
+        impl VertexFormat for $ty {
+            const FORMAT: vk::Format = vk::Format::$format;
+            const NORMALIZED_FORMAT: vk::Format = vk::Format::$normalized;
+        }
+    };
+}
+
+vertex_formats!(f32 => [R32_SFLOAT, R32G32_SFLOAT, R32G32B32_SFLOAT, R32G32B32A32_SFLOAT]);
+vertex_formats!(@impl f64, R64_SFLOAT, UNDEFINED);
+vertex_formats!(@impl [f64; 1], R64_SFLOAT, UNDEFINED);
+vertex_formats!(@impl [f64; 2], R64G64_SFLOAT, UNDEFINED);
+vertex_formats!(i32 => [R32_SINT, R32G32_SINT, R32G32B32_SINT, R32G32B32A32_SINT]);
+vertex_formats!(u32 => [R32_UINT, R32G32_UINT, R32G32B32_UINT, R32G32B32A32_UINT]);
+vertex_formats!(
+    i16 => [R16_SINT, R16G16_SINT, R16G16B16_SINT, R16G16B16A16_SINT],
+    [R16_SNORM, R16G16_SNORM, R16G16B16_SNORM, R16G16B16A16_SNORM]
+);
+vertex_formats!(
+    u16 => [R16_UINT, R16G16_UINT, R16G16B16_UINT, R16G16B16A16_UINT],
+    [R16_UNORM, R16G16_UNORM, R16G16B16_UNORM, R16G16B16A16_UNORM]
+);
+vertex_formats!(
+    i8 => [R8_SINT, R8G8_SINT, R8G8B8_SINT, R8G8B8A8_SINT],
+    [R8_SNORM, R8G8_SNORM, R8G8B8_SNORM, R8G8B8A8_SNORM]
+);
+vertex_formats!(
+    u8 => [R8_UINT, R8G8_UINT, R8G8B8_UINT, R8G8B8A8_UINT],
+    [R8_UNORM, R8G8_UNORM, R8G8B8_UNORM, R8G8B8A8_UNORM]
+);
+
+/// A location holds at most 128 bits, so the third component goes in a second location.
+impl VertexFormat for [f64; 3] {
+    const FORMAT: vk::Format = vk::Format::R64G64_SFLOAT;
+    const LOCATIONS: u32 = 2;
+    const LOCATION_STRIDE: u32 = 16;
+    const LAST_FORMAT: vk::Format = vk::Format::R64_SFLOAT;
+}
+
+impl VertexFormat for [f64; 4] {
+    const FORMAT: vk::Format = vk::Format::R64G64_SFLOAT;
+    const LOCATIONS: u32 = 2;
+    const LOCATION_STRIDE: u32 = 16;
+}
+
+/// Column-major matrices, one location per column.
+impl<const ROWS: usize, const COLUMNS: usize> VertexFormat for [[f32; ROWS]; COLUMNS]
+where
+    [f32; ROWS]: VertexFormat,
+{
+    const FORMAT: vk::Format = <[f32; ROWS] as VertexFormat>::FORMAT;
+    const LOCATIONS: u32 = COLUMNS as u32;
+    const LOCATION_STRIDE: u32 = size_of::<[f32; ROWS]>() as u32;
+}
+
+impl VertexLayout {
+    /// A layout with `V` in binding 0.
+    pub fn from_vertex<V: Vertex>() -> Self {
+        Self::default().with_vertex::<V>()
+    }
+
+    /// Adds `V` in the next free binding, at its `INPUT_RATE`. Its attributes start at the location
+    /// after the highest one in use.
+    pub fn with_vertex<V: Vertex>(self) -> Self {
+        self.with_vertex_rate::<V>(V::INPUT_RATE)
+    }
+
+    /// Adds `V` as per-instance data, like `with_vertex`.
+    pub fn with_instance<V: Vertex>(self) -> Self {
+        self.with_vertex_rate::<V>(vk::VertexInputRate::INSTANCE)
+    }
+
+    pub fn with_vertex_rate<V: Vertex>(mut self, input_rate: vk::VertexInputRate) -> Self {
+        let binding = self
+            .bindings
+            .iter()
+            .map(|b| b.binding + 1)
+            .max()
+            .unwrap_or(0);
+        let location = self
+            .attributes
+            .iter()
+            .map(|a| a.location + format_locations(a.format))
+            .max()
+            .unwrap_or(0);
+
+        self.bindings.push(vk::VertexInputBindingDescription {
+            binding,
+            stride: size_of::<V>() as u32,
+            input_rate,
+        });
+        self.attributes.extend(V::attributes(binding, location));
+        self
+    }
+}
+
+/// The number of locations an attribute of `format` takes up: 64-bit formats with 3 or 4 components
+/// take up two.
+pub fn format_locations(format: vk::Format) -> u32 {
+    match format {
+        vk::Format::R64G64B64_SFLOAT
+        | vk::Format::R64G64B64_UINT
+        | vk::Format::R64G64B64_SINT
+        | vk::Format::R64G64B64A64_SFLOAT
+        | vk::Format::R64G64B64A64_UINT
+        | vk::Format::R64G64B64A64_SINT => 2,
+        _ => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytemuck::{Pod, Zeroable};
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Pod, Zeroable, Vertex)]
+    struct MeshVertex {
+        position: [f32; 3],
+        normal: [f32; 3],
+        uv: [f32; 2],
+        #[vertex(normalized)]
+        color: [u8; 4],
+        #[vertex(format = "A2B10G10R10_UNORM_PACK32")]
+        tangent: u32,
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Pod, Zeroable, Vertex)]
+    #[vertex(instance)]
+    struct Instance {
+        transform: [[f32; 4]; 4],
+        #[vertex(skip)]
+        _padding: [f32; 2],
+        #[vertex(location = 6)]
+        layer: u32,
+        tint: [f32; 4],
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Pod, Zeroable, Vertex)]
+    struct DoubleVertex {
+        position: [f64; 3],
+        weight: f64,
+        color: [f64; 4],
+        id: u32,
+        flags: u32,
+    }
+
+    fn summary(attributes: &[vk::VertexInputAttributeDescription]) -> Vec<(u32, vk::Format, u32)> {
+        attributes
+            .iter()
+            .map(|a| (a.location, a.format, a.offset))
+            .collect()
+    }
+
+    #[test]
+    fn derives_offsets_formats_and_consecutive_locations() {
+        let attributes = MeshVertex::attributes(3, 2);
+        assert!(attributes.iter().all(|a| a.binding == 3));
+        assert_eq!(
+            summary(&attributes),
+            [
+                (2, vk::Format::R32G32B32_SFLOAT, 0),
+                (3, vk::Format::R32G32B32_SFLOAT, 12),
+                (4, vk::Format::R32G32_SFLOAT, 24),
+                (5, vk::Format::R8G8B8A8_UNORM, 32),
+                (6, vk::Format::A2B10G10R10_UNORM_PACK32, 36),
+            ]
+        );
+        assert_eq!(MeshVertex::INPUT_RATE, vk::VertexInputRate::VERTEX);
+    }
+
+    #[test]
+    fn matrices_take_a_location_per_column() {
+        let attributes = Instance::attributes(0, 0);
+        assert_eq!(
+            summary(&attributes),
+            [
+                (0, vk::Format::R32G32B32A32_SFLOAT, 0),
+                (1, vk::Format::R32G32B32A32_SFLOAT, 16),
+                (2, vk::Format::R32G32B32A32_SFLOAT, 32),
+                (3, vk::Format::R32G32B32A32_SFLOAT, 48),
+                (6, vk::Format::R32_UINT, 72),
+                (7, vk::Format::R32G32B32A32_SFLOAT, 76),
+            ]
+        );
+        assert_eq!(Instance::INPUT_RATE, vk::VertexInputRate::INSTANCE);
+    }
+
+    #[test]
+    fn wide_64_bit_vectors_take_two_locations() {
+        let attributes = DoubleVertex::attributes(0, 0);
+        assert_eq!(
+            summary(&attributes),
+            [
+                (0, vk::Format::R64G64_SFLOAT, 0),
+                (1, vk::Format::R64_SFLOAT, 16),
+                (2, vk::Format::R64_SFLOAT, 24),
+                (3, vk::Format::R64G64_SFLOAT, 32),
+                (4, vk::Format::R64G64_SFLOAT, 48),
+                (5, vk::Format::R32_UINT, 64),
+                (6, vk::Format::R32_UINT, 68),
+            ]
+        );
+    }
+
+    #[test]
+    fn layouts_continue_after_the_last_used_location() {
+        let layout = VertexLayout::from_vertex::<MeshVertex>().with_vertex::<Instance>();
+        assert_eq!(
+            layout
+                .bindings
+                .iter()
+                .map(|b| (b.binding, b.stride, b.input_rate))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    0,
+                    size_of::<MeshVertex>() as u32,
+                    vk::VertexInputRate::VERTEX
+                ),
+                (
+                    1,
+                    size_of::<Instance>() as u32,
+                    vk::VertexInputRate::INSTANCE
+                ),
+            ]
+        );
+        let instance_locations = layout
+            .attributes
+            .iter()
+            .filter(|a| a.binding == 1)
+            .map(|a| a.location)
+            .collect::<Vec<_>>();
+        assert_eq!(instance_locations, [5, 6, 7, 8, 11, 12]);
+
+        // a hand-written 64-bit attribute still counts as two locations
+        let layout = VertexLayout {
+            bindings: vec![vk::VertexInputBindingDescription {
+                binding: 0,
+                stride: 32,
+                input_rate: vk::VertexInputRate::VERTEX,
+            }],
+            attributes: vec![vk::VertexInputAttributeDescription {
+                location: 0,
+                binding: 0,
+                format: vk::Format::R64G64B64A64_SFLOAT,
+                offset: 0,
+            }],
+        }
+        .with_instance::<MeshVertex>();
+        assert_eq!(layout.attributes[1].location, 2);
+        assert_eq!(layout.attributes[1].binding, 1);
+    }
+}
@@ -0,0 +1,200 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{Data, DeriveInput, Fields, LitInt, LitStr, Member, parse_macro_input};
+
+/// Implements `render::vertex::Vertex` for a `#[repr(C)]` struct whose fields all implement
+/// `VertexFormat`, assigning consecutive locations in field order. The generated code refers to
+/// `crate::render::vertex` and `::ash`, so it's meant for use inside vkism.
+///
+/// On the struct, `#[vertex(instance)]` makes it per-instance data by default.
+///
+/// On fields:
+/// - `#[vertex(normalized)]` uses the type's normalized format, e.g. `R8G8B8A8_UNORM` for `[u8; 4]`.
+/// - `#[vertex(format = "A2B10G10R10_UNORM_PACK32")]` overrides the format, e.g. for packed types.
+/// - `#[vertex(location = 4)]` places the field at a location relative to the struct's first one; the
+///   following fields continue from there.
+/// - `#[vertex(skip)]` leaves the field out, e.g. for padding.
+#[proc_macro_derive(Vertex, attributes(vertex))]
+pub fn derive_vertex(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+#[derive(Default)]
+struct FieldOptions {
+    normalized: bool,
+    format: Option<LitStr>,
+    location: Option<LitInt>,
+    skip: bool,
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let mut repr_c = false;
+    let mut instance = false;
+    for attr in input.attrs.iter() {
+        if attr.path().is_ident("repr") {
+            attr.parse_nested_meta(|meta| {
+                repr_c |= meta.path.is_ident("C");
+                Ok(())
+            })?;
+        } else if attr.path().is_ident("vertex") {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("instance") {
+                    instance = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("expected `instance`"))
+                }
+            })?;
+        }
+    }
+    if !repr_c {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "#[derive(Vertex)] requires #[repr(C)] for predictable field offsets",
+        ));
+    }
+
+    let Data::Struct(data) = &input.data else {
+        return Err(syn::Error::new(
+            input.ident.span(),
+            "#[derive(Vertex)] only supports structs",
+        ));
+    };
+    let members: Vec<(Member, &syn::Field)> = match &data.fields {
+        Fields::Named(fields) => fields
+            .named
+            .iter()
+            .map(|field| (Member::Named(field.ident.clone().unwrap()), field))
+            .collect(),
+        Fields::Unnamed(fields) => fields
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(index, field)| (Member::from(index), field))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    let mut pushes = Vec::new();
+    for (member, field) in members.iter() {
+        let options = field_options(field)?;
+        if options.skip {
+            continue;
+        }
+
+        let ty = &field.ty;
+        let span = ty.span();
+        let (format, locations, stride, last_format) = match (&options.format, options.normalized) {
+            (Some(_), true) => {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "`format` and `normalized` can't be combined",
+                ));
+            }
+            (Some(format), false) => {
+                let format = format_ident!("{}", format.value(), span = format.span());
+                let format = quote!(::ash::vk::Format::#format);
+                (format.clone(), quote!(1u32), quote!(0u32), format)
+            }
+            (None, true) => {
+                let format = quote_spanned! {span=>
+                    {
+                        const {
+                            assert!(
+                                <#ty as crate::render::vertex::VertexFormat>::NORMALIZED_FORMAT
+                                    .as_raw() != 0,
+                                "this type has no normalized vertex format",
+                            )
+                        };
+                        <#ty as crate::render::vertex::VertexFormat>::NORMALIZED_FORMAT
+                    }
+                };
+                (
+                    format.clone(),
+                    quote_spanned!(span=> <#ty as crate::render::vertex::VertexFormat>::LOCATIONS),
+                    quote_spanned!(span=> <#ty as crate::render::vertex::VertexFormat>::LOCATION_STRIDE),
+                    format,
+                )
+            }
+            (None, false) => (
+                quote_spanned!(span=> <#ty as crate::render::vertex::VertexFormat>::FORMAT),
+                quote_spanned!(span=> <#ty as crate::render::vertex::VertexFormat>::LOCATIONS),
+                quote_spanned!(span=> <#ty as crate::render::vertex::VertexFormat>::LOCATION_STRIDE),
+                quote_spanned!(span=> <#ty as crate::render::vertex::VertexFormat>::LAST_FORMAT),
+            ),
+        };
+        let set_location = options
+            .location
+            .map(|location| quote!(location = first_location + #location;));
+
+        pushes.push(quote! {
+            #set_location
+            let locations: u32 = #locations;
+            let offset = ::core::mem::offset_of!(Self, #member) as u32;
+            for column in 0..locations {
+                attributes.push(::ash::vk::VertexInputAttributeDescription {
+                    location: location + column,
+                    binding,
+                    format: if column + 1 < locations { #format } else { #last_format },
+                    offset: offset + column * #stride,
+                });
+            }
+            location += locations;
+        });
+    }
+
+    let name = &input.ident;
+    let (impl_generics, type_generics, where_clause) = input.generics.split_for_impl();
+    let input_rate = if instance {
+        quote!(::ash::vk::VertexInputRate::INSTANCE)
+    } else {
+        quote!(::ash::vk::VertexInputRate::VERTEX)
+    };
+
+    Ok(quote! {
+        impl #impl_generics crate::render::vertex::Vertex for #name #type_generics #where_clause {
+            const INPUT_RATE: ::ash::vk::VertexInputRate = #input_rate;
+
+            #[allow(unused_assignments, unused_mut, unused_variables)]
+            fn attributes(
+                binding: u32,
+                first_location: u32,
+            ) -> ::std::vec::Vec<::ash::vk::VertexInputAttributeDescription> {
+                let mut attributes = ::std::vec::Vec::new();
+                let mut location = first_location;
+                #(#pushes)*
+                attributes
+            }
+        }
+    })
+}
+
+fn field_options(field: &syn::Field) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    for attr in field
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("vertex"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("normalized") {
+                options.normalized = true;
+            } else if meta.path.is_ident("skip") {
+                options.skip = true;
+            } else if meta.path.is_ident("format") {
+                options.format = Some(meta.value()?.parse()?);
+            } else if meta.path.is_ident("location") {
+                options.location = Some(meta.value()?.parse()?);
+            } else {
+                return Err(meta.error("expected `normalized`, `skip`, `format` or `location`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(options)
+}